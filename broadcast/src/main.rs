@@ -1,14 +1,15 @@
 use std::{
   collections::{HashMap, HashSet},
-  io::StdoutLock,
-  time::UNIX_EPOCH,
+  time::{SystemTime, UNIX_EPOCH},
 };
 use virvelvind as vv;
 use vv::{
-  requests::Initialize, res::MaelstromResponse, CooperativeNode, Deserialize, Event, Node,
-  Serialize,
+  log::Level, requests::Incoming, service, CallOptions, Context, Deserialize, ErrorCode, Event,
+  Node, NodeError, RpcResult, Serialize, Timers,
 };
 
+const GOSSIP_TIMER: &str = "gossip";
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RPCRead {
   messages: Vec<usize>,
@@ -25,8 +26,8 @@ impl Topology {
   }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(tag = "type", rename_all = "snake_case")] // make enum "internally tagged"
+#[service]
+#[derive(Debug)]
 /// https://fly.io/dist-sys/3a/ defines the broadcast service
 pub enum BroadcastServiceDefinition {
   Broadcast { message: usize },
@@ -38,6 +39,7 @@ pub enum BroadcastServiceDefinition {
   Topology(Topology),
   TopologyOk,
 
+  #[service(reply = GossipReceipt)]
   Gossip { news: Vec<GossipMessage> },
   GossipReceipt { receipt: Vec<BatchId> },
 }
@@ -59,7 +61,6 @@ pub struct GossipMessage {
 // by using ID's, and in this case, we group messages together by batch ID:s.
 #[derive(Default)]
 pub struct BroadcastServiceNode {
-  init: Initialize,
   // the current gossip batch that's being produced
   current_new_message_state: HashSet<usize>,
   // all gossip batches that has been produced (and sent), as a mapping of BatchId -> MessageBatch
@@ -84,12 +85,14 @@ impl BroadcastServiceNode {
         return true;
       }
     }
-    return false;
+    false
   }
 
-  pub fn get_unknown(&mut self, node: &String) -> Vec<GossipMessage> {
+  /// The gossip `node` hasn't acknowledged yet. Messages received since the last call are put in a
+  /// new batch, identified by `now`.
+  pub fn get_unknown(&mut self, node: &String, now: SystemTime) -> Vec<GossipMessage> {
     if !self.current_new_message_state.is_empty() {
-      let new_id = std::time::SystemTime::now()
+      let new_id = now
         .duration_since(UNIX_EPOCH)
         .expect("")
         .as_micros() as usize;
@@ -117,6 +120,14 @@ impl BroadcastServiceNode {
     res
   }
 
+  /// Record that `node` has received the gossip batches in `receipt`
+  pub fn acknowledge(&mut self, node: &str, receipt: Vec<BatchId>) {
+    // only neighbours are gossiped to, so there's nothing to acknowledge for anyone else
+    if let Some(acknowledged) = self.acknowledged_sent_batches.get_mut(node) {
+      acknowledged.extend(receipt);
+    }
+  }
+
   /// Constructs all messages that this node has seen, by iterating over all produced batches (as well as the one being currently built in `current_new_message_state`)
   pub fn all_messages(&self) -> Vec<usize> {
     let total_msg_cnt = self
@@ -129,7 +140,7 @@ impl BroadcastServiceNode {
     for msg in self
       .message_batches
       .values()
-      .flat_map(|set| set)
+      .flatten()
       .chain(self.current_new_message_state.iter())
     {
       preallocated.push(*msg);
@@ -138,138 +149,145 @@ impl BroadcastServiceNode {
   }
 }
 
-impl Node<BroadcastServiceDefinition> for BroadcastServiceNode {
-  fn init(&mut self, init: Initialize) {
-    self.init = init;
+impl BroadcastServiceDefinitionHandler for BroadcastServiceNode {
+  fn on_broadcast(
+    &mut self,
+    _: &Incoming,
+    message: usize,
+    _: &mut Context<Self>,
+  ) -> Result<BroadcastServiceDefinition, NodeError> {
+    self.current_new_message_state.insert(message);
+    Ok(BroadcastServiceDefinition::BroadcastOk)
   }
 
-  fn get_init(&self) -> &Initialize {
-    &self.init
+  fn on_read(
+    &mut self,
+    _: &Incoming,
+    _: &mut Context<Self>,
+  ) -> Result<BroadcastServiceDefinition, NodeError> {
+    let messages = self.all_messages();
+    Ok(BroadcastServiceDefinition::ReadOk(RPCRead { messages }))
   }
 
-  fn process_message(
+  fn on_topology(
     &mut self,
-    _msg: virvelvind::req::MaelstromRequest<BroadcastServiceDefinition>,
-    _local_msg_id: usize,
-  ) -> Result<virvelvind::res::MaelstromResponse<BroadcastServiceDefinition>, String> {
-    todo!()
+    _: &Incoming,
+    Topology { mut topology }: Topology,
+    ctx: &mut Context<Self>,
+  ) -> Result<BroadcastServiceDefinition, NodeError> {
+    let nbs = topology.remove(ctx.cluster().me()).ok_or_else(|| {
+      NodeError::malformed_request(format!("no topology for {}", ctx.cluster().me()))
+    })?;
+    for nb in nbs.iter().cloned() {
+      self
+        .acknowledged_sent_batches
+        .insert(nb.clone(), Default::default());
+      self.received_batches.insert(nb, Default::default());
+    }
+
+    self.neighbors = nbs;
+    Ok(BroadcastServiceDefinition::TopologyOk)
   }
-}
 
-impl CooperativeNode<BroadcastServiceDefinition> for BroadcastServiceNode {
-  fn setup_sidechannel_thread(
+  fn on_gossip(
     &mut self,
-    tx: std::sync::mpsc::Sender<vv::Event<BroadcastServiceDefinition>>,
-  ) -> Option<std::thread::JoinHandle<()>> {
-    Some(std::thread::spawn(move || loop {
-      std::thread::sleep(std::time::Duration::from_millis(12));
-      match tx.send(Event::GossipEvent) {
-        Ok(_) => {}
-        Err(err) => {
-          eprintln!("Gossip event failed: {err}");
-          std::process::exit(-1)
+    msg: &Incoming,
+    news: Vec<GossipMessage>,
+    _: &mut Context<Self>,
+  ) -> Result<BroadcastServiceDefinition, NodeError> {
+    if !self.received_batches.contains_key(&msg.src) {
+      return Err(NodeError::malformed_request(format!(
+        "gossip from {}, which isn't a neighbour",
+        msg.src
+      )));
+    }
+    let mut receipts = vec![];
+    for batch in news {
+      receipts.push(batch.id);
+      if !self.received_batches[&msg.src].contains(&batch.id) {
+        for value in batch.payload {
+          if !self.has_seen(value) {
+            self.current_new_message_state.insert(value);
+          }
+        }
+        if let Some(received) = self.received_batches.get_mut(&msg.src) {
+          received.insert(batch.id);
         }
       }
-    }))
+    }
+    Ok(BroadcastServiceDefinition::GossipReceipt { receipt: receipts })
   }
 
-  fn process_event(
+  // receipts are normally handled by the reply callback set up when gossiping
+  fn on_gossip_receipt(
     &mut self,
-    evt: Event<BroadcastServiceDefinition>,
-    local_msg_id: usize,
-    stdout: &mut StdoutLock,
-  ) {
-    match evt {
-      Event::IOEvent(msg) => {
-        match msg.body.data {
-        BroadcastServiceDefinition::Broadcast { message } => {
-          self.current_new_message_state.insert(message);
-          MaelstromResponse {
-            src: self.init.node_id.clone(),
-            dest: msg.src,
-            body: vv::res::ResponseBody {
-              in_reply_to: msg.body.msg_id,
-              msg_id: Some(local_msg_id),
-              response_type: BroadcastServiceDefinition::BroadcastOk,
-            },
-          }.take_send(stdout).expect("could not send broadcast ok ok");
-        },
-        BroadcastServiceDefinition::Read => {
-          let seen = self.all_messages();
-          MaelstromResponse {
-            src: self.init.node_id.clone(),
-            dest: msg.src,
-            body: vv::res::ResponseBody {
-              in_reply_to: msg.body.msg_id,
-              msg_id: Some(local_msg_id),
-              response_type: BroadcastServiceDefinition::ReadOk(RPCRead { messages: seen }),
-            },
-          }.take_send(stdout).expect("could not send read ok");
-        },
-        BroadcastServiceDefinition::Topology(Topology { mut topology }) => {
-          let nbs = topology.remove(&self.init.node_id).expect("Did not find topology data for this node in this request");
-          for nb in nbs.iter().cloned() {
-            self.acknowledged_sent_batches.insert(nb.clone(), Default::default());
-            self.received_batches.insert(nb, Default::default());
-          }
-
-          self.neighbors = nbs;
-
-          MaelstromResponse {
-            src: self.init.node_id.clone(),
-            dest: msg.src,
-            body: vv::res::ResponseBody {
-              in_reply_to: msg.body.msg_id,
-              msg_id: Some(local_msg_id),
-              response_type: BroadcastServiceDefinition::TopologyOk,
-            },
-          }.take_send(stdout).expect("could not send topology ok");
-        },
-        BroadcastServiceDefinition::Gossip { news } => {
-          let mut receipts = vec![];
-          for batch in news {
-            receipts.push(batch.id);
-            if !self.received_batches.get(&msg.src).unwrap().contains(&batch.id) {
-              for value in batch.payload {
-                if !self.has_seen(value) {
-                  self.current_new_message_state.insert(value);
-                }
-              }
-              self.received_batches.get_mut(&msg.src).unwrap().insert(batch.id);
-            }
-          }
+    msg: &Incoming,
+    receipt: Vec<BatchId>,
+    _: &mut Context<Self>,
+  ) -> Result<(), NodeError> {
+    self.acknowledge(&msg.src, receipt);
+    Ok(())
+  }
+}
 
-          MaelstromResponse {
-            src: self.init.node_id.clone(),
-            dest: msg.src,
-            body: vv::res::ResponseBody::uni_dir(BroadcastServiceDefinition::GossipReceipt { receipt: receipts })
-          }.take_send(stdout).expect("Failed to send receipt");
-        },
-        BroadcastServiceDefinition::GossipReceipt { receipt } => {
-          for id in receipt {
-            self.acknowledged_sent_batches.get_mut(&msg.src).unwrap().insert(id);
-          }
-        },
-        BroadcastServiceDefinition::TopologyOk // these events should not be sent or received by nodes
-        | BroadcastServiceDefinition::ReadOk(_)
-        | BroadcastServiceDefinition::BroadcastOk => panic!("should never receive these messages"),
+impl Node<BroadcastServiceDefinition> for BroadcastServiceNode {
+  fn setup_timers(&mut self, timers: &mut Timers) {
+    timers.every(GOSSIP_TIMER, std::time::Duration::from_millis(12));
+  }
 
-      }
-      }
-      Event::GossipEvent => {
-        let nodes: Vec<_> = self.neighbors.iter().cloned().collect();
+  fn handle(
+    &mut self,
+    evt: Event<BroadcastServiceDefinition>,
+    ctx: &mut Context<Self>,
+  ) -> Result<(), NodeError> {
+    match evt {
+      Event::IOEvent(msg) => self.dispatch(msg, ctx)?,
+      Event::Timer(id) if id == GOSSIP_TIMER => {
+        let nodes: Vec<_> = self.neighbors.to_vec();
+        let now = ctx.clock().system_time();
         for n in nodes {
-          let news = self.get_unknown(&n);
+          let news = self.get_unknown(&n, now);
+          ctx
+            .metrics()
+            .set_gauge("unacked_batches", &[("peer", &n)], news.len() as i64);
           if !news.is_empty() {
-            MaelstromResponse {
-              src: self.init.node_id.clone(),
-              dest: n,
-              body: vv::res::ResponseBody::uni_dir(BroadcastServiceDefinition::Gossip { news: news })
-            }.take_send(stdout).expect("failed to send gossip event");
+            let sent = ctx.call_with(
+              &n,
+              BroadcastServiceDefinition::Gossip { news },
+              // no need to retry; anything not acknowledged is sent again next gossip round
+              CallOptions::timeout(std::time::Duration::from_millis(500)),
+              |node: &mut Self,
+               reply: RpcResult<BroadcastServiceDefinition>,
+               ctx: &mut Context<Self>| {
+                match reply {
+                  Ok(reply) => {
+                    if let BroadcastServiceDefinition::GossipReceipt { receipt } = reply.body.data {
+                      node.acknowledge(&reply.src, receipt);
+                    }
+                  }
+                  Err(err) if err.code == ErrorCode::Timeout => {}
+                  Err(err) => ctx
+                    .log(Level::Warn, "gossip was not acknowledged")
+                    .field("error", err.to_string())
+                    .emit(),
+                }
+              },
+            );
+            if let Err(err) = sent {
+              ctx
+                .log(Level::Error, "failed to send gossip")
+                .field("dest", &n)
+                .field("error", err.to_string())
+                .emit();
+            }
           }
         }
+        let batches = self.message_batches.len() as i64;
+        ctx.metrics().set_gauge("batches", &[], batches);
       }
+      Event::Timer(_) => {}
     }
+    Ok(())
   }
 }
 
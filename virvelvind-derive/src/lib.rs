@@ -0,0 +1,331 @@
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DeriveInput, Fields, Ident, LitStr};
+
+/// Turns an enum into a Maelstrom service definition; each variant is a message, whose `type` is
+/// the variant's name in snake case (or its `#[serde(rename = "..")]`). Put it before any
+/// `#[derive]`s; the enum's own `#[serde]` attributes mustn't set `tag`, `content`, `untagged`
+/// or `rename_all`. For an enum `Foo` it generates
+///
+/// - the `Serialize` and `Deserialize` impls, with the messages internally tagged by `type`,
+/// - `Foo::TYPE_NAMES`, `Foo::type_name` and `Foo::reply_type`; a variant `BarOk` is the reply to `Bar`, unless `Bar`
+///   says otherwise with `#[service(reply = Baz)]`,
+/// - a trait `FooHandler` with a method `on_bar` for every variant `Bar`, which gets the fields of
+///   the message, and a `dispatch` that calls the method a message is for. Handlers of requests
+///   with a reply return the reply, which `dispatch` sends; all other handlers reply themselves,
+///   if need be. Every handler answers `not-supported` unless it's implemented.
+#[proc_macro_attribute]
+pub fn service(attr: TokenStream, item: TokenStream) -> TokenStream {
+  if !attr.is_empty() {
+    return syn::Error::new(Span::call_site(), "#[service] takes no arguments")
+      .to_compile_error()
+      .into();
+  }
+  let input = parse_macro_input!(item as DeriveInput);
+  match expand(input) {
+    Ok(tokens) => tokens.into(),
+    Err(e) => e.to_compile_error().into(),
+  }
+}
+
+struct Message {
+  ident: Ident,
+  type_name: String,
+  reply: Option<Ident>,
+  /// Names of the handler arguments the fields are passed as
+  args: Vec<Ident>,
+  fields: Fields,
+}
+
+/// Skip a serde attribute we have no use for, along with its value or its nested attributes.
+fn skip_meta(meta: syn::meta::ParseNestedMeta) -> syn::Result<()> {
+  if meta.input.peek(syn::Token![=]) {
+    meta.value()?.parse::<syn::Lit>()?;
+  } else if meta.input.peek(syn::token::Paren) {
+    meta.parse_nested_meta(skip_meta)?;
+  }
+  Ok(())
+}
+
+/// What serde's `rename_all = "snake_case"` makes of a variant name.
+fn snake_case(name: &str) -> String {
+  let mut snake = String::new();
+  for (i, c) in name.char_indices() {
+    if c.is_uppercase() {
+      if i > 0 {
+        snake.push('_');
+      }
+      snake.extend(c.to_lowercase());
+    } else {
+      snake.push(c);
+    }
+  }
+  snake
+}
+
+fn expand(mut input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+  let Data::Enum(data) = &mut input.data else {
+    return Err(syn::Error::new_spanned(
+      &input.ident,
+      "#[service] only applies to enums",
+    ));
+  };
+
+  // the messages are tagged by their type, so these are set by us
+  for attr in input
+    .attrs
+    .iter()
+    .filter(|attr| attr.path().is_ident("serde"))
+  {
+    attr.parse_nested_meta(|meta| {
+      for ours in ["tag", "content", "untagged", "rename_all"] {
+        if meta.path.is_ident(ours) {
+          return Err(meta.error(format!(
+            "#[service] sets `{ours}` itself; messages are tagged by `type`, in snake case"
+          )));
+        }
+      }
+      skip_meta(meta)
+    })?;
+  }
+
+  let mut messages = Vec::new();
+  for variant in &mut data.variants {
+    let mut reply = None;
+    let mut type_name = snake_case(&variant.ident.to_string());
+    for attr in &variant.attrs {
+      if attr.path().is_ident("service") {
+        attr.parse_nested_meta(|meta| {
+          if meta.path.is_ident("reply") {
+            reply = Some(meta.value()?.parse::<Ident>()?);
+            Ok(())
+          } else {
+            Err(meta.error("expected `reply = Variant`"))
+          }
+        })?;
+      } else if attr.path().is_ident("serde") {
+        // the rename has to be known to check replies; other serde attributes are left to serde
+        attr.parse_nested_meta(|meta| {
+          if !meta.path.is_ident("rename") {
+            return skip_meta(meta);
+          }
+          if !meta.input.peek(syn::Token![=]) {
+            return Err(
+              meta.error("a message has a single `type`; rename it with `rename = \"..\"`"),
+            );
+          }
+          type_name = meta.value()?.parse::<LitStr>()?.value();
+          Ok(())
+        })?;
+      }
+    }
+    // the helper attribute is ours; it mustn't be left for the compiler to find
+    variant
+      .attrs
+      .retain(|attr| !attr.path().is_ident("service"));
+    let args = variant
+      .fields
+      .iter()
+      .enumerate()
+      .map(|(i, field)| {
+        field
+          .ident
+          .clone()
+          .unwrap_or_else(|| format_ident!("field{}", i))
+      })
+      .collect();
+    messages.push(Message {
+      ident: variant.ident.clone(),
+      type_name,
+      reply,
+      args,
+      fields: variant.fields.clone(),
+    });
+  }
+
+  // pair requests with their replies
+  let names: Vec<Ident> = messages.iter().map(|m| m.ident.clone()).collect();
+  for message in &mut messages {
+    match &message.reply {
+      Some(reply) if !names.contains(reply) => {
+        return Err(syn::Error::new_spanned(
+          reply,
+          format!("there's no message {reply} to reply with"),
+        ))
+      }
+      Some(_) => {}
+      None => {
+        let ok = format_ident!("{}Ok", message.ident);
+        message.reply = names.contains(&ok).then_some(ok);
+      }
+    }
+  }
+  let reply_type = |reply: &Ident| {
+    messages
+      .iter()
+      .find(|m| m.ident == *reply)
+      .map(|m| m.type_name.clone())
+      .expect("replies were checked to exist")
+  };
+
+  let vis = &input.vis;
+  let name = &input.ident;
+  let handler = format_ident!("{}Handler", name);
+  let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+  let generics_params = &input.generics.params;
+  let generics_params = if generics_params.is_empty() {
+    quote!()
+  } else {
+    quote!(<#generics_params>)
+  };
+  let vv = quote!(::virvelvind);
+
+  let patterns: Vec<_> = messages
+    .iter()
+    .map(|m| {
+      let ident = &m.ident;
+      let args = &m.args;
+      match &m.fields {
+        Fields::Named(_) => quote!(#name::#ident { #(#args),* }),
+        Fields::Unnamed(_) => quote!(#name::#ident(#(#args),*)),
+        Fields::Unit => quote!(#name::#ident),
+      }
+    })
+    .collect();
+  let wildcards: Vec<_> = messages
+    .iter()
+    .map(|m| {
+      let ident = &m.ident;
+      match &m.fields {
+        Fields::Named(_) => quote!(#name::#ident { .. }),
+        Fields::Unnamed(_) => quote!(#name::#ident(..)),
+        Fields::Unit => quote!(#name::#ident),
+      }
+    })
+    .collect();
+  let type_names: Vec<_> = messages.iter().map(|m| &m.type_name).collect();
+  let reply_types: Vec<_> = messages
+    .iter()
+    .map(|m| match &m.reply {
+      Some(reply) => {
+        let reply = reply_type(reply);
+        quote!(Some(#reply))
+      }
+      None => quote!(None),
+    })
+    .collect();
+
+  let mut methods = Vec::new();
+  let mut arms = Vec::new();
+  for (message, pattern) in messages.iter().zip(&patterns) {
+    let method = format_ident!("on_{}", snake_case(&message.ident.to_string()));
+    let args = &message.args;
+    let types: Vec<_> = message.fields.iter().map(|f| &f.ty).collect();
+    let type_name = &message.type_name;
+    let unsupported = format!("{type_name} is not supported");
+    let returns = match &message.reply {
+      Some(_) => quote!(#name #ty_generics),
+      None => quote!(()),
+    };
+    // the message and context are bound under names no field can collide with, since fields are
+    // bound by their own names alongside them
+    methods.push(quote! {
+      fn #method(
+        &mut self,
+        __vv_msg: &#vv::req::Incoming,
+        #(#args: #types,)*
+        __vv_ctx: &mut #vv::Context<Self>,
+      ) -> Result<#returns, #vv::NodeError> {
+        let _ = (__vv_msg, #(#args,)* __vv_ctx);
+        Err(#vv::NodeError::not_supported(#unsupported))
+      }
+    });
+    arms.push(match &message.reply {
+      Some(reply) => {
+        let reply = reply_type(reply);
+        let mismatch = format!("{type_name} must be answered with {reply}, not {{}}");
+        quote! {
+          #pattern => {
+            let __vv_reply = self.#method(&__vv_msg, #(#args,)* __vv_ctx)?;
+            if __vv_reply.type_name() != #reply {
+              return Err(#vv::NodeError::crash(format!(#mismatch, __vv_reply.type_name())));
+            }
+            __vv_ctx.reply(&__vv_msg.src, __vv_msg.msg_id, __vv_reply)
+          }
+        }
+      }
+      None => quote!(#pattern => self.#method(&__vv_msg, #(#args,)* __vv_ctx),),
+    });
+  }
+
+  let attrs = std::mem::take(&mut input.attrs);
+  let data = &input.data;
+  let Data::Enum(data) = data else {
+    unreachable!("checked above")
+  };
+  let variants = &data.variants;
+  let generics = &input.generics;
+  let mut service_generics = input.generics.clone();
+  service_generics
+    .make_where_clause()
+    .predicates
+    .push(syn::parse_quote! {
+      Self: #vv::Serialize + #vv::DeserializeOwned + Send
+    });
+  let service_where = &service_generics.where_clause;
+
+  Ok(quote! {
+    #[derive(#vv::Serialize, #vv::Deserialize)]
+    #[serde(crate = "::virvelvind::serde", tag = "type", rename_all = "snake_case")]
+    #(#attrs)*
+    #vis enum #name #generics #where_clause {
+      #variants
+    }
+
+    impl #impl_generics #name #ty_generics #where_clause {
+      /// The `type` of every message of the service
+      pub const TYPE_NAMES: &'static [&'static str] = &[#(#type_names),*];
+
+      /// The message's `type`
+      pub fn type_name(&self) -> &'static str {
+        match self {
+          #(#wildcards => #type_names,)*
+        }
+      }
+
+      /// The `type` of the reply to this message, if it's a request that has one
+      pub fn reply_type(&self) -> Option<&'static str> {
+        match self {
+          #(#wildcards => #reply_types,)*
+        }
+      }
+    }
+
+    impl #impl_generics #vv::Service for #name #ty_generics #service_where {
+      fn type_names() -> &'static [&'static str] {
+        Self::TYPE_NAMES
+      }
+    }
+
+    /// Handlers for each message of the service. See [`virvelvind::service`].
+    #vis trait #handler #generics_params: Sized #where_clause {
+      #(#methods)*
+
+      /// Hand a message to the handler for its type, and send the reply it returns, if any.
+      fn dispatch(
+        &mut self,
+        __vv_msg: #vv::req::MaelstromRequest<#name #ty_generics>,
+        __vv_ctx: &mut #vv::Context<Self>,
+      ) -> Result<(), #vv::NodeError>
+      where
+        #name #ty_generics: #vv::Serialize,
+      {
+        let (__vv_msg, __vv_data) = __vv_msg.split();
+        match __vv_data {
+          #(#arms)*
+        }
+      }
+    }
+  })
+}
@@ -0,0 +1,36 @@
+//! The echo node, with its messages dispatched by a [`Router`] rather than a `#[service]` type.
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use virvelvind::{req::MaelstromRequest, Context, Event, Node, NodeError, Router};
+
+#[derive(Debug, Deserialize)]
+pub struct Echo {
+  echo: String,
+}
+
+/// Echoes whatever it's sent. Its messages are handled by the router, so the node only sees the
+/// events that aren't messages.
+#[derive(Default)]
+pub struct EchoNode;
+
+impl EchoNode {
+  pub fn router() -> Router<EchoNode> {
+    let mut router = Router::new();
+    router.on("echo", |_, msg: MaelstromRequest<Echo>, ctx| {
+      let body = json!({ "type": "echo_ok", "echo": msg.body.data.echo });
+      ctx.reply(&msg.src, msg.body.msg_id, body)
+    });
+    router
+  }
+}
+
+impl Node<Value> for EchoNode {
+  fn handle(&mut self, _: Event<Value>, _: &mut Context<Self>) -> Result<(), NodeError> {
+    Ok(())
+  }
+}
+
+fn main() -> Result<(), String> {
+  virvelvind::start_routed(EchoNode, EchoNode::router())
+}
@@ -0,0 +1,691 @@
+//! Checking histories of operations against a sequential [`Model`] for linearizability, the way
+//! Knossos (Maelstrom's checker) does it, using the Wing & Gong / Lowe search: operations are
+//! linearized one at a time, in an order consistent with real time, backtracking when the model
+//! can't explain what an operation returned, and caching (linearized set, model state) pairs so
+//! that no configuration is explored twice. Like with Knossos, writes that may or may not have
+//! taken effect are what makes a search expensive; each of them stays concurrent with every
+//! operation after it.
+
+use std::{collections::HashSet, fmt::Debug, hash::Hash};
+
+use serde::{Deserialize, Serialize};
+
+pub mod model;
+pub use model::{
+  CasRegister, CasRegisterOp, Counter, CounterOp, Queue, QueueOp, Register, RegisterOp, Set, SetOp,
+};
+
+/// A sequential specification of the system under test.
+pub trait Model: Clone + Eq + Hash {
+  /// An operation, with its arguments
+  type Op;
+  /// What an operation returned
+  type Ret;
+
+  /// The state after applying `op`, if `op` could have returned `ret` in this state. `ret` is
+  /// `None` when it's unknown what the operation returned.
+  fn step(&self, op: &Self::Op, ret: Option<&Self::Ret>) -> Option<Self>;
+
+  /// Whether `op` never changes the state. Such operations tell us nothing if we don't know what
+  /// they returned, and are left out of the search, which keeps it from blowing up when many
+  /// reads time out.
+  fn is_read_only(_op: &Self::Op) -> bool {
+    false
+  }
+}
+
+/// One event in a history, in the order they were observed. A process has at most one operation
+/// in flight at a time; every `Invoke` is followed by one of `Ok`, `Fail` or `Info` from the same
+/// process, or nothing if the history ended before the operation completed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Entry<Op, Ret> {
+  Invoke {
+    process: usize,
+    op: Op,
+  },
+  /// The operation took effect and returned `ret`
+  Ok {
+    process: usize,
+    ret: Ret,
+  },
+  /// The operation definitely did not take effect
+  Fail {
+    process: usize,
+  },
+  /// It's unknown if the operation took effect, f.ex. because it timed out
+  Info {
+    process: usize,
+  },
+}
+
+impl<Op, Ret> Entry<Op, Ret> {
+  pub fn process(&self) -> usize {
+    match self {
+      Entry::Invoke { process, .. }
+      | Entry::Ok { process, .. }
+      | Entry::Fail { process }
+      | Entry::Info { process } => *process,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct History<Op, Ret> {
+  entries: Vec<Entry<Op, Ret>>,
+}
+
+impl<Op, Ret> Default for History<Op, Ret> {
+  fn default() -> Self {
+    History {
+      entries: Vec::new(),
+    }
+  }
+}
+
+impl<Op, Ret> History<Op, Ret> {
+  pub fn new() -> History<Op, Ret> {
+    History::default()
+  }
+
+  pub fn from_entries(entries: Vec<Entry<Op, Ret>>) -> History<Op, Ret> {
+    History { entries }
+  }
+
+  pub fn entries(&self) -> &[Entry<Op, Ret>] {
+    &self.entries
+  }
+
+  pub fn push(&mut self, entry: Entry<Op, Ret>) {
+    self.entries.push(entry);
+  }
+
+  pub fn invoke(&mut self, process: usize, op: Op) {
+    self.push(Entry::Invoke { process, op });
+  }
+
+  pub fn ok(&mut self, process: usize, ret: Ret) {
+    self.push(Entry::Ok { process, ret });
+  }
+
+  pub fn fail(&mut self, process: usize) {
+    self.push(Entry::Fail { process });
+  }
+
+  pub fn info(&mut self, process: usize) {
+    self.push(Entry::Info { process });
+  }
+
+  pub fn len(&self) -> usize {
+    self.entries.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.entries.is_empty()
+  }
+}
+
+/// An operation in a history, with an invocation and (maybe) a completion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Operation<Op, Ret> {
+  pub process: usize,
+  pub op: Op,
+  /// What the operation returned, `None` if it's unknown
+  pub ret: Option<Ret>,
+  /// Index of the invocation in the history
+  pub invoked: usize,
+  /// Index of the completion in the history. `None` if it's unknown whether the operation
+  /// completed, in which case it may have taken effect at any point after it was invoked, or not
+  /// at all.
+  pub completed: Option<usize>,
+}
+
+/// Proof that a history isn't linearizable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation<Op, Ret> {
+  /// Index in the history of the first completion that can't be explained; the history up to
+  /// just before it is linearizable, but not if it's included.
+  pub at: usize,
+  /// The operation completing at `at`
+  pub op: Operation<Op, Ret>,
+  /// The operations concurrent with `op` in the shortest non-linearizable prefix of the history,
+  /// `op` included, in the order they were invoked. No order of these is consistent with what
+  /// they returned.
+  pub window: Vec<Operation<Op, Ret>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckError<Op, Ret> {
+  /// The history isn't well formed, f.ex. a process completed an operation it never invoked
+  Malformed {
+    at: usize,
+    reason: String,
+  },
+  NotLinearizable(Violation<Op, Ret>),
+}
+
+impl<Op: Debug, Ret: Debug> std::fmt::Display for CheckError<Op, Ret> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      CheckError::Malformed { at, reason } => write!(f, "malformed history at {at}: {reason}"),
+      CheckError::NotLinearizable(violation) => write!(
+        f,
+        "not linearizable: {:?} returning {:?} at {} can't be explained. Concurrent operations: {:?}",
+        violation.op.op, violation.op.ret, violation.at, violation.window
+      ),
+    }
+  }
+}
+
+impl<Op: Debug, Ret: Debug> std::error::Error for CheckError<Op, Ret> {}
+
+/// Pair up the invocations and completions of `history`. Failed operations never took effect, so
+/// they're left out.
+pub fn operations<Op: Clone, Ret: Clone>(
+  history: &History<Op, Ret>,
+) -> Result<Vec<Operation<Op, Ret>>, CheckError<Op, Ret>> {
+  let mut ops: Vec<Option<Operation<Op, Ret>>> = Vec::new();
+  // process -> index into `ops` of the operation it has in flight
+  let mut in_flight = std::collections::HashMap::new();
+  for (at, entry) in history.entries.iter().enumerate() {
+    let process = entry.process();
+    if let Entry::Invoke { op, .. } = entry {
+      if in_flight.insert(process, ops.len()).is_some() {
+        return Err(CheckError::Malformed {
+          at,
+          reason: format!("process {process} invoked an operation while another was in flight"),
+        });
+      }
+      ops.push(Some(Operation {
+        process,
+        op: op.clone(),
+        ret: None,
+        invoked: at,
+        completed: None,
+      }));
+      continue;
+    }
+    let Some(idx) = in_flight.remove(&process) else {
+      return Err(CheckError::Malformed {
+        at,
+        reason: format!("process {process} completed an operation it never invoked"),
+      });
+    };
+    match entry {
+      Entry::Ok { ret, .. } => {
+        let op = ops[idx]
+          .as_mut()
+          .expect("operations are only removed when they fail");
+        op.ret = Some(ret.clone());
+        op.completed = Some(at);
+      }
+      Entry::Fail { .. } => ops[idx] = None,
+      Entry::Info { .. } | Entry::Invoke { .. } => {}
+    }
+  }
+  Ok(ops.into_iter().flatten().collect())
+}
+
+/// Check whether `history` is linearizable, starting from the model state `initial`. If it isn't,
+/// the error says where it stops being linearizable.
+pub fn linearizable<M>(
+  initial: &M,
+  history: &History<M::Op, M::Ret>,
+) -> Result<(), CheckError<M::Op, M::Ret>>
+where
+  M: Model,
+  M::Op: Clone,
+  M::Ret: Clone,
+{
+  let mut ops = operations(history)?;
+  ops.retain(|op| op.completed.is_some() || !M::is_read_only(&op.op));
+  if search(initial, &ops) {
+    return Ok(());
+  }
+
+  // Cutting a history short can only make it easier to linearize, so the shortest prefix that
+  // can't be linearized is found with a binary search over where the completions are.
+  let mut completions: Vec<usize> = ops.iter().filter_map(|op| op.completed).collect();
+  completions.sort_unstable();
+  let (mut lo, mut hi) = (0, completions.len().saturating_sub(1));
+  while lo < hi {
+    let mid = lo + (hi - lo) / 2;
+    if search(initial, &prefix(&ops, completions[mid])) {
+      lo = mid + 1;
+    } else {
+      hi = mid;
+    }
+  }
+  let at = completions.get(hi).copied().unwrap_or_default();
+  let ops = prefix(&ops, at);
+  let op = ops
+    .iter()
+    .find(|op| op.completed == Some(at))
+    .cloned()
+    .expect("every completion belongs to an operation");
+  let window = ops
+    .iter()
+    .filter(|other| other.invoked <= at && other.completed.is_none_or(|c| c >= op.invoked))
+    .cloned()
+    .collect();
+  Err(CheckError::NotLinearizable(Violation { at, op, window }))
+}
+
+/// The operations of a history cut off right after index `at`. Operations completing later are
+/// not known to have completed yet.
+fn prefix<Op: Clone, Ret: Clone>(ops: &[Operation<Op, Ret>], at: usize) -> Vec<Operation<Op, Ret>> {
+  ops
+    .iter()
+    .filter(|op| op.invoked <= at)
+    .map(|op| match op.completed {
+      Some(completed) if completed > at => Operation {
+        ret: None,
+        completed: None,
+        ..op.clone()
+      },
+      _ => op.clone(),
+    })
+    .collect()
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct BitSet(Vec<u64>);
+
+impl BitSet {
+  fn new(len: usize) -> BitSet {
+    BitSet(vec![0; len.div_ceil(64)])
+  }
+
+  fn set(&mut self, idx: usize) {
+    self.0[idx / 64] |= 1 << (idx % 64);
+  }
+
+  fn clear(&mut self, idx: usize) {
+    self.0[idx / 64] &= !(1 << (idx % 64));
+  }
+}
+
+/// The calls and returns of the operations, as a doubly linked list in history order. Operation
+/// `i`'s call is node `2 * i` and its return `2 * i + 1`. Operations that may not have completed
+/// return after everything else. Linearized operations are lifted out of the list, and put back
+/// when backtracking.
+struct Calls {
+  next: Vec<usize>,
+  prev: Vec<usize>,
+}
+
+impl Calls {
+  const HEAD: usize = usize::MAX - 1;
+  const END: usize = usize::MAX;
+
+  fn new<Op, Ret>(ops: &[Operation<Op, Ret>]) -> Calls {
+    let mut order: Vec<(usize, usize)> = Vec::with_capacity(ops.len() * 2);
+    for (idx, op) in ops.iter().enumerate() {
+      order.push((op.invoked, 2 * idx));
+      order.push((op.completed.unwrap_or(usize::MAX), 2 * idx + 1));
+    }
+    order.sort_unstable();
+    let mut calls = Calls {
+      next: vec![Calls::END; ops.len() * 2 + 1],
+      prev: vec![Calls::HEAD; ops.len() * 2],
+    };
+    let mut last = Calls::HEAD;
+    for (_, node) in order {
+      *calls.next_mut(last) = node;
+      calls.prev[node] = last;
+      last = node;
+    }
+    calls
+  }
+
+  fn next(&self, node: usize) -> usize {
+    if node == Calls::HEAD {
+      self.next[self.next.len() - 1]
+    } else {
+      self.next[node]
+    }
+  }
+
+  fn next_mut(&mut self, node: usize) -> &mut usize {
+    let idx = if node == Calls::HEAD {
+      self.next.len() - 1
+    } else {
+      node
+    };
+    &mut self.next[idx]
+  }
+
+  fn unlink(&mut self, node: usize) {
+    let (prev, next) = (self.prev[node], self.next[node]);
+    *self.next_mut(prev) = next;
+    if next != Calls::END {
+      self.prev[next] = prev;
+    }
+  }
+
+  fn relink(&mut self, node: usize) {
+    let (prev, next) = (self.prev[node], self.next[node]);
+    *self.next_mut(prev) = node;
+    if next != Calls::END {
+      self.prev[next] = node;
+    }
+  }
+
+  fn lift(&mut self, op: usize) {
+    self.unlink(2 * op);
+    self.unlink(2 * op + 1);
+  }
+
+  fn unlift(&mut self, op: usize) {
+    self.relink(2 * op + 1);
+    self.relink(2 * op);
+  }
+}
+
+fn search<M: Model>(initial: &M, ops: &[Operation<M::Op, M::Ret>]) -> bool {
+  let mut calls = Calls::new(ops);
+  let mut state = initial.clone();
+  let mut linearized = BitSet::new(ops.len());
+  let mut cache: HashSet<(BitSet, M)> = HashSet::new();
+  let mut stack: Vec<(usize, M)> = Vec::new();
+  // operations that may not have completed don't have to be linearized
+  let mut remaining = ops.iter().filter(|op| op.completed.is_some()).count();
+
+  let mut node = calls.next(Calls::HEAD);
+  loop {
+    if remaining == 0 {
+      return true;
+    }
+    if node != Calls::END && node.is_multiple_of(2) {
+      let idx = node / 2;
+      let op = &ops[idx];
+      if let Some(next) = state.step(&op.op, op.ret.as_ref()) {
+        let mut next_linearized = linearized.clone();
+        next_linearized.set(idx);
+        if cache.insert((next_linearized.clone(), next.clone())) {
+          stack.push((idx, std::mem::replace(&mut state, next)));
+          linearized = next_linearized;
+          if op.completed.is_some() {
+            remaining -= 1;
+          }
+          calls.lift(idx);
+          node = calls.next(Calls::HEAD);
+          continue;
+        }
+      }
+      node = calls.next(node);
+      continue;
+    }
+    // reached the return of an operation that hasn't been linearized; undo the last choice
+    let Some((idx, prev)) = stack.pop() else {
+      return false;
+    };
+    state = prev;
+    linearized.clear(idx);
+    if ops[idx].completed.is_some() {
+      remaining += 1;
+    }
+    calls.unlift(idx);
+    node = calls.next(2 * idx);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::BTreeSet;
+
+  use super::*;
+
+  /// Where `history` stops being linearizable, and when the operations in the window were invoked
+  fn violation<M>(initial: &M, history: &History<M::Op, M::Ret>) -> (usize, Vec<usize>)
+  where
+    M: Model,
+    M::Op: Clone + Debug,
+    M::Ret: Clone + Debug,
+  {
+    match linearizable(initial, history) {
+      Err(CheckError::NotLinearizable(violation)) => {
+        assert_eq!(violation.op.completed, Some(violation.at));
+        let window = violation.window.iter().map(|op| op.invoked).collect();
+        (violation.at, window)
+      }
+      other => panic!("expected a violation, got {other:?}"),
+    }
+  }
+
+  /// The first `len` entries of `history`
+  fn prefix_of<Op: Clone, Ret: Clone>(history: &History<Op, Ret>, len: usize) -> History<Op, Ret> {
+    History::from_entries(history.entries()[..len].to_vec())
+  }
+
+  #[test]
+  fn register_reads_see_the_last_write() {
+    let mut history = History::new();
+    history.invoke(0, RegisterOp::Write(1));
+    history.invoke(1, RegisterOp::Read);
+    // concurrent with the write, so it may or may not see it
+    history.ok(1, None);
+    history.ok(0, None);
+    history.invoke(1, RegisterOp::Read);
+    history.ok(1, Some(1));
+    assert_eq!(linearizable(&Register::default(), &history), Ok(()));
+  }
+
+  #[test]
+  fn a_stale_register_read_is_caught() {
+    let mut history = History::new();
+    history.invoke(0, RegisterOp::Write(1)); // 0
+    history.ok(0, None);
+    history.invoke(0, RegisterOp::Write(2)); // 2
+    history.ok(0, None);
+    history.invoke(1, RegisterOp::Read); // 4
+    history.ok(1, Some(1));
+    assert_eq!(violation(&Register::default(), &history), (5, vec![4]));
+  }
+
+  #[test]
+  fn a_read_can_not_go_back_to_before_a_write_another_read_saw() {
+    let mut history = History::new();
+    history.invoke(0, RegisterOp::Write(1)); // 0
+    history.invoke(1, RegisterOp::Read); // 1
+    history.ok(1, Some(1));
+    history.invoke(2, RegisterOp::Read); // 3
+    history.ok(2, None);
+    history.ok(0, None);
+    history.invoke(1, RegisterOp::Read); // 6
+    history.ok(1, Some(1));
+    // the first read completed before the second started, so it's left out of the window
+    assert_eq!(violation(&Register::default(), &history), (4, vec![0, 3]));
+  }
+
+  #[test]
+  fn writes_that_may_not_have_happened_take_effect_at_any_point_or_never() {
+    let mut history = History::new();
+    history.invoke(0, RegisterOp::Write(1));
+    history.info(0);
+    history.invoke(1, RegisterOp::Read);
+    history.ok(1, None);
+    history.invoke(1, RegisterOp::Read);
+    history.ok(1, Some(1));
+    assert_eq!(linearizable(&Register::default(), &history), Ok(()));
+
+    let mut never = History::new();
+    never.invoke(0, RegisterOp::Write(1));
+    never.info(0);
+    never.invoke(1, RegisterOp::Read);
+    never.ok(1, None);
+    assert_eq!(linearizable(&Register::default(), &never), Ok(()));
+
+    // but once it took effect, it can't be undone
+    history.invoke(1, RegisterOp::Read); // 6
+    history.ok(1, None);
+    assert_eq!(violation(&Register::default(), &history), (7, vec![0, 6]));
+  }
+
+  #[test]
+  fn reads_that_timed_out_are_ignored() {
+    let mut history = History::new();
+    history.invoke(0, RegisterOp::Write(1));
+    history.ok(0, None);
+    history.invoke(1, RegisterOp::Read);
+    history.info(1);
+    history.invoke(2, RegisterOp::Read);
+    history.fail(2);
+    assert_eq!(linearizable(&Register::default(), &history), Ok(()));
+  }
+
+  #[test]
+  fn compare_and_set_takes_effect_only_if_the_value_matches() {
+    let mut history = History::new();
+    history.invoke(0, CasRegisterOp::Write(1));
+    history.ok(0, None);
+    history.invoke(1, CasRegisterOp::Cas { from: 2, to: 3 });
+    history.fail(1);
+    history.invoke(1, CasRegisterOp::Cas { from: 1, to: 2 });
+    history.ok(1, None);
+    history.invoke(0, CasRegisterOp::Read);
+    history.ok(0, Some(2));
+    assert_eq!(linearizable(&CasRegister::default(), &history), Ok(()));
+  }
+
+  #[test]
+  fn a_lost_compare_and_set_is_caught() {
+    let mut history = History::new();
+    history.invoke(0, CasRegisterOp::Write(1)); // 0
+    history.ok(0, None);
+    history.invoke(1, CasRegisterOp::Cas { from: 1, to: 2 }); // 2
+    history.ok(1, None);
+    history.invoke(0, CasRegisterOp::Read); // 4
+    history.ok(0, Some(1));
+    assert_eq!(violation(&CasRegister::default(), &history), (5, vec![4]));
+  }
+
+  #[test]
+  fn a_compare_and_set_that_could_not_have_matched_is_caught() {
+    let mut history = History::new();
+    history.invoke(0, CasRegisterOp::Write(1)); // 0
+    history.invoke(1, CasRegisterOp::Cas { from: 1, to: 2 }); // 1
+    history.ok(1, None);
+    history.invoke(2, CasRegisterOp::Cas { from: 1, to: 3 }); // 3
+    history.ok(2, None);
+    history.ok(0, None);
+    // the first compare-and-set needs the write, and leaves nothing for the second one to match
+    assert_eq!(
+      violation(&CasRegister::default(), &history),
+      (4, vec![0, 3])
+    );
+  }
+
+  #[test]
+  fn a_compare_and_set_that_timed_out_may_have_happened() {
+    let mut history = History::new();
+    history.invoke(0, CasRegisterOp::Write(1));
+    history.ok(0, None);
+    history.invoke(1, CasRegisterOp::Cas { from: 1, to: 2 });
+    history.info(1);
+    history.invoke(0, CasRegisterOp::Read);
+    history.ok(0, Some(1));
+    history.invoke(0, CasRegisterOp::Read);
+    history.ok(0, Some(2));
+    assert_eq!(linearizable(&CasRegister::default(), &history), Ok(()));
+  }
+
+  #[test]
+  fn counter_reads_see_the_sum_of_the_adds() {
+    let mut history = History::new();
+    history.invoke(0, CounterOp::Add(1));
+    history.ok(0, None);
+    history.invoke(0, CounterOp::Add(2));
+    history.info(0);
+    history.invoke(1, CounterOp::Read);
+    history.ok(1, Some(1));
+    history.invoke(1, CounterOp::Read);
+    history.ok(1, Some(3));
+    assert_eq!(linearizable(&Counter::default(), &history), Ok(()));
+
+    history.invoke(1, CounterOp::Read); // 8
+    history.ok(1, Some(1));
+    assert_eq!(violation(&Counter::default(), &history), (9, vec![2, 8]));
+  }
+
+  #[test]
+  fn a_stale_counter_read_is_caught() {
+    let mut history = History::new();
+    history.invoke(0, CounterOp::Add(5)); // 0
+    history.ok(0, None);
+    history.invoke(1, CounterOp::Read); // 2
+    history.ok(1, Some(0));
+    assert_eq!(violation(&Counter(0), &history), (3, vec![2]));
+    // whether a read is stale depends on where the counter started
+    assert_eq!(linearizable(&Counter(-5), &history), Ok(()));
+  }
+
+  #[test]
+  fn set_reads_see_every_added_element() {
+    let set = |elements: &[u64]| Some(elements.iter().copied().collect::<BTreeSet<_>>());
+    let mut history = History::new();
+    history.invoke(0, SetOp::Add(1)); // 0
+    history.ok(0, None);
+    history.invoke(0, SetOp::Add(2)); // 2
+    history.invoke(1, SetOp::Read);
+    history.ok(1, set(&[1]));
+    history.invoke(1, SetOp::Read);
+    history.ok(1, set(&[1, 2]));
+    assert_eq!(linearizable(&Set::default(), &history), Ok(()));
+
+    // the add is still in flight, but another read already saw it
+    history.invoke(1, SetOp::Read); // 7
+    history.ok(1, set(&[1]));
+    assert_eq!(violation(&Set::default(), &history), (8, vec![2, 7]));
+  }
+
+  #[test]
+  fn queues_are_first_in_first_out() {
+    let mut history = History::new();
+    history.invoke(0, QueueOp::Enqueue(1));
+    history.invoke(1, QueueOp::Enqueue(2));
+    history.ok(0, None);
+    history.ok(1, None);
+    // the enqueues were concurrent, so either may have gone first
+    history.invoke(0, QueueOp::Dequeue);
+    history.ok(0, Some(2));
+    history.invoke(0, QueueOp::Dequeue);
+    history.ok(0, Some(1));
+    history.invoke(0, QueueOp::Dequeue);
+    history.ok(0, None);
+    assert_eq!(linearizable(&Queue::default(), &history), Ok(()));
+  }
+
+  #[test]
+  fn a_dequeue_out_of_order_is_caught() {
+    let mut history = History::new();
+    history.invoke(0, QueueOp::Enqueue(1)); // 0
+    history.ok(0, None);
+    history.invoke(0, QueueOp::Enqueue(2)); // 2
+    history.ok(0, None);
+    history.invoke(1, QueueOp::Dequeue); // 4
+    history.invoke(2, QueueOp::Dequeue); // 5
+    history.ok(2, Some(2));
+    history.ok(1, None);
+    // the other dequeue may have taken 1 first, if it happened; it hasn't returned yet
+    assert_eq!(
+      linearizable(&Queue::default(), &prefix_of(&history, 7)),
+      Ok(())
+    );
+    assert_eq!(violation(&Queue::default(), &history), (7, vec![4, 5]));
+  }
+
+  #[test]
+  fn a_dequeue_that_timed_out_may_have_taken_an_element() {
+    let mut history = History::new();
+    history.invoke(0, QueueOp::Enqueue(1));
+    history.ok(0, None);
+    history.invoke(0, QueueOp::Enqueue(2));
+    history.ok(0, None);
+    history.invoke(1, QueueOp::Dequeue);
+    history.info(1);
+    history.invoke(2, QueueOp::Dequeue);
+    history.ok(2, Some(2));
+    assert_eq!(linearizable(&Queue::default(), &history), Ok(()));
+  }
+}
@@ -0,0 +1,261 @@
+use std::{
+  collections::{BTreeSet, VecDeque},
+  hash::Hash,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::Model;
+
+// In all models, what a read returned is checked, what writes return is ignored.
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "f", content = "value", rename_all = "snake_case")]
+pub enum RegisterOp<V> {
+  Read,
+  Write(V),
+}
+
+/// A single value; `None` until it's first written. Reads return what they read, or `None` if the
+/// register wasn't written yet.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Register<V>(pub Option<V>);
+
+impl<V: Clone + Eq + Hash> Model for Register<V> {
+  type Op = RegisterOp<V>;
+  type Ret = Option<V>;
+
+  fn step(&self, op: &Self::Op, ret: Option<&Self::Ret>) -> Option<Self> {
+    match op {
+      RegisterOp::Read => match ret {
+        Some(read) if *read != self.0 => None,
+        _ => Some(self.clone()),
+      },
+      RegisterOp::Write(value) => Some(Register(Some(value.clone()))),
+    }
+  }
+
+  fn is_read_only(op: &Self::Op) -> bool {
+    matches!(op, RegisterOp::Read)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "f", content = "value", rename_all = "snake_case")]
+pub enum CasRegisterOp<V> {
+  Read,
+  Write(V),
+  /// Set the register to `to`, if it holds `from`. A successful compare-and-set must be recorded
+  /// as `ok`, one that didn't match as `fail`.
+  Cas {
+    from: V,
+    to: V,
+  },
+}
+
+/// A register that also supports compare-and-set, like Maelstrom's `lin-kv` for a single key.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct CasRegister<V>(pub Option<V>);
+
+impl<V: Clone + Eq + Hash> Model for CasRegister<V> {
+  type Op = CasRegisterOp<V>;
+  type Ret = Option<V>;
+
+  fn step(&self, op: &Self::Op, ret: Option<&Self::Ret>) -> Option<Self> {
+    match op {
+      CasRegisterOp::Read => match ret {
+        Some(read) if *read != self.0 => None,
+        _ => Some(self.clone()),
+      },
+      CasRegisterOp::Write(value) => Some(CasRegister(Some(value.clone()))),
+      CasRegisterOp::Cas { from, to } => {
+        (self.0.as_ref() == Some(from)).then(|| CasRegister(Some(to.clone())))
+      }
+    }
+  }
+
+  fn is_read_only(op: &Self::Op) -> bool {
+    matches!(op, CasRegisterOp::Read)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "f", content = "value", rename_all = "snake_case")]
+pub enum CounterOp {
+  Add(i64),
+  Read,
+}
+
+/// A counter starting at the given value. Reads return `Some(value)`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Counter(pub i64);
+
+impl Model for Counter {
+  type Op = CounterOp;
+  type Ret = Option<i64>;
+
+  fn step(&self, op: &Self::Op, ret: Option<&Self::Ret>) -> Option<Self> {
+    match op {
+      CounterOp::Add(delta) => Some(Counter(self.0 + delta)),
+      CounterOp::Read => match ret {
+        Some(Some(read)) if *read != self.0 => None,
+        _ => Some(self.clone()),
+      },
+    }
+  }
+
+  fn is_read_only(op: &Self::Op) -> bool {
+    matches!(op, CounterOp::Read)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "f", content = "value", rename_all = "snake_case")]
+pub enum SetOp<V> {
+  Add(V),
+  Read,
+}
+
+/// A grow-only set, like the messages a broadcast node has seen. Reads return `Some(elements)`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Set<V: Ord>(pub BTreeSet<V>);
+
+impl<V: Ord> Default for Set<V> {
+  fn default() -> Self {
+    Set(BTreeSet::new())
+  }
+}
+
+impl<V: Clone + Ord + Hash> Model for Set<V> {
+  type Op = SetOp<V>;
+  type Ret = Option<BTreeSet<V>>;
+
+  fn step(&self, op: &Self::Op, ret: Option<&Self::Ret>) -> Option<Self> {
+    match op {
+      SetOp::Add(value) => {
+        let mut set = self.0.clone();
+        set.insert(value.clone());
+        Some(Set(set))
+      }
+      SetOp::Read => match ret {
+        Some(Some(read)) if *read != self.0 => None,
+        _ => Some(self.clone()),
+      },
+    }
+  }
+
+  fn is_read_only(op: &Self::Op) -> bool {
+    matches!(op, SetOp::Read)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "f", content = "value", rename_all = "snake_case")]
+pub enum QueueOp<V> {
+  Enqueue(V),
+  Dequeue,
+}
+
+/// A FIFO queue. Dequeues return what they dequeued, or `None` if the queue was empty.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Queue<V>(pub VecDeque<V>);
+
+impl<V> Default for Queue<V> {
+  fn default() -> Self {
+    Queue(VecDeque::new())
+  }
+}
+
+impl<V: Clone + Eq + Hash> Model for Queue<V> {
+  type Op = QueueOp<V>;
+  type Ret = Option<V>;
+
+  fn step(&self, op: &Self::Op, ret: Option<&Self::Ret>) -> Option<Self> {
+    match op {
+      QueueOp::Enqueue(value) => {
+        let mut queue = self.0.clone();
+        queue.push_back(value.clone());
+        Some(Queue(queue))
+      }
+      QueueOp::Dequeue => {
+        let mut queue = self.0.clone();
+        let dequeued = queue.pop_front();
+        match ret {
+          Some(ret) if *ret != dequeued => None,
+          _ => Some(Queue(queue)),
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn register_reads_must_match_the_value() {
+    let register = Register(Some(1));
+    assert_eq!(
+      register.step(&RegisterOp::Read, Some(&Some(1))),
+      Some(Register(Some(1)))
+    );
+    assert_eq!(register.step(&RegisterOp::Read, Some(&None)), None);
+    // a read that didn't return is possible in any state
+    assert_eq!(
+      register.step(&RegisterOp::Read, None),
+      Some(Register(Some(1)))
+    );
+    assert_eq!(
+      register.step(&RegisterOp::Write(2), None),
+      Some(Register(Some(2)))
+    );
+    assert!(Register::<u64>::is_read_only(&RegisterOp::Read));
+  }
+
+  #[test]
+  fn compare_and_set_needs_the_value_it_expects() {
+    let register = CasRegister(Some(1));
+    let cas = |from, to| CasRegisterOp::Cas { from, to };
+    assert_eq!(register.step(&cas(1, 2), None), Some(CasRegister(Some(2))));
+    assert_eq!(register.step(&cas(2, 3), None), None);
+    assert_eq!(CasRegister::default().step(&cas(1, 2), None), None);
+    assert!(!CasRegister::<u64>::is_read_only(&cas(1, 1)));
+  }
+
+  #[test]
+  fn counter_adds_up() {
+    let counter = Counter(2);
+    assert_eq!(counter.step(&CounterOp::Add(-3), None), Some(Counter(-1)));
+    assert_eq!(
+      counter.step(&CounterOp::Read, Some(&Some(2))),
+      Some(Counter(2))
+    );
+    assert_eq!(counter.step(&CounterOp::Read, Some(&Some(3))), None);
+  }
+
+  #[test]
+  fn set_adds_are_idempotent() {
+    let set = Set([1].into_iter().collect());
+    assert_eq!(set.step(&SetOp::Add(1), None), Some(set.clone()));
+    let read = Some([1].into_iter().collect());
+    assert_eq!(set.step(&SetOp::Read, Some(&read)), Some(set.clone()));
+    assert_eq!(set.step(&SetOp::Read, Some(&Some(BTreeSet::new()))), None);
+  }
+
+  #[test]
+  fn dequeues_take_the_oldest_element() {
+    let queue = Queue(VecDeque::from([1, 2]));
+    let rest = Some(Queue(VecDeque::from([2])));
+    assert_eq!(queue.step(&QueueOp::Dequeue, Some(&Some(1))), rest);
+    assert_eq!(queue.step(&QueueOp::Dequeue, Some(&Some(2))), None);
+    // even if it's unknown what it returned
+    assert_eq!(queue.step(&QueueOp::Dequeue, None), rest);
+    assert_eq!(
+      Queue::<u64>::default().step(&QueueOp::Dequeue, Some(&None)),
+      Some(Queue::default())
+    );
+    // dequeues change the queue, so they're never left out of a search
+    assert!(!Queue::<u64>::is_read_only(&QueueOp::Dequeue));
+  }
+}
@@ -0,0 +1,88 @@
+use std::{
+  sync::{Arc, Mutex},
+  time::{Duration, Instant, SystemTime},
+};
+
+/// Where a node gets the time from. Nodes should read the time through the clock handed to them
+/// in their [`Context`](crate::Context), rather than calling `Instant::now` or `SystemTime::now`,
+/// so that tests and simulations can control it.
+pub trait Clock {
+  /// Monotonic time, what timers and request deadlines are measured in
+  fn now(&self) -> Instant;
+  /// Wall clock time. Unlike `now` it can be skewed, and can jump backwards
+  fn system_time(&self) -> SystemTime;
+}
+
+/// The real clocks of the machine.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+  fn now(&self) -> Instant {
+    Instant::now()
+  }
+
+  fn system_time(&self) -> SystemTime {
+    SystemTime::now()
+  }
+}
+
+/// A clock that only moves when told to. Clones share the same time, so one can be handed to a
+/// node while the other is used to control it.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+  state: Arc<Mutex<ManualState>>,
+}
+
+#[derive(Debug)]
+struct ManualState {
+  now: Instant,
+  /// What the wall clock read when the monotonic clock read `now`
+  wall: SystemTime,
+}
+
+impl ManualClock {
+  pub fn new(now: Instant, wall: SystemTime) -> ManualClock {
+    ManualClock {
+      state: Arc::new(Mutex::new(ManualState { now, wall })),
+    }
+  }
+
+  fn state(&self) -> std::sync::MutexGuard<'_, ManualState> {
+    // the state is always consistent, a panic while holding the lock can't break it
+    self.state.lock().unwrap_or_else(|e| e.into_inner())
+  }
+
+  /// Move both clocks forward by `by`.
+  pub fn advance(&self, by: Duration) {
+    let mut state = self.state();
+    state.now += by;
+    state.wall += by;
+  }
+
+  /// Move both clocks forward to `now`. The monotonic clock never goes backwards, so an earlier
+  /// `now` does nothing.
+  pub fn set(&self, now: Instant) {
+    let mut state = self.state();
+    if let Some(elapsed) = now.checked_duration_since(state.now) {
+      state.now = now;
+      state.wall += elapsed;
+    }
+  }
+
+  /// Set the wall clock, leaving the monotonic clock alone. Use it to skew the clock, or make it
+  /// jump, backwards as well as forwards.
+  pub fn set_wall(&self, wall: SystemTime) {
+    self.state().wall = wall;
+  }
+}
+
+impl Clock for ManualClock {
+  fn now(&self) -> Instant {
+    self.state().now
+  }
+
+  fn system_time(&self) -> SystemTime {
+    self.state().wall
+  }
+}
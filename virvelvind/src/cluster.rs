@@ -0,0 +1,127 @@
+//! The nodes a node runs alongside, as Maelstrom listed them in `init`. Every node of a cluster
+//! orders them the same way, by name with `n2` before `n10`, so helpers that pick a node by
+//! position, like [`Cluster::leader`] or [`Cluster::owner_of`], agree across nodes without them
+//! having to talk to each other.
+
+use std::hash::{Hash, Hasher};
+
+use crate::{req::Initialize, NetworkEntityId};
+
+/// This node and all others in the cluster. Handed out by the runtime through
+/// [`Context::cluster`](crate::Context::cluster).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cluster {
+  me: NetworkEntityId,
+  /// Every node, including this one, in order
+  nodes: Vec<NetworkEntityId>,
+  my_index: usize,
+}
+
+impl Cluster {
+  /// The cluster as described by `init`. `init.node_ids` doesn't have to list the node itself.
+  pub fn new(init: &Initialize) -> Cluster {
+    let mut nodes = init.node_ids.clone();
+    if !nodes.contains(&init.node_id) {
+      nodes.push(init.node_id.clone());
+    }
+    nodes.sort_by(|a, b| (a.len(), a).cmp(&(b.len(), b)));
+    nodes.dedup();
+    let my_index = nodes
+      .iter()
+      .position(|id| *id == init.node_id)
+      .expect("the node was just added");
+    Cluster {
+      me: init.node_id.clone(),
+      nodes,
+      my_index,
+    }
+  }
+
+  /// This node's id.
+  pub fn me(&self) -> &str {
+    &self.me
+  }
+
+  /// Every node, including this one.
+  pub fn nodes(&self) -> &[NetworkEntityId] {
+    &self.nodes
+  }
+
+  /// Every node except this one.
+  pub fn peers(&self) -> impl Iterator<Item = &str> {
+    self
+      .nodes
+      .iter()
+      .filter(move |id| **id != self.me)
+      .map(String::as_str)
+  }
+
+  /// Number of nodes, including this one.
+  pub fn len(&self) -> usize {
+    self.nodes.len()
+  }
+
+  /// Never true, since a cluster always has this node in it.
+  pub fn is_empty(&self) -> bool {
+    self.nodes.is_empty()
+  }
+
+  pub fn contains(&self, id: &str) -> bool {
+    self.index_of(id).is_some()
+  }
+
+  /// The position of `id` in the cluster's order.
+  pub fn index_of(&self, id: &str) -> Option<usize> {
+    self.nodes.iter().position(|node| node == id)
+  }
+
+  pub fn my_index(&self) -> usize {
+    self.my_index
+  }
+
+  /// The smallest number of nodes that make up more than half of the cluster.
+  pub fn majority(&self) -> usize {
+    self.nodes.len() / 2 + 1
+  }
+
+  /// The number of nodes that can fail while a majority is still up.
+  pub fn tolerated_failures(&self) -> usize {
+    self.nodes.len() - self.majority()
+  }
+
+  /// The first node; a fixed leader, for as long as nobody needs to take over.
+  pub fn leader(&self) -> &str {
+    &self.nodes[0]
+  }
+
+  pub fn is_leader(&self) -> bool {
+    self.my_index == 0
+  }
+
+  /// The leader of `term`, taking turns in the cluster's order, so that a node suspecting the
+  /// leader of failing can move on to the next term and know who leads it.
+  pub fn leader_of(&self, term: u64) -> &str {
+    &self.nodes[(term % self.nodes.len() as u64) as usize]
+  }
+
+  /// The node after `id` in the cluster's order, wrapping around; f.ex. for passing something
+  /// around a ring. `None` if `id` isn't in the cluster.
+  pub fn successor(&self, id: &str) -> Option<&str> {
+    let next = (self.index_of(id)? + 1) % self.nodes.len();
+    Some(&self.nodes[next])
+  }
+
+  /// The node responsible for `key`, when keys are spread over the cluster by their hash. The
+  /// hash is the same on every node running the same binary.
+  pub fn owner_of<K: Hash + ?Sized>(&self, key: &K) -> &str {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    key.hash(&mut hasher);
+    &self.nodes[(hasher.finish() % self.nodes.len() as u64) as usize]
+  }
+}
+
+impl From<&Initialize> for Cluster {
+  fn from(init: &Initialize) -> Cluster {
+    Cluster::new(init)
+  }
+}
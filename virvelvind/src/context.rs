@@ -0,0 +1,194 @@
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+  clock::Clock,
+  cluster::Cluster,
+  log::{Level, Logger, Record},
+  metrics::Metrics,
+  outbound::Outbound,
+  outbox::Outbox,
+  res::{MaelstromResponse, ResponseBody},
+  rpc::{CallOptions, ReplyHandle, Rpc, RpcResult},
+  NodeError, Timers,
+};
+
+/// What a node gets handed alongside every event it handles. Everything a node sends goes
+/// through here: replies, one-off messages to any destination and requests that expect a reply.
+/// Messages are queued in the [`Outbox`] and written by the runtime after the handler returns.
+pub struct Context<'a, N> {
+  pub(crate) node_id: &'a str,
+  pub(crate) cluster: &'a Cluster,
+  pub(crate) outbox: &'a mut Outbox,
+  pub(crate) outbound: &'a Outbound,
+  pub(crate) rpc: &'a mut Rpc<N>,
+  pub(crate) timers: &'a mut Timers,
+  pub(crate) clock: &'a dyn Clock,
+  pub(crate) log: &'a Logger,
+  pub(crate) metrics: &'a mut Metrics,
+  #[cfg(feature = "async")]
+  pub(crate) tasks: &'a crate::task::Handle,
+}
+
+impl<'a, N> Context<'a, N> {
+  pub fn node_id(&self) -> &str {
+    self.node_id
+  }
+
+  /// The nodes this one runs alongside, see [`cluster`](crate::cluster).
+  pub fn cluster(&self) -> &Cluster {
+    self.cluster
+  }
+
+  /// Allocate a new message id for a message sent by this node.
+  pub fn next_msg_id(&mut self) -> usize {
+    self.rpc.next_msg_id()
+  }
+
+  pub fn timers(&mut self) -> &mut Timers {
+    self.timers
+  }
+
+  /// The clock nodes should read the time from.
+  pub fn clock(&self) -> &dyn Clock {
+    self.clock
+  }
+
+  /// Start a log record, see [`log`](crate::log). Records logged while handling a message say
+  /// which message it was.
+  pub fn log(&self, level: Level, msg: &str) -> Record {
+    self.log.record(level, msg, self.clock.system_time())
+  }
+
+  /// The node's metrics, see [`metrics`](crate::metrics).
+  pub fn metrics(&mut self) -> &mut Metrics {
+    self.metrics
+  }
+
+  pub fn outbox(&mut self) -> &mut Outbox {
+    self.outbox
+  }
+
+  /// A handle for sending messages as this node from other threads, see
+  /// [`outbound`](crate::outbound).
+  pub fn outbound(&self) -> Outbound {
+    self.outbound.clone()
+  }
+
+  /// Run `task` on the node's executor, see [`task`](crate::task).
+  #[cfg(feature = "async")]
+  pub fn spawn<F: std::future::Future<Output = ()> + 'static>(&mut self, task: F) {
+    self.tasks.spawn(task)
+  }
+
+  /// A handle for tasks to send messages and spawn tasks with.
+  #[cfg(feature = "async")]
+  pub fn handle(&self) -> crate::task::Handle {
+    self.tasks.clone()
+  }
+
+  /// Number of requests sent by this node still waiting on a reply.
+  pub fn pending_requests(&self) -> usize {
+    self.rpc.pending()
+  }
+
+  /// Send `body` to `dest`, not expecting any reply.
+  pub fn send<B: Serialize>(&mut self, dest: &str, body: B) -> Result<(), NodeError> {
+    self.outbox.push(&MaelstromResponse {
+      src: self.node_id.to_owned(),
+      dest: dest.to_owned(),
+      body: ResponseBody::uni_dir(body),
+    })
+  }
+
+  /// Reply with `body` to the message `in_reply_to` that `dest` sent.
+  pub fn reply<B: Serialize>(
+    &mut self,
+    dest: &str,
+    in_reply_to: Option<usize>,
+    body: B,
+  ) -> Result<(), NodeError> {
+    let msg_id = self.rpc.next_msg_id();
+    self.outbox.push(&MaelstromResponse {
+      src: self.node_id.to_owned(),
+      dest: dest.to_owned(),
+      body: ResponseBody {
+        in_reply_to,
+        msg_id: Some(msg_id),
+        response_type: body,
+      },
+    })
+  }
+
+  /// Send `request` to `dest` and run `on_reply` with the reply, see [`Context::call_with`].
+  /// Waits for a reply forever.
+  pub fn call<Req, Resp, F>(
+    &mut self,
+    dest: &str,
+    request: Req,
+    on_reply: F,
+  ) -> Result<usize, NodeError>
+  where
+    Req: Serialize,
+    Resp: DeserializeOwned,
+    F: FnOnce(&mut N, RpcResult<Resp>, &mut Context<N>) + 'static,
+  {
+    self.call_with(dest, request, CallOptions::default(), on_reply)
+  }
+
+  /// Send `request` to `dest` and run `on_reply` when a message with a matching `in_reply_to`
+  /// arrives, or with a `timeout` error if `options` has a deadline and no reply arrived by then.
+  /// An `error` reply, or one that can't be deserialized as `Resp`, is handed to `on_reply` as
+  /// an `Err`. Returns the `msg_id` the request was (first) sent with.
+  pub fn call_with<Req, Resp, F>(
+    &mut self,
+    dest: &str,
+    request: Req,
+    options: CallOptions,
+    on_reply: F,
+  ) -> Result<usize, NodeError>
+  where
+    Req: Serialize,
+    Resp: DeserializeOwned,
+    F: FnOnce(&mut N, RpcResult<Resp>, &mut Context<N>) + 'static,
+  {
+    self.rpc.call_with(
+      self.outbox,
+      self.node_id.to_owned(),
+      dest.to_owned(),
+      request,
+      options,
+      on_reply,
+    )
+  }
+
+  /// Send `request` to `dest` and get a [`ReplyHandle`] that will receive the reply.
+  pub fn call_handle<Req, Resp>(
+    &mut self,
+    dest: &str,
+    request: Req,
+  ) -> Result<ReplyHandle<Resp>, NodeError>
+  where
+    Req: Serialize,
+    Resp: DeserializeOwned + Send + 'static,
+  {
+    self.call_handle_with(dest, request, CallOptions::default())
+  }
+
+  pub fn call_handle_with<Req, Resp>(
+    &mut self,
+    dest: &str,
+    request: Req,
+    options: CallOptions,
+  ) -> Result<ReplyHandle<Resp>, NodeError>
+  where
+    Req: Serialize,
+    Resp: DeserializeOwned + Send + 'static,
+  {
+    let (tx, handle) = ReplyHandle::channel();
+    self.call_with(dest, request, options, move |_, reply, _| {
+      // the handle may have been dropped; nobody cares about the reply then.
+      let _ = tx.send(reply);
+    })?;
+    Ok(handle)
+  }
+}
@@ -0,0 +1,184 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// The standard error codes defined by Maelstrom, see
+/// https://github.com/jepsen-io/maelstrom/blob/main/doc/protocol.md#errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+  /// Indicates that the requested operation could not be completed within a timeout.
+  Timeout,
+  /// Thrown when a client sends an RPC request to a node which does not exist.
+  NodeNotFound,
+  /// Use this error to indicate that a requested operation is not supported by the current
+  /// implementation.
+  NotSupported,
+  /// Indicates that the operation definitely cannot be performed at this time, perhaps because
+  /// the server is in a read-only state, has not yet been initialized, believes its peers to be
+  /// down, and so on.
+  TemporarilyUnavailable,
+  /// The client's request did not conform to the server's expectations, and could not possibly
+  /// have been processed.
+  MalformedRequest,
+  /// Indicates that some kind of general, indefinite error occurred.
+  Crash,
+  /// Indicates that some kind of general, definite error occurred.
+  Abort,
+  /// The client requested an operation on a key which does not exist.
+  KeyDoesNotExist,
+  /// The client requested the creation of a key which already exists.
+  KeyAlreadyExists,
+  /// The requested operation expected some conditions to hold, and those conditions were not
+  /// met.
+  PreconditionFailed,
+  /// The requested transaction has been aborted because of a conflict with another transaction.
+  TxnConflict,
+  /// Custom error codes; Maelstrom reserves 0-999 for its own use.
+  Other(u32),
+}
+
+impl ErrorCode {
+  pub fn code(&self) -> u32 {
+    match self {
+      ErrorCode::Timeout => 0,
+      ErrorCode::NodeNotFound => 1,
+      ErrorCode::NotSupported => 10,
+      ErrorCode::TemporarilyUnavailable => 11,
+      ErrorCode::MalformedRequest => 12,
+      ErrorCode::Crash => 13,
+      ErrorCode::Abort => 14,
+      ErrorCode::KeyDoesNotExist => 20,
+      ErrorCode::KeyAlreadyExists => 21,
+      ErrorCode::PreconditionFailed => 22,
+      ErrorCode::TxnConflict => 30,
+      ErrorCode::Other(code) => *code,
+    }
+  }
+
+  pub fn from_code(code: u32) -> ErrorCode {
+    match code {
+      0 => ErrorCode::Timeout,
+      1 => ErrorCode::NodeNotFound,
+      10 => ErrorCode::NotSupported,
+      11 => ErrorCode::TemporarilyUnavailable,
+      12 => ErrorCode::MalformedRequest,
+      13 => ErrorCode::Crash,
+      14 => ErrorCode::Abort,
+      20 => ErrorCode::KeyDoesNotExist,
+      21 => ErrorCode::KeyAlreadyExists,
+      22 => ErrorCode::PreconditionFailed,
+      30 => ErrorCode::TxnConflict,
+      other => ErrorCode::Other(other),
+    }
+  }
+
+  /// Definite errors mean the operation did not (and never will) take place. Indefinite errors
+  /// (timeout, crash and unknown codes) mean it may or may not have happened.
+  pub fn is_definite(&self) -> bool {
+    !matches!(
+      self,
+      ErrorCode::Timeout | ErrorCode::Crash | ErrorCode::Other(_)
+    )
+  }
+}
+
+impl Serialize for ErrorCode {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u32(self.code())
+  }
+}
+
+impl<'de> Deserialize<'de> for ErrorCode {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    u32::deserialize(deserializer).map(ErrorCode::from_code)
+  }
+}
+
+/// The `error` message body. Returned by nodes from their handlers, which the runtime turns into
+/// an `error` reply to the message being handled, and handed to RPC reply callbacks when the
+/// other side replied with an error.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename = "error")]
+pub struct NodeError {
+  pub code: ErrorCode,
+  #[serde(default)]
+  pub text: String,
+}
+
+impl NodeError {
+  pub fn new<T: Into<String>>(code: ErrorCode, text: T) -> NodeError {
+    NodeError {
+      code,
+      text: text.into(),
+    }
+  }
+
+  pub fn timeout<T: Into<String>>(text: T) -> NodeError {
+    NodeError::new(ErrorCode::Timeout, text)
+  }
+
+  pub fn node_not_found<T: Into<String>>(text: T) -> NodeError {
+    NodeError::new(ErrorCode::NodeNotFound, text)
+  }
+
+  pub fn not_supported<T: Into<String>>(text: T) -> NodeError {
+    NodeError::new(ErrorCode::NotSupported, text)
+  }
+
+  pub fn temporarily_unavailable<T: Into<String>>(text: T) -> NodeError {
+    NodeError::new(ErrorCode::TemporarilyUnavailable, text)
+  }
+
+  pub fn malformed_request<T: Into<String>>(text: T) -> NodeError {
+    NodeError::new(ErrorCode::MalformedRequest, text)
+  }
+
+  pub fn crash<T: Into<String>>(text: T) -> NodeError {
+    NodeError::new(ErrorCode::Crash, text)
+  }
+
+  pub fn abort<T: Into<String>>(text: T) -> NodeError {
+    NodeError::new(ErrorCode::Abort, text)
+  }
+
+  pub fn key_does_not_exist<T: Into<String>>(text: T) -> NodeError {
+    NodeError::new(ErrorCode::KeyDoesNotExist, text)
+  }
+
+  pub fn key_already_exists<T: Into<String>>(text: T) -> NodeError {
+    NodeError::new(ErrorCode::KeyAlreadyExists, text)
+  }
+
+  pub fn precondition_failed<T: Into<String>>(text: T) -> NodeError {
+    NodeError::new(ErrorCode::PreconditionFailed, text)
+  }
+
+  pub fn txn_conflict<T: Into<String>>(text: T) -> NodeError {
+    NodeError::new(ErrorCode::TxnConflict, text)
+  }
+}
+
+impl std::fmt::Display for NodeError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "error {:?} ({}): {}",
+      self.code,
+      self.code.code(),
+      self.text
+    )
+  }
+}
+
+impl std::error::Error for NodeError {}
+
+/// Plain string errors are treated as the node having crashed while handling the message.
+impl From<String> for NodeError {
+  fn from(text: String) -> Self {
+    NodeError::crash(text)
+  }
+}
+
+impl From<&str> for NodeError {
+  fn from(text: &str) -> Self {
+    NodeError::crash(text)
+  }
+}
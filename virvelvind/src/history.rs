@@ -0,0 +1,470 @@
+//! Histories in the shape Jepsen and Maelstrom write them to `store/*/history.edn`; one map per
+//! operation, with the operation's `:type` (`:invoke`, `:ok`, `:fail` or `:info`), `:f`, `:value`,
+//! `:time` (nanoseconds since the start of the test), `:process` and `:index`. Histories can be
+//! written and read as EDN, to be fed to Jepsen and Elle, and as JSON. A history of typed entries,
+//! like the ones workloads record, can be turned into one with [`History::from_entries`], and a
+//! history read from a file into something the checkers in [`crate::check`] take with
+//! [`History::to_check`].
+//!
+//! The runtime can also record the operations clients make against a node, as seen by the node;
+//! set `VIRVELVIND_HISTORY` to a path to have [`serve`](crate::serve) write them there as EDN, or
+//! use [`Simulation::record_history`](crate::sim::Simulation::record_history).
+
+use std::{
+  collections::HashMap,
+  fmt::Display,
+  io::{self, Write},
+  time::Duration,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{
+  check,
+  req::{self, Header},
+  NetworkEntityId, NodeError,
+};
+
+mod edn;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OpType {
+  Invoke,
+  Ok,
+  Fail,
+  Info,
+}
+
+/// Who performed an operation; a client, numbered, or something like Jepsen's `:nemesis`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Process {
+  Client(usize),
+  Named(String),
+}
+
+impl Display for Process {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Process::Client(process) => write!(f, "{process}"),
+      Process::Named(name) => write!(f, "{name}"),
+    }
+  }
+}
+
+/// One event of a history.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Op {
+  #[serde(rename = "type")]
+  pub op_type: OpType,
+  pub f: String,
+  #[serde(default)]
+  pub value: Value,
+  /// Nanoseconds since the start of the test
+  #[serde(default)]
+  pub time: u64,
+  pub process: Process,
+  #[serde(default)]
+  pub index: usize,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub error: Option<Value>,
+}
+
+impl Op {
+  fn to_edn(&self) -> String {
+    let mut out = String::from("{:type ");
+    edn::write_keyword(&mut out, edn::Keyword(self.type_name()));
+    out.push_str(", :f ");
+    edn::write_keyword(&mut out, edn::Keyword(&self.f));
+    out.push_str(", :value ");
+    edn::write_value(&mut out, &self.value);
+    out.push_str(&format!(", :time {}, :process ", self.time));
+    match &self.process {
+      Process::Client(process) => out.push_str(&process.to_string()),
+      Process::Named(name) => edn::write_keyword(&mut out, edn::Keyword(name)),
+    }
+    out.push_str(&format!(", :index {}", self.index));
+    if let Some(error) = &self.error {
+      out.push_str(", :error ");
+      edn::write_value(&mut out, error);
+    }
+    out.push('}');
+    out
+  }
+
+  fn type_name(&self) -> &'static str {
+    match self.op_type {
+      OpType::Invoke => "invoke",
+      OpType::Ok => "ok",
+      OpType::Fail => "fail",
+      OpType::Info => "info",
+    }
+  }
+}
+
+#[derive(Debug)]
+pub enum HistoryError {
+  /// The input couldn't be parsed
+  Parse(String),
+  /// The op at `index` couldn't be read as what was asked for
+  Op { index: usize, reason: String },
+}
+
+impl Display for HistoryError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      HistoryError::Parse(reason) => write!(f, "couldn't parse history: {reason}"),
+      HistoryError::Op { index, reason } => write!(f, "op {index}: {reason}"),
+    }
+  }
+}
+
+impl std::error::Error for HistoryError {}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct History {
+  ops: Vec<Op>,
+}
+
+impl History {
+  pub fn new() -> History {
+    History::default()
+  }
+
+  pub fn ops(&self) -> &[Op] {
+    &self.ops
+  }
+
+  pub fn len(&self) -> usize {
+    self.ops.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.ops.is_empty()
+  }
+
+  /// Append `op`, giving it the next index.
+  pub fn push(&mut self, mut op: Op) {
+    op.index = self.ops.len();
+    self.ops.push(op);
+  }
+
+  /// Append an operation that happened `time` into the test.
+  pub fn record(
+    &mut self,
+    time: Duration,
+    process: Process,
+    op_type: OpType,
+    f: &str,
+    value: Value,
+  ) {
+    self.push(Op {
+      op_type,
+      f: f.to_owned(),
+      value,
+      time: time.as_nanos() as u64,
+      process,
+      index: 0,
+      error: None,
+    });
+  }
+
+  /// A history of typed entries, each with the time it happened. See [`History::to_check`] for
+  /// how they're turned into an `f` and a `value`.
+  pub fn from_entries<'a, O, R, I>(entries: I) -> Result<History, serde_json::Error>
+  where
+    O: Serialize + 'a,
+    R: Serialize + 'a,
+    I: IntoIterator<Item = (Duration, &'a check::Entry<O, R>)>,
+  {
+    let mut history = History::new();
+    // the invocation each process has in flight
+    let mut invoked: HashMap<usize, (String, Value)> = HashMap::new();
+    for (time, entry) in entries {
+      let process = entry.process();
+      let (op_type, f, value) = match entry {
+        check::Entry::Invoke { op, .. } => {
+          let (f, value) = split_op(serde_json::to_value(op)?);
+          invoked.insert(process, (f.clone(), value.clone()));
+          (OpType::Invoke, f, value)
+        }
+        check::Entry::Ok { ret, .. } => {
+          let (f, _) = invoked.remove(&process).unwrap_or_default();
+          (OpType::Ok, f, strip_type(serde_json::to_value(ret)?))
+        }
+        check::Entry::Fail { .. } | check::Entry::Info { .. } => {
+          let (f, value) = invoked.remove(&process).unwrap_or_default();
+          let op_type = match entry {
+            check::Entry::Fail { .. } => OpType::Fail,
+            _ => OpType::Info,
+          };
+          (op_type, f, value)
+        }
+      };
+      history.record(time, Process::Client(process), op_type, &f, value);
+    }
+    Ok(history)
+  }
+
+  /// The history as typed entries, for the checkers. Operations by processes that aren't clients,
+  /// like Jepsen's nemesis, are left out.
+  ///
+  /// An invocation is read as `O` from `{"f": f, "value": value}`, which is the shape of the ops
+  /// of the models in [`check::model`]. Failing that, it's read as a Maelstrom message body;
+  /// `f` is its `type`, and `value` its other fields. A completion's value is read as `R`, or
+  /// failing that as a Maelstrom reply to the invocation, of type `f` followed by `_ok`.
+  pub fn to_check<O, R>(&self) -> Result<check::History<O, R>, HistoryError>
+  where
+    O: DeserializeOwned,
+    R: DeserializeOwned,
+  {
+    let mut history = check::History::new();
+    for op in &self.ops {
+      let Process::Client(process) = op.process else {
+        continue;
+      };
+      let error = |reason: serde_json::Error| HistoryError::Op {
+        index: op.index,
+        reason: reason.to_string(),
+      };
+      match op.op_type {
+        OpType::Invoke => {
+          let as_model = serde_json::json!({ "f": op.f, "value": op.value });
+          let invoked = serde_json::from_value(as_model)
+            .or_else(|_| serde_json::from_value(with_type(&op.f, &op.value)))
+            .map_err(error)?;
+          history.invoke(process, invoked);
+        }
+        OpType::Ok => {
+          let ret = serde_json::from_value(op.value.clone())
+            .or_else(|_| serde_json::from_value(with_type(&format!("{}_ok", op.f), &op.value)))
+            .map_err(error)?;
+          history.ok(process, ret);
+        }
+        OpType::Fail => history.fail(process),
+        OpType::Info => history.info(process),
+      }
+    }
+    Ok(history)
+  }
+
+  /// One EDN map per line, like Maelstrom's `history.edn`.
+  pub fn to_edn(&self) -> String {
+    let mut out = String::new();
+    for op in &self.ops {
+      out.push_str(&op.to_edn());
+      out.push('\n');
+    }
+    out
+  }
+
+  /// Read a history of EDN maps, either one after another, or in a vector.
+  pub fn from_edn(input: &str) -> Result<History, HistoryError> {
+    let mut forms = edn::read_all(input).map_err(HistoryError::Parse)?;
+    if let [Value::Array(ops)] = &mut forms[..] {
+      forms = std::mem::take(ops);
+    }
+    History::from_values(forms)
+  }
+
+  /// One JSON object per line, with the same fields as the EDN.
+  pub fn to_json(&self) -> String {
+    let mut out = String::new();
+    for op in &self.ops {
+      out.push_str(&serde_json::to_string(op).expect("ops serialize to JSON"));
+      out.push('\n');
+    }
+    out
+  }
+
+  /// Read a history of JSON objects, either one per line, or in an array.
+  pub fn from_json(input: &str) -> Result<History, HistoryError> {
+    let values = if input.trim_start().starts_with('[') {
+      serde_json::from_str(input).map_err(|e| HistoryError::Parse(e.to_string()))?
+    } else {
+      serde_json::Deserializer::from_str(input)
+        .into_iter::<Value>()
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| HistoryError::Parse(e.to_string()))?
+    };
+    History::from_values(values)
+  }
+
+  fn from_values(values: Vec<Value>) -> Result<History, HistoryError> {
+    let mut ops = values
+      .into_iter()
+      .enumerate()
+      .map(|(index, value)| {
+        serde_json::from_value::<Op>(value).map_err(|e| HistoryError::Op {
+          index,
+          reason: e.to_string(),
+        })
+      })
+      .collect::<Result<Vec<_>, _>>()?;
+    // keep the indices of the input, if it had any
+    if ops.iter().all(|op| op.index == 0) {
+      for (index, op) in ops.iter_mut().enumerate() {
+        op.index = index;
+      }
+    }
+    Ok(History { ops })
+  }
+}
+
+/// The `f` and `value` of an operation, serialized to JSON.
+fn split_op(op: Value) -> (String, Value) {
+  match op {
+    Value::Object(mut map) => {
+      if let Some(Value::String(f)) = map.remove("f") {
+        return (f, map.remove("value").unwrap_or_default());
+      }
+      match map.remove("type") {
+        Some(Value::String(f)) => (f, object_or_nil(map)),
+        _ => (String::new(), Value::Object(map)),
+      }
+    }
+    // unit variants serialize to their name
+    Value::String(f) => (f, Value::Null),
+    value => (String::new(), value),
+  }
+}
+
+/// A Maelstrom reply without its `type`.
+fn strip_type(ret: Value) -> Value {
+  match ret {
+    Value::Object(mut map) if map.contains_key("type") => {
+      map.remove("type");
+      object_or_nil(map)
+    }
+    ret => ret,
+  }
+}
+
+fn object_or_nil(map: Map<String, Value>) -> Value {
+  if map.is_empty() {
+    Value::Null
+  } else {
+    Value::Object(map)
+  }
+}
+
+/// A Maelstrom body of type `f`, with the fields of `value`.
+fn with_type(f: &str, value: &Value) -> Value {
+  let mut map = match value {
+    Value::Object(map) => map.clone(),
+    _ => Map::new(),
+  };
+  map.insert("type".into(), Value::String(f.to_owned()));
+  Value::Object(map)
+}
+
+/// Records the operations clients make against a node, as the node sees them; a request from a
+/// client is an invocation, the reply to it the completion.
+pub(crate) struct Recorder {
+  history: History,
+  /// f and value of the requests clients are waiting on a reply to, by client and msg_id
+  pending: HashMap<(NetworkEntityId, usize), (String, Value)>,
+  /// Where each op is written, as EDN, as it's recorded
+  sink: Option<Box<dyn Write + Send>>,
+}
+
+impl Recorder {
+  pub(crate) fn new(sink: Option<Box<dyn Write + Send>>) -> Recorder {
+    Recorder {
+      history: History::new(),
+      pending: HashMap::new(),
+      sink,
+    }
+  }
+
+  pub(crate) fn history(&self) -> &History {
+    &self.history
+  }
+
+  /// Maelstrom's clients are named `c1`, `c2`, ...
+  fn client(id: &str) -> Option<Process> {
+    let number = id.strip_prefix('c')?;
+    Some(
+      number
+        .parse()
+        .map(Process::Client)
+        .unwrap_or_else(|_| Process::Named(id.to_owned())),
+    )
+  }
+
+  /// `envelope` was received at `time`. Fails if the op couldn't be written to the sink, which
+  /// isn't written to anymore after that.
+  pub(crate) fn received(&mut self, time: Duration, envelope: &req::Envelope) -> io::Result<()> {
+    let (Some(process), Some(msg_id)) = (Recorder::client(&envelope.src), envelope.body.msg_id)
+    else {
+      return Ok(());
+    };
+    let Some(body) = body_of(envelope) else {
+      return Ok(());
+    };
+    let (f, value) = split_op(body);
+    let key = (envelope.src.to_string(), msg_id);
+    self.pending.insert(key, (f.clone(), value.clone()));
+    self.record(time, process, OpType::Invoke, &f, value, None)
+  }
+
+  /// `line`, with `header`, was sent at `time`. Only replies to clients are parsed, for their
+  /// body. Fails like [`Recorder::received`].
+  pub(crate) fn sent(&mut self, time: Duration, header: Header, line: &str) -> io::Result<()> {
+    let Some(in_reply_to) = header.in_reply_to else {
+      return Ok(());
+    };
+    let Some((f, value)) = self.pending.remove(&(header.dest.to_owned(), in_reply_to)) else {
+      return Ok(());
+    };
+    let process = Recorder::client(header.dest).expect("only clients' requests are pending");
+    let Ok(envelope) = req::parse_envelope(line) else {
+      return Ok(());
+    };
+    let body = body_of(&envelope).unwrap_or_default();
+    if envelope.is_error() {
+      let definite = serde_json::from_value::<NodeError>(body.clone())
+        .map(|e| e.code.is_definite())
+        .unwrap_or(false);
+      let op_type = if definite { OpType::Fail } else { OpType::Info };
+      self.record(time, process, op_type, &f, value, Some(strip_type(body)))
+    } else {
+      self.record(time, process, OpType::Ok, &f, strip_type(body), None)
+    }
+  }
+
+  fn record(
+    &mut self,
+    time: Duration,
+    process: Process,
+    op_type: OpType,
+    f: &str,
+    value: Value,
+    error: Option<Value>,
+  ) -> io::Result<()> {
+    self.history.record(time, process, op_type, f, value);
+    let op = self.history.ops.last_mut().expect("just recorded");
+    op.error = error;
+    let Some(sink) = &mut self.sink else {
+      return Ok(());
+    };
+    let written = writeln!(sink, "{}", op.to_edn()).and_then(|_| sink.flush());
+    if written.is_err() {
+      self.sink = None;
+    }
+    written
+  }
+}
+
+/// The body of a message, without the fields every message has.
+fn body_of(envelope: &req::Envelope) -> Option<Value> {
+  let mut body: Value = serde_json::from_str(envelope.raw_body()).ok()?;
+  if let Value::Object(map) = &mut body {
+    for field in ["msg_id", "in_reply_to"] {
+      map.remove(field);
+    }
+  }
+  Some(body)
+}
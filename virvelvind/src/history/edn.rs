@@ -0,0 +1,424 @@
+use std::fmt::Write;
+
+use serde_json::{Map, Number, Value};
+
+/// A value that's written as an EDN keyword rather than a string.
+pub(crate) struct Keyword<'a>(pub(crate) &'a str);
+
+/// Whether `s` can be written as a keyword, i.e. `:s` reads back as `s`.
+pub(crate) fn is_keyword(s: &str) -> bool {
+  let mut chars = s.chars();
+  let Some(first) = chars.next() else {
+    return false;
+  };
+  let allowed = |c: char| c.is_alphanumeric() || "*+!-_?<>=/.".contains(c);
+  !first.is_ascii_digit() && allowed(first) && chars.all(allowed)
+}
+
+pub(crate) fn write_keyword(out: &mut String, keyword: Keyword) {
+  if is_keyword(keyword.0) {
+    out.push(':');
+    out.push_str(keyword.0);
+  } else {
+    write_string(out, keyword.0);
+  }
+}
+
+fn write_string(out: &mut String, s: &str) {
+  out.push('"');
+  for c in s.chars() {
+    match c {
+      '"' => out.push_str("\\\""),
+      '\\' => out.push_str("\\\\"),
+      '\n' => out.push_str("\\n"),
+      '\r' => out.push_str("\\r"),
+      '\t' => out.push_str("\\t"),
+      c => out.push(c),
+    }
+  }
+  out.push('"');
+}
+
+/// Write `value` as EDN. Object keys are written as keywords. Strings are written as strings,
+/// except the `f` of a micro-op; the first element of a three element array, when it's `"r"`, `"w"`
+/// or `"append"`, which is how Elle's transactional histories spell them.
+pub(crate) fn write_value(out: &mut String, value: &Value) {
+  match value {
+    Value::Null => out.push_str("nil"),
+    Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+    Value::Number(n) => {
+      let _ = write!(out, "{n}");
+    }
+    Value::String(s) => write_string(out, s),
+    Value::Array(items) => {
+      out.push('[');
+      for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+          out.push(' ');
+        }
+        match item {
+          Value::String(f)
+            if i == 0 && items.len() == 3 && ["r", "w", "append"].contains(&&**f) =>
+          {
+            write_keyword(out, Keyword(f))
+          }
+          item => write_value(out, item),
+        }
+      }
+      out.push(']');
+    }
+    Value::Object(map) => {
+      out.push('{');
+      for (i, (key, item)) in map.iter().enumerate() {
+        if i > 0 {
+          out.push_str(", ");
+        }
+        write_keyword(out, Keyword(key));
+        out.push(' ');
+        write_value(out, item);
+      }
+      out.push('}');
+    }
+  }
+}
+
+/// Read every form in `input`. Keywords and symbols are read as strings (without the `:`), lists
+/// and sets as arrays, characters as one character strings, and map keys that aren't strings as
+/// their EDN text. Tagged literals are read as the value they tag.
+pub(crate) fn read_all(input: &str) -> Result<Vec<Value>, String> {
+  let mut reader = Reader { input, pos: 0 };
+  let mut values = Vec::new();
+  loop {
+    reader.skip_whitespace();
+    if reader.pos == input.len() {
+      return Ok(values);
+    }
+    if let Some(value) = reader.read()? {
+      values.push(value);
+    }
+  }
+}
+
+struct Reader<'a> {
+  input: &'a str,
+  pos: usize,
+}
+
+impl Reader<'_> {
+  fn error(&self, what: &str) -> String {
+    let line = self.input[..self.pos].matches('\n').count() + 1;
+    format!("{what} at line {line}")
+  }
+
+  fn peek(&self) -> Option<char> {
+    self.input[self.pos..].chars().next()
+  }
+
+  fn bump(&mut self) -> Option<char> {
+    let c = self.peek()?;
+    self.pos += c.len_utf8();
+    Some(c)
+  }
+
+  fn skip_whitespace(&mut self) {
+    while let Some(c) = self.peek() {
+      if c == ';' {
+        while !matches!(self.bump(), Some('\n') | None) {}
+      } else if c.is_whitespace() || c == ',' {
+        self.bump();
+      } else {
+        break;
+      }
+    }
+  }
+
+  fn token(&mut self) -> &str {
+    let start = self.pos;
+    while let Some(c) = self.peek() {
+      if c.is_whitespace() || ",()[]{}\";".contains(c) {
+        break;
+      }
+      self.bump();
+    }
+    &self.input[start..self.pos]
+  }
+
+  /// The next value, or `None` for a form that reads as nothing (`#_ discarded`).
+  fn read(&mut self) -> Result<Option<Value>, String> {
+    self.skip_whitespace();
+    let Some(c) = self.peek() else {
+      return Err(self.error("unexpected end of input"));
+    };
+    let value = match c {
+      '[' | '(' => {
+        self.bump();
+        Value::Array(self.read_seq(if c == '[' { ']' } else { ')' })?)
+      }
+      '{' => {
+        self.bump();
+        self.read_map()?
+      }
+      '"' => {
+        self.bump();
+        Value::String(self.read_string()?)
+      }
+      '\\' => {
+        self.bump();
+        let token = self.token();
+        let c = match token {
+          "newline" => "\n",
+          "space" => " ",
+          "tab" => "\t",
+          "return" => "\r",
+          token => token,
+        };
+        Value::String(c.to_owned())
+      }
+      '#' => {
+        self.bump();
+        match self.peek() {
+          Some('{') => {
+            self.bump();
+            Value::Array(self.read_seq('}')?)
+          }
+          Some('_') => {
+            self.bump();
+            self.read()?;
+            return Ok(None);
+          }
+          _ => {
+            // a tagged literal, like #inst "..." or #jepsen.history.Op {...}
+            self.token();
+            return self.read();
+          }
+        }
+      }
+      ')' | ']' | '}' => return Err(self.error(&format!("unexpected {c}"))),
+      _ => {
+        let start = self.pos;
+        let token = self.token().to_owned();
+        if token.is_empty() {
+          self.pos = start;
+          return Err(self.error(&format!("unexpected {c}")));
+        }
+        self.read_atom(&token)?
+      }
+    };
+    Ok(Some(value))
+  }
+
+  fn read_seq(&mut self, close: char) -> Result<Vec<Value>, String> {
+    let mut items = Vec::new();
+    loop {
+      self.skip_whitespace();
+      if self.peek() == Some(close) {
+        self.bump();
+        return Ok(items);
+      }
+      if let Some(item) = self.read()? {
+        items.push(item);
+      }
+    }
+  }
+
+  fn read_map(&mut self) -> Result<Value, String> {
+    let items = self.read_seq('}')?;
+    if items.len() % 2 != 0 {
+      return Err(self.error("map with an odd number of forms"));
+    }
+    let mut map = Map::new();
+    let mut items = items.into_iter();
+    while let (Some(key), Some(value)) = (items.next(), items.next()) {
+      let key = match key {
+        Value::String(key) => key,
+        key => {
+          let mut text = String::new();
+          write_value(&mut text, &key);
+          text
+        }
+      };
+      map.insert(key, value);
+    }
+    Ok(Value::Object(map))
+  }
+
+  fn read_string(&mut self) -> Result<String, String> {
+    let mut s = String::new();
+    loop {
+      match self.bump() {
+        None => return Err(self.error("unterminated string")),
+        Some('"') => return Ok(s),
+        Some('\\') => match self.bump() {
+          Some('n') => s.push('\n'),
+          Some('r') => s.push('\r'),
+          Some('t') => s.push('\t'),
+          Some('u') => {
+            let hex = self.input.get(self.pos..self.pos + 4).unwrap_or_default();
+            let c = u32::from_str_radix(hex, 16)
+              .ok()
+              .and_then(char::from_u32)
+              .ok_or_else(|| self.error("invalid unicode escape"))?;
+            self.pos += 4;
+            s.push(c);
+          }
+          Some(c) => s.push(c),
+          None => return Err(self.error("unterminated string")),
+        },
+        Some(c) => s.push(c),
+      }
+    }
+  }
+
+  fn read_atom(&self, token: &str) -> Result<Value, String> {
+    match token {
+      "nil" => return Ok(Value::Null),
+      "true" => return Ok(Value::Bool(true)),
+      "false" => return Ok(Value::Bool(false)),
+      _ => {}
+    }
+    if let Some(keyword) = token.strip_prefix(':') {
+      return Ok(Value::String(keyword.to_owned()));
+    }
+    let numeric = token.starts_with(|c: char| c.is_ascii_digit())
+      || (token.len() > 1
+        && token.starts_with(['-', '+'])
+        && token[1..].starts_with(|c: char| c.is_ascii_digit()));
+    if !numeric {
+      // a symbol
+      return Ok(Value::String(token.to_owned()));
+    }
+    let number = token.trim_start_matches('+').trim_end_matches(['N', 'M']);
+    if let Ok(n) = number.parse::<i64>() {
+      return Ok(Value::Number(n.into()));
+    }
+    if let Ok(n) = number.parse::<u64>() {
+      return Ok(Value::Number(n.into()));
+    }
+    number
+      .parse::<f64>()
+      .ok()
+      .and_then(Number::from_f64)
+      .map(Value::Number)
+      .ok_or_else(|| self.error(&format!("invalid number {token}")))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+
+  /// Part of a `history.edn` from a Jepsen run, with a nemesis and a discarded op thrown in
+  const HISTORY: &str = r#"
+{:type :invoke, :f :txn, :value [[:r 9 nil] [:append 9 1]], :time 12883463, :process 0, :index 0}
+#jepsen.history.Op{:index 1, :time 13059133, :type :ok, :process 0, :f :txn, :value [[:r 9 []] [:append 9 1]]}
+{:type :info, :f :start-partition, :value :majority, :time 25000000000N, :process :nemesis, :index 2}
+#_{:type :invoke, :f :read, :value nil, :time 25100000000, :process 1, :index 3}
+{:type :invoke, :f :read, :value nil, :time 25200000000, :process 1, :index 3}
+{:type :ok, :f :read, :value #{0 9223372036854775808N}, :time 25300000000, :process 1, :index 4}
+{:type :fail, :f :cas, :value [3 4], :time 25400000000, :process 2, :index 5, :error [:precondition-failed "expected 3, read 4 \u2014 caf\u00e9"]}
+"#;
+
+  fn round_trip(values: &[Value]) -> Vec<Value> {
+    let mut out = String::new();
+    for value in values {
+      write_value(&mut out, value);
+      out.push('\n');
+    }
+    read_all(&out).unwrap()
+  }
+
+  #[test]
+  fn reads_a_jepsen_history() {
+    let ops = read_all(HISTORY).unwrap();
+    assert_eq!(ops.len(), 6);
+    assert_eq!(
+      ops[0],
+      json!({
+        "type": "invoke",
+        "f": "txn",
+        "value": [["r", 9, null], ["append", 9, 1]],
+        "time": 12883463,
+        "process": 0,
+        "index": 0,
+      })
+    );
+    // the tag is dropped, the map kept
+    assert_eq!(ops[1]["type"], "ok");
+    assert_eq!(ops[1]["value"], json!([["r", 9, []], ["append", 9, 1]]));
+    assert_eq!(ops[2]["process"], "nemesis");
+    assert_eq!(ops[2]["time"], 25_000_000_000u64);
+    // the discarded op isn't read
+    assert_eq!(ops[3]["index"], 3);
+    assert_eq!(ops[3]["time"], 25_200_000_000u64);
+    assert_eq!(ops[4]["value"], json!([0, 9_223_372_036_854_775_808u64]));
+    assert_eq!(
+      ops[5]["error"],
+      json!([
+        "precondition-failed",
+        "expected 3, read 4 \u{2014} caf\u{e9}"
+      ])
+    );
+  }
+
+  #[test]
+  fn what_is_written_reads_back_the_same() {
+    let ops = read_all(HISTORY).unwrap();
+    assert_eq!(round_trip(&ops), ops);
+
+    // strings stay strings, except the `f` of a micro-op
+    let mut out = String::new();
+    write_value(&mut out, &ops[0]);
+    assert_eq!(
+      out,
+      "{:f \"txn\", :index 0, :process 0, :time 12883463, :type \"invoke\", \
+       :value [[:r 9 nil] [:append 9 1]]}"
+    );
+
+    let awkward = json!({
+      "quoted \"keys\"": ["\\", "tab\tnewline\n", "", "é"],
+      "nested": { "r": ["r", 1], "empty": {} },
+      "numbers": [-1, 1.5, u64::MAX, i64::MIN],
+      "flags": [true, false, null],
+    });
+    assert_eq!(round_trip(std::slice::from_ref(&awkward)), [awkward]);
+  }
+
+  #[test]
+  fn reads_characters_comments_and_keys_that_are_not_strings() {
+    let values =
+      read_all("[\\a \\newline \\space] ; the rest of the line is ignored\n(1 2)").unwrap();
+    assert_eq!(values, [json!(["a", "\n", " "]), json!([1, 2])]);
+    assert_eq!(
+      read_all("{[1 2] :a, 3 :b}").unwrap(),
+      [json!({ "[1 2]": "a", "3": "b" })]
+    );
+  }
+
+  #[test]
+  fn malformed_input_is_rejected() {
+    assert_eq!(
+      read_all("{:type :ok}\n{:type :ok, :error \"no end"),
+      Err("unterminated string at line 2".to_string())
+    );
+    assert_eq!(
+      read_all("{:type :ok, :f}"),
+      Err("map with an odd number of forms at line 1".to_string())
+    );
+    // a discarded form doesn't count
+    assert_eq!(
+      read_all("{:type :ok, #_:f}").unwrap(),
+      [json!({ "type": "ok" })]
+    );
+    assert_eq!(
+      read_all("[1 2"),
+      Err("unexpected end of input at line 1".to_string())
+    );
+    assert_eq!(read_all("[1 2}"), Err("unexpected } at line 1".to_string()));
+    assert_eq!(
+      read_all("\"\\u12\""),
+      Err("invalid unicode escape at line 1".to_string())
+    );
+  }
+}
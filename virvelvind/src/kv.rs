@@ -0,0 +1,435 @@
+//! Clients for the key-value stores Maelstrom runs as network services, `seq-kv` (sequentially
+//! consistent), `lin-kv` (linearizable) and `lww-kv` (last write wins). They all take `read`,
+//! `write` and `cas` requests, and reply with a `key does not exist` (20) error when reading a
+//! key that was never written, or `precondition failed` (22) when a `cas` finds another value
+//! than the one expected.
+//!
+//! Requests are sent through the node's [`Context`] and the outcome, a value or a [`KvError`], is
+//! handed to a callback once the service replies, like with [`Context::call`]. With the `async`
+//! feature, the `_async` variants can be awaited from tasks instead.
+
+use std::{marker::PhantomData, time::Duration};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{rpc::RpcResult, CallOptions, Context, ErrorCode, NodeError};
+
+/// The key-value services Maelstrom provides.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Service {
+  /// Sequentially consistent
+  SeqKv,
+  /// Linearizable
+  LinKv,
+  /// Last write wins; may lose writes and return stale reads
+  LwwKv,
+}
+
+impl Service {
+  /// The node id the service is addressed by.
+  pub fn id(&self) -> &'static str {
+    match self {
+      Service::SeqKv => "seq-kv",
+      Service::LinKv => "lin-kv",
+      Service::LwwKv => "lww-kv",
+    }
+  }
+}
+
+/// How a request to a key-value service failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KvError {
+  /// The key has never been written
+  KeyDoesNotExist,
+  /// `cas` found another value than `from`
+  PreconditionFailed,
+  /// No reply before the deadline; the request may or may not have taken effect
+  Timeout,
+  /// A reply that couldn't be understood; the request may or may not have taken effect
+  Unexpected(String),
+  /// Any other error the service replied with
+  Other(NodeError),
+}
+
+impl KvError {
+  /// Whether the request definitely did not take effect.
+  pub fn is_definite(&self) -> bool {
+    match self {
+      KvError::KeyDoesNotExist | KvError::PreconditionFailed => true,
+      KvError::Timeout | KvError::Unexpected(_) => false,
+      KvError::Other(err) => err.code.is_definite(),
+    }
+  }
+}
+
+impl From<NodeError> for KvError {
+  fn from(err: NodeError) -> KvError {
+    match err.code {
+      ErrorCode::KeyDoesNotExist => KvError::KeyDoesNotExist,
+      ErrorCode::PreconditionFailed => KvError::PreconditionFailed,
+      ErrorCode::Timeout => KvError::Timeout,
+      _ => KvError::Other(err),
+    }
+  }
+}
+
+/// So that handlers can pass a failed request on as the reply to the message they're handling.
+impl From<KvError> for NodeError {
+  fn from(err: KvError) -> NodeError {
+    match err {
+      KvError::KeyDoesNotExist => NodeError::key_does_not_exist("Key does not exist"),
+      KvError::PreconditionFailed => NodeError::precondition_failed("Precondition failed"),
+      KvError::Timeout => NodeError::timeout("No reply from the key-value service"),
+      KvError::Unexpected(text) => NodeError::crash(text),
+      KvError::Other(err) => err,
+    }
+  }
+}
+
+impl std::fmt::Display for KvError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      KvError::KeyDoesNotExist => write!(f, "key does not exist"),
+      KvError::PreconditionFailed => write!(f, "precondition failed"),
+      KvError::Timeout => write!(f, "timed out"),
+      KvError::Unexpected(text) => write!(f, "{text}"),
+      KvError::Other(err) => write!(f, "{err}"),
+    }
+  }
+}
+
+impl std::error::Error for KvError {}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Request<'a, K, V> {
+  Read {
+    key: &'a K,
+  },
+  Write {
+    key: &'a K,
+    value: &'a V,
+  },
+  Cas {
+    key: &'a K,
+    from: &'a V,
+    to: &'a V,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    create_if_not_exists: bool,
+  },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum Response<V> {
+  #[serde(rename = "read_ok")]
+  Read { value: V },
+  #[serde(rename = "write_ok")]
+  Write,
+  #[serde(rename = "cas_ok")]
+  Cas,
+}
+
+// replies are taken as plain JSON and parsed here, so that one the client doesn't understand can
+// be told apart from a malformed-request error sent by the service
+impl<V: DeserializeOwned> Response<V> {
+  fn parse(reply: RpcResult<Value>) -> Result<Response<V>, KvError> {
+    serde_json::from_value(reply?.body.data)
+      .map_err(|e| KvError::Unexpected(format!("Unexpected reply: {e}")))
+  }
+
+  fn value(reply: RpcResult<Value>) -> Result<V, KvError> {
+    match Response::parse(reply)? {
+      Response::Read { value } => Ok(value),
+      _ => Err(KvError::Unexpected("Expected a read_ok reply".into())),
+    }
+  }
+
+  fn done(reply: RpcResult<Value>) -> Result<(), KvError> {
+    match Response::<V>::parse(reply)? {
+      Response::Write | Response::Cas => Ok(()),
+      Response::Read { .. } => Err(KvError::Unexpected(
+        "Expected a write_ok or cas_ok reply".into(),
+      )),
+    }
+  }
+}
+
+/// A client for one of Maelstrom's key-value services, with keys of type `K` and values of type
+/// `V`. Requests time out after a second by default, and are never resent, since a `write` or
+/// `cas` that is resent may take effect twice.
+#[derive(Debug)]
+pub struct KvClient<K, V> {
+  service: Service,
+  options: CallOptions,
+  _types: PhantomData<fn(K) -> V>,
+}
+
+// not derived, as that would require K and V to be Copy
+impl<K, V> Clone for KvClient<K, V> {
+  fn clone(&self) -> Self {
+    *self
+  }
+}
+
+impl<K, V> Copy for KvClient<K, V> {}
+
+impl<K, V> KvClient<K, V>
+where
+  K: Serialize,
+  V: Serialize + DeserializeOwned + 'static,
+{
+  pub fn new(service: Service) -> KvClient<K, V> {
+    KvClient {
+      service,
+      options: CallOptions::timeout(Duration::from_secs(1)),
+      _types: PhantomData,
+    }
+  }
+
+  pub fn seq() -> KvClient<K, V> {
+    KvClient::new(Service::SeqKv)
+  }
+
+  pub fn lin() -> KvClient<K, V> {
+    KvClient::new(Service::LinKv)
+  }
+
+  pub fn lww() -> KvClient<K, V> {
+    KvClient::new(Service::LwwKv)
+  }
+
+  /// Send requests with `options` instead.
+  pub fn with_options(self, options: CallOptions) -> KvClient<K, V> {
+    KvClient { options, ..self }
+  }
+
+  pub fn service(&self) -> Service {
+    self.service
+  }
+
+  /// Read the value of `key`.
+  pub fn read<N, F>(&self, ctx: &mut Context<N>, key: &K, on_reply: F) -> Result<usize, NodeError>
+  where
+    F: FnOnce(&mut N, Result<V, KvError>, &mut Context<N>) + 'static,
+  {
+    let request: Request<K, V> = Request::Read { key };
+    ctx.call_with(
+      self.service.id(),
+      request,
+      self.options,
+      move |node, reply, ctx| on_reply(node, Response::value(reply), ctx),
+    )
+  }
+
+  /// Set `key` to `value`.
+  pub fn write<N, F>(
+    &self,
+    ctx: &mut Context<N>,
+    key: &K,
+    value: &V,
+    on_reply: F,
+  ) -> Result<usize, NodeError>
+  where
+    F: FnOnce(&mut N, Result<(), KvError>, &mut Context<N>) + 'static,
+  {
+    ctx.call_with(
+      self.service.id(),
+      Request::Write { key, value },
+      self.options,
+      move |node, reply, ctx| on_reply(node, Response::<V>::done(reply), ctx),
+    )
+  }
+
+  /// Set `key` to `to` if its value is `from`. With `create_if_not_exists`, a key that doesn't
+  /// exist is set to `to` as well, instead of failing with [`KvError::KeyDoesNotExist`].
+  pub fn cas<N, F>(
+    &self,
+    ctx: &mut Context<N>,
+    key: &K,
+    from: &V,
+    to: &V,
+    create_if_not_exists: bool,
+    on_reply: F,
+  ) -> Result<usize, NodeError>
+  where
+    F: FnOnce(&mut N, Result<(), KvError>, &mut Context<N>) + 'static,
+  {
+    ctx.call_with(
+      self.service.id(),
+      Request::Cas {
+        key,
+        from,
+        to,
+        create_if_not_exists,
+      },
+      self.options,
+      move |node, reply, ctx| on_reply(node, Response::<V>::done(reply), ctx),
+    )
+  }
+}
+
+#[cfg(feature = "async")]
+impl<K, V> KvClient<K, V>
+where
+  K: Serialize,
+  V: Serialize + DeserializeOwned + 'static,
+{
+  /// [`KvClient::read`], from a task.
+  pub async fn read_async(&self, handle: &crate::task::Handle, key: &K) -> Result<V, KvError> {
+    let request: Request<K, V> = Request::Read { key };
+    let reply = handle.call_with(self.service.id(), request, self.options);
+    Response::value(reply.await)
+  }
+
+  /// [`KvClient::write`], from a task.
+  pub async fn write_async(
+    &self,
+    handle: &crate::task::Handle,
+    key: &K,
+    value: &V,
+  ) -> Result<(), KvError> {
+    let reply = handle.call_with(
+      self.service.id(),
+      Request::Write { key, value },
+      self.options,
+    );
+    Response::<V>::done(reply.await)
+  }
+
+  /// [`KvClient::cas`], from a task.
+  pub async fn cas_async(
+    &self,
+    handle: &crate::task::Handle,
+    key: &K,
+    from: &V,
+    to: &V,
+    create_if_not_exists: bool,
+  ) -> Result<(), KvError> {
+    let reply = handle.call_with(
+      self.service.id(),
+      Request::Cas {
+        key,
+        from,
+        to,
+        create_if_not_exists,
+      },
+      self.options,
+    );
+    Response::<V>::done(reply.await)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+  use crate::req::{MaelstromRequest, RequestBody};
+
+  fn reply(body: Value) -> RpcResult<Value> {
+    Ok(MaelstromRequest {
+      src: "lin-kv".into(),
+      dest: "n0".into(),
+      body: RequestBody {
+        data: body,
+        msg_id: Some(1),
+        in_reply_to: Some(1),
+      },
+    })
+  }
+
+  #[test]
+  fn requests_are_maelstrom_bodies() {
+    let read: Request<&str, u64> = Request::Read { key: &"x" };
+    assert_eq!(
+      serde_json::to_value(read).unwrap(),
+      json!({ "type": "read", "key": "x" })
+    );
+    let write: Request<&str, u64> = Request::Write {
+      key: &"x",
+      value: &1,
+    };
+    assert_eq!(
+      serde_json::to_value(write).unwrap(),
+      json!({ "type": "write", "key": "x", "value": 1 })
+    );
+    let cas = |create_if_not_exists| {
+      let cas: Request<&str, u64> = Request::Cas {
+        key: &"x",
+        from: &1,
+        to: &2,
+        create_if_not_exists,
+      };
+      serde_json::to_value(cas).unwrap()
+    };
+    assert_eq!(
+      cas(false),
+      json!({ "type": "cas", "key": "x", "from": 1, "to": 2 })
+    );
+    assert_eq!(cas(true)["create_if_not_exists"], true);
+  }
+
+  #[test]
+  fn replies_are_read_into_values() {
+    let read = reply(json!({ "type": "read_ok", "value": [1, 2] }));
+    assert_eq!(Response::value(read), Ok(vec![1, 2]));
+    assert_eq!(
+      Response::<u64>::done(reply(json!({ "type": "write_ok" }))),
+      Ok(())
+    );
+    assert_eq!(
+      Response::<u64>::done(reply(json!({ "type": "cas_ok" }))),
+      Ok(())
+    );
+  }
+
+  #[test]
+  fn error_replies_are_mapped_to_kv_errors() {
+    let error = |code, text| Response::<u64>::value(Err(NodeError::new(code, text)));
+    assert_eq!(
+      error(ErrorCode::KeyDoesNotExist, "no x"),
+      Err(KvError::KeyDoesNotExist)
+    );
+    assert_eq!(
+      error(ErrorCode::PreconditionFailed, "x is 3"),
+      Err(KvError::PreconditionFailed)
+    );
+    assert_eq!(error(ErrorCode::Timeout, "late"), Err(KvError::Timeout));
+    let conflict = NodeError::txn_conflict("busy");
+    assert_eq!(
+      error(ErrorCode::TxnConflict, "busy"),
+      Err(KvError::Other(conflict.clone()))
+    );
+
+    // and back, to answer the message that needed the value
+    assert_eq!(
+      NodeError::from(KvError::PreconditionFailed).code,
+      ErrorCode::PreconditionFailed
+    );
+    assert_eq!(NodeError::from(KvError::Other(conflict.clone())), conflict);
+  }
+
+  #[test]
+  fn only_errors_that_rule_out_an_effect_are_definite() {
+    assert!(KvError::KeyDoesNotExist.is_definite());
+    assert!(KvError::PreconditionFailed.is_definite());
+    assert!(KvError::Other(NodeError::txn_conflict("busy")).is_definite());
+    assert!(!KvError::Timeout.is_definite());
+    assert!(!KvError::Other(NodeError::crash("gone")).is_definite());
+
+    // a reply the client can't make sense of says nothing about whether the request took effect
+    let garbled = Response::<u64>::value(reply(json!({ "type": "read_ok", "value": "one" })));
+    let garbled = garbled.unwrap_err();
+    assert!(matches!(garbled, KvError::Unexpected(_)), "{garbled:?}");
+    assert!(!garbled.is_definite());
+    let mismatched = Response::<u64>::done(reply(json!({ "type": "read_ok", "value": 1 })));
+    assert!(!mismatched.unwrap_err().is_definite());
+    let unknown = Response::<u64>::value(reply(json!({ "type": "write_ok" })));
+    assert!(!unknown.unwrap_err().is_definite());
+    // while the service saying the request was malformed means it wasn't applied
+    let malformed = Response::<u64>::value(Err(NodeError::malformed_request("no key")));
+    assert!(malformed.unwrap_err().is_definite());
+  }
+}
@@ -1,13 +1,55 @@
-use std::io::{BufRead, BufReader, StdinLock, StdoutLock, Write};
+use std::{
+  io::{BufRead, Write},
+  sync::mpsc::RecvTimeoutError,
+  time::Instant,
+};
 // rename
 pub use requests as req;
-use requests::MaelstromRequest;
 pub use response as res;
 
+pub mod check;
+pub mod clock;
+pub mod cluster;
+pub mod context;
+pub mod error;
+pub mod history;
+pub mod kv;
+pub mod log;
+pub mod metrics;
+pub mod outbound;
+pub mod outbox;
+pub mod rng;
+pub mod router;
+pub mod rpc;
+mod runtime;
+pub mod sim;
+#[cfg(feature = "async")]
+pub mod task;
+pub mod timer;
+pub mod transport;
+pub mod tso;
+pub mod workload;
+pub mod writer;
+pub use clock::{Clock, ManualClock, SystemClock};
+pub use cluster::Cluster;
+pub use context::Context;
+pub use error::{ErrorCode, NodeError};
+pub use outbound::Outbound;
+pub use outbox::Outbox;
+pub use router::{Module, Router};
+pub use rpc::{Backoff, CallOptions, ReplyHandle, RetryPolicy, RpcResult};
+use runtime::Runtime;
+pub use timer::{TimerId, Timers};
+pub use transport::Transport;
+
 use req::Initialize;
 use res::{MaelstromResponse, ResponseBody};
 
 pub use serde::{de::DeserializeOwned, Deserialize, Serialize};
+// the code generated by `service` names serde through us, so users don't need to depend on it
+#[doc(hidden)]
+pub use serde;
+pub use virvelvind_derive::service;
 
 pub use String as NetworkEntityId;
 
@@ -24,11 +66,14 @@ enum MaelstromService {
 }
 
 pub mod requests {
+  use std::borrow::Cow;
+
   use crate::{
     res::{MaelstromResponse, ResponseBody},
     NetworkEntityId,
   };
   use serde::{de::DeserializeOwned, Deserialize, Serialize};
+  use serde_json::value::RawValue;
 
   #[derive(Deserialize, Serialize, Default)]
   pub struct Initialize {
@@ -42,14 +87,16 @@ pub mod requests {
     pub data: ServiceRequestType,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub msg_id: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub in_reply_to: Option<usize>,
   }
 
   impl<ServiceRequestType> RequestBody<ServiceRequestType> {
     pub fn into_response(self, msg_id: Option<usize>) -> ResponseBody<ServiceRequestType> {
       ResponseBody {
         in_reply_to: self.msg_id,
-        msg_id: msg_id,
-        response_type: self.data.into(),
+        msg_id,
+        response_type: self.data,
       }
     }
   }
@@ -61,16 +108,120 @@ pub mod requests {
     pub body: RequestBody<ServiceRequestType>,
   }
 
-  pub fn parse_request<'a, S: DeserializeOwned>(
-    input: &'a str,
+  pub fn parse_request<S: DeserializeOwned>(
+    input: &str,
   ) -> Result<MaelstromRequest<S>, serde_json::Error> {
-    serde_json::from_str(input.trim()).map_err(|e| {
-      eprintln!("errored on input: '{input}'");
-      e
+    parse_envelope(input)?.to_request()
+  }
+
+  /// The addressing part of a message; everything but the payload of the body, which is kept as
+  /// raw JSON. Parsing only the envelope lets us find out if a message is a reply to a request
+  /// this node has sent, or who to send an error to when the payload is broken, before the body
+  /// is parsed as whatever type the one claiming it expects. Borrows from the line it was parsed
+  /// from.
+  #[derive(Debug)]
+  pub struct Envelope<'a> {
+    pub src: Cow<'a, str>,
+    pub dest: Cow<'a, str>,
+    pub body: EnvelopeBody,
+    raw_body: &'a RawValue,
+  }
+
+  #[derive(Debug, Deserialize)]
+  pub struct EnvelopeBody {
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+    pub msg_id: Option<usize>,
+    pub in_reply_to: Option<usize>,
+  }
+
+  /// Who a message is from and to, its `type`, and which message it is or answers; what the
+  /// runtime logs and counts messages by.
+  #[derive(Debug, Clone, Copy)]
+  pub(crate) struct Header<'a> {
+    pub src: &'a str,
+    pub dest: &'a str,
+    pub kind: Option<&'a str>,
+    pub msg_id: Option<usize>,
+    pub in_reply_to: Option<usize>,
+  }
+
+  impl<'a> Envelope<'a> {
+    pub fn is_error(&self) -> bool {
+      self.body.kind.as_deref() == Some("error")
+    }
+
+    pub(crate) fn header(&self) -> Header<'_> {
+      Header {
+        src: &self.src,
+        dest: &self.dest,
+        kind: self.body.kind.as_deref(),
+        msg_id: self.body.msg_id,
+        in_reply_to: self.body.in_reply_to,
+      }
+    }
+
+    /// The body, as it was received.
+    pub fn raw_body(&self) -> &'a str {
+      self.raw_body.get()
+    }
+
+    pub fn parse_body<S: DeserializeOwned>(&self) -> Result<RequestBody<S>, serde_json::Error> {
+      serde_json::from_str(self.raw_body.get())
+    }
+
+    /// The whole message, with the body parsed as `S`.
+    pub fn to_request<S: DeserializeOwned>(
+      &self,
+    ) -> Result<MaelstromRequest<S>, serde_json::Error> {
+      Ok(MaelstromRequest {
+        src: self.src.clone().into_owned(),
+        dest: self.dest.clone().into_owned(),
+        body: self.parse_body()?,
+      })
+    }
+  }
+
+  pub fn parse_envelope(input: &str) -> Result<Envelope<'_>, serde_json::Error> {
+    #[derive(Deserialize)]
+    struct Raw<'a> {
+      #[serde(borrow)]
+      src: Cow<'a, str>,
+      #[serde(borrow)]
+      dest: Cow<'a, str>,
+      #[serde(borrow)]
+      body: &'a RawValue,
+    }
+    let raw: Raw = serde_json::from_str(input.trim())?;
+    Ok(Envelope {
+      body: serde_json::from_str(raw.body.get())?,
+      src: raw.src,
+      dest: raw.dest,
+      raw_body: raw.body,
     })
   }
 
+  /// Where a message came from, without its payload.
+  #[derive(Debug, Clone)]
+  pub struct Incoming {
+    pub src: NetworkEntityId,
+    pub dest: NetworkEntityId,
+    pub msg_id: Option<usize>,
+    pub in_reply_to: Option<usize>,
+  }
+
   impl<ServiceRequestType> MaelstromRequest<ServiceRequestType> {
+    /// Separate the payload from the rest of the message.
+    pub fn split(self) -> (Incoming, ServiceRequestType) {
+      let incoming = Incoming {
+        src: self.src,
+        dest: self.dest,
+        msg_id: self.body.msg_id,
+        in_reply_to: self.body.in_reply_to,
+      };
+      (incoming, self.body.data)
+    }
+
     pub fn into_reply(
       self,
       msg_id: Option<usize>,
@@ -114,70 +265,139 @@ pub mod response {
   where
     ServiceType: Serialize,
   {
-    pub fn take_send<W: std::io::Write>(self, output: &mut W) -> Result<(), &'static str> {
-      let contents = serde_json::to_string(&self).map_err(|_| "Couldn't serialize message")?;
-      output
-        .write(contents.as_bytes())
-        .expect("Failed to write response");
-      output.write_all(b"\n").expect("Failed to write newline");
-      Ok(())
+    /// Write the message to `output` as a line of JSON. Doesn't flush `output`.
+    pub fn take_send<W: std::io::Write>(
+      self,
+      output: &mut W,
+    ) -> Result<(), crate::writer::WriteError> {
+      self.send_ref(output)
     }
 
-    pub fn send_ref<W: std::io::Write>(&self, output: &mut W) -> Result<(), &'static str> {
-      let contents = serde_json::to_string(&self).map_err(|_| "Couldn't serialize message")?;
-      output
-        .write(contents.as_bytes())
-        .expect("Failed to write response");
-      output.write_all(b"\n").expect("Failed to write newline");
-      Ok(())
+    /// Write the message to `output` as a line of JSON. Doesn't flush `output`.
+    pub fn send_ref<W: std::io::Write>(
+      &self,
+      output: &mut W,
+    ) -> Result<(), crate::writer::WriteError> {
+      crate::writer::write_line(output, self)
     }
   }
 }
 
-pub trait Node<ServiceType>
+/// The messages a node understands. [`service`] implements it for the enums it's put on; nodes
+/// taking their messages as [`serde_json::Value`]s, like those using a [`Router`], take any type.
+pub trait Service: Serialize + DeserializeOwned + Send {
+  /// The `type` of every message, so that one of another type can be told apart from one that
+  /// doesn't fit its type. Empty if the service takes messages of any type.
+  fn type_names() -> &'static [&'static str];
+}
+
+impl Service for serde_json::Value {
+  fn type_names() -> &'static [&'static str] {
+    &[]
+  }
+}
+
+/// A Maelstrom node. The runtime (see [`start_service`]) initializes it, then hands it every
+/// event; messages from other nodes and clients, expired timers and whatever side channel threads
+/// post. Everything the node sends goes through the [`Context`] it's handed, so a handler may send
+/// zero, one or many messages, to any destination.
+pub trait Node<ServiceType>: Sized
 where
-  ServiceType: DeserializeOwned + Serialize,
+  ServiceType: DeserializeOwned + Serialize + Send,
 {
-  fn init(&mut self, init: Initialize);
-  fn get_init(&self) -> &Initialize;
+  /// Called once with the `init` message, before anything else. The runtime keeps it; handlers
+  /// find the node's id and its peers through [`Context::cluster`].
+  fn init(&mut self, _init: &Initialize) {}
 
-  fn is_initialized(&self) -> bool {
-    let init = self.get_init();
-    let default_init = Initialize::default();
-    default_init.node_id != init.node_id
-  }
-  fn process_message(
+  /// Spawn a thread that posts events to the node through `tx`. The thread must return once
+  /// `tx.is_shutting_down()` or a send fails, as the runtime joins it when shutting down.
+  fn setup_sidechannel_thread(
     &mut self,
-    msg: req::MaelstromRequest<ServiceType>,
-    local_msg_id: usize,
-  ) -> Result<res::MaelstromResponse<ServiceType>, String>;
+    _tx: EventSender<ServiceType>,
+  ) -> Option<std::thread::JoinHandle<()>> {
+    None
+  }
+
+  /// Register the timers the node needs from the start, f.ex. a gossip interval. Called once,
+  /// after `init`.
+  fn setup_timers(&mut self, _timers: &mut Timers) {}
+
+  /// Handle `evt`. Replies to requests sent with `ctx.call` never show up here, they are routed
+  /// to the callback registered with the request instead. Returning an error while handling an
+  /// `IOEvent` makes the runtime send it as an `error` reply to the message.
+  fn handle(&mut self, evt: Event<ServiceType>, ctx: &mut Context<Self>) -> Result<(), NodeError>;
+
+  /// Called once the input has ended, before the runtime exits; f.ex. to flush state or dump
+  /// metrics. Messages sent here are still written, but timers won't fire and replies to
+  /// requests won't arrive anymore.
+  fn on_shutdown(&mut self, _ctx: &mut Context<Self>) {}
 }
 
 pub enum Event<ServiceType: Serialize + DeserializeOwned + Send> {
   IOEvent(req::MaelstromRequest<ServiceType>),
-  GossipEvent,
+  /// A timer registered with [`Timers`] has expired
+  Timer(TimerId),
 }
 
-pub trait GossipMessagePump<ServiceType>
-where
-ServiceType: Serialize + DeserializeOwned + Send + 'static,
-{
-  fn get_duration(&self) -> std::time::Duration;
-  fn tx(&self) -> std::sync::mpsc::Sender<Event<ServiceType>>;
+/// What the input thread and side channel threads feed the event loop in `start_service`. Input
+/// is passed on as raw lines, so that the event loop can first check if it's a reply to a
+/// request made by the node, before deserializing it as a `ServiceType` message.
+enum Inbound<ServiceType: Serialize + DeserializeOwned + Send> {
+  Line(String),
+  Event(Event<ServiceType>),
+  /// The input has ended (or can't be read anymore)
+  Eof,
 }
 
-pub trait CooperativeNode<ServiceType>: Node<ServiceType>
-where
-  ServiceType: DeserializeOwned + Serialize + Send,
-{
-  fn setup_sidechannel_thread(
-    &mut self,
-    _tx: std::sync::mpsc::Sender<Event<ServiceType>>,
-  ) -> Option<std::thread::JoinHandle<()>> {
-    None
+/// Handed to side channel threads, so that they can post events to the node's event loop, or
+/// send messages directly through [`EventSender::outbound`].
+pub struct EventSender<ServiceType: Serialize + DeserializeOwned + Send> {
+  tx: std::sync::mpsc::Sender<Inbound<ServiceType>>,
+  outbound: Outbound,
+  shutting_down: std::sync::Arc<std::sync::atomic::AtomicBool>,
+  /// Number of events sent but not yet taken off the queue by the event loop
+  queued: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+}
+
+impl<ServiceType: Serialize + DeserializeOwned + Send> Clone for EventSender<ServiceType> {
+  fn clone(&self) -> Self {
+    EventSender {
+      tx: self.tx.clone(),
+      outbound: self.outbound.clone(),
+      shutting_down: self.shutting_down.clone(),
+      queued: self.queued.clone(),
+    }
+  }
+}
+
+impl<ServiceType: Serialize + DeserializeOwned + Send> EventSender<ServiceType> {
+  /// Fails once the event loop has exited.
+  pub fn send(
+    &self,
+    evt: Event<ServiceType>,
+  ) -> Result<(), std::sync::mpsc::SendError<Event<ServiceType>>> {
+    self
+      .queued
+      .fetch_add(1, std::sync::atomic::Ordering::AcqRel);
+    self.tx.send(Inbound::Event(evt)).map_err(|e| match e.0 {
+      Inbound::Event(evt) => std::sync::mpsc::SendError(evt),
+      Inbound::Line(_) | Inbound::Eof => {
+        unreachable!("only events are sent through an EventSender")
+      }
+    })
+  }
+
+  /// A handle for sending messages as the node without going through the event loop.
+  pub fn outbound(&self) -> Outbound {
+    self.outbound.clone()
   }
 
-  fn process_event(&mut self, msg: Event<ServiceType>, local_msg_id: usize, comms: &mut StdoutLock);
+  /// Whether the node is shutting down, in which case the thread holding this should return.
+  pub fn is_shutting_down(&self) -> bool {
+    self
+      .shutting_down
+      .load(std::sync::atomic::Ordering::Acquire)
+  }
 }
 
 pub fn prepare_response<ServiceType: serde::Serialize>(
@@ -193,8 +413,8 @@ fn init_response(
   dest: String,
 ) -> MaelstromResponse<MaelstromService> {
   MaelstromResponse {
-    src: src,
-    dest: dest,
+    src,
+    dest,
     body: ResponseBody {
       msg_id: Some(msg_id),
       response_type: MaelstromService::InitOk,
@@ -205,119 +425,265 @@ fn init_response(
 
 fn exit_threads<T1, T2>(
   io: std::thread::JoinHandle<T1>,
-  gossip: Option<std::thread::JoinHandle<T2>>,
+  sidechannel: Option<std::thread::JoinHandle<T2>>,
 ) -> Result<(), String> {
   io.join()
     .map_err(|e| format!("IO Thread join failed. Cause:\n\t {e:#?}"))?;
-  let Some(gossip) = gossip else { return Ok(()) };
-  gossip
+  let Some(sidechannel) = sidechannel else {
+    return Ok(());
+  };
+  sidechannel
     .join()
-    .map_err(|e| format!("Gossip thread join failed. Cause:\n\t {e:#?}"))?;
+    .map_err(|e| format!("Side channel thread join failed. Cause:\n\t {e:#?}"))?;
   Ok(())
 }
 
-fn wait_for_init_and_respond(mut stdin: StdinLock) -> Result<Initialize, String> {
+fn wait_for_init_and_respond<R: BufRead, W: Write>(
+  input: &mut R,
+  output: &mut writer::MessageWriter<W>,
+) -> Result<Initialize, String> {
   let mut buf = String::with_capacity(512);
-  stdin
+  input
     .read_line(&mut buf)
     .map_err(|_| "Failed to read init packet")?;
-  eprintln!("first packet: {}", &buf);
   let init: req::MaelstromRequest<Initialize> = serde_json::from_str(&buf).map_err(|e| {
     format!("Init request always required but failed to parse: {e}. Contents: {buf}")
   })?;
+  if init.body.data.node_id.is_empty() {
+    return Err("Node initialized with faulty settings: no node id".to_string());
+  }
+  // without a msg_id there's nothing the init_ok could be a reply to
+  let Some(msg_id) = init.body.msg_id else {
+    return Err(format!("Init request has no msg_id to reply to. Contents: {buf}"));
+  };
 
-  let init_respose_ = init_response(
-    init.body.msg_id.expect("Init request ill-formed"),
-    1,
-    init.dest,
-    init.src,
-  );
-  let msg =
-    serde_json::to_string(&init_respose_).map_err(|_| "Failed to serialize init resposne")?;
-  let mut stdout: StdoutLock = std::io::stdout().lock();
-  stdout
-    .write(msg.as_bytes())
-    .expect("Failed to send init response");
-  stdout.write_all(b"\n").expect("");
+  let init_respose_ = init_response(msg_id, 1, init.dest, init.src);
+  output
+    .write_message(&init_respose_)
+    .map_err(|e| format!("Failed to send init response: {e}"))?;
+  output
+    .flush()
+    .map_err(|e| format!("Failed to send init response: {e}"))?;
 
   Ok(init.body.data)
 }
 
-pub fn start_service<N, ServiceType>(mut node: N) -> Result<(), String>
+/// Run `node` as a Maelstrom node, talking to Maelstrom over stdin and stdout.
+pub fn start_service<N, ServiceType>(node: N) -> Result<(), String>
 where
-  N: CooperativeNode<ServiceType>,
-  ServiceType: Serialize + DeserializeOwned + Send + 'static,
+  N: Node<ServiceType>,
+  ServiceType: Service + 'static,
 {
-  let (tx, rx) = std::sync::mpsc::channel::<Event<ServiceType>>();
+  serve(node, transport::Stdio)
+}
 
-  let init = wait_for_init_and_respond(std::io::stdin().lock())?;
-  node.init(init);
+/// Run `node` as a Maelstrom node over stdin and stdout, dispatching the messages it's sent with
+/// `router`. See [`serve_routed`].
+pub fn start_routed<N, ServiceType>(node: N, router: Router<N>) -> Result<(), String>
+where
+  N: Node<ServiceType>,
+  ServiceType: Service + 'static,
+{
+  serve_routed(node, router, transport::Stdio)
+}
 
-  if !node.is_initialized() {
-    panic!("Node initialized with faulty settings");
-  }
+/// Run `node`, reading its messages from and writing its messages to `transport`.
+pub fn serve<N, ServiceType, T>(node: N, transport: T) -> Result<(), String>
+where
+  N: Node<ServiceType>,
+  ServiceType: Service + 'static,
+  T: Transport,
+{
+  run(node, None, transport)
+}
 
-  let node_tx_ = tx.clone();
-  let gossip_thread = node.setup_sidechannel_thread(node_tx_);
+/// Like [`serve`], but every message the node is sent is handed to `router` instead of
+/// `Node::handle`, which only gets timers and the events side channel threads post. A message of
+/// a type the router has no handler for is answered with `not-supported`. Messages aren't parsed
+/// as `ServiceType` then, so a node that takes all of them through the router can implement
+/// `Node<serde_json::Value>`.
+pub fn serve_routed<N, ServiceType, T>(
+  node: N,
+  router: Router<N>,
+  transport: T,
+) -> Result<(), String>
+where
+  N: Node<ServiceType>,
+  ServiceType: Service + 'static,
+  T: Transport,
+{
+  run(node, Some(router), transport)
+}
 
+fn run<N, ServiceType, T>(
+  mut node: N,
+  router: Option<Router<N>>,
+  transport: T,
+) -> Result<(), String>
+where
+  N: Node<ServiceType>,
+  ServiceType: Service + 'static,
+  T: Transport,
+{
+  let (tx, rx) = std::sync::mpsc::channel::<Inbound<ServiceType>>();
+
+  let (mut input, output) = transport
+    .open()
+    .map_err(|e| format!("Failed to open transport: {e}"))?;
+  let mut output = writer::MessageWriter::new(output, writer::FlushPolicy::from_env());
+  let init = wait_for_init_and_respond(&mut input, &mut output)?;
+  node.init(&init);
+
+  // Everything the node sends, from the event loop or any other thread, is written by this one,
+  // a chunk of complete lines at a time. An empty chunk tells it to stop.
+  let (writer_tx, writer_rx) = std::sync::mpsc::channel::<Vec<u8>>();
+  let writer_thread = std::thread::spawn(move || writer::drain(&mut output, writer_rx));
+
+  let mut runtime: Runtime<N, ServiceType> = Runtime::new(
+    node,
+    &init,
+    Box::new(SystemClock),
+    rng::Rng::from_time(),
+    writer_tx.clone(),
+  );
+  if let Some(router) = router {
+    runtime.route(std::rc::Rc::new(router));
+  }
+  let shutting_down = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+  let queued = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+  let node_tx_ = EventSender {
+    tx: tx.clone(),
+    outbound: runtime.outbound(),
+    shutting_down: shutting_down.clone(),
+    queued: queued.clone(),
+  };
+  let sidechannel_thread = runtime.node_mut().setup_sidechannel_thread(node_tx_);
+  let flush = |runtime: &mut Runtime<N, ServiceType>| -> Result<(), String> {
+    let out = runtime.take_output();
+    if out.is_empty() {
+      return Ok(());
+    }
+    writer_tx
+      .send(out)
+      .map_err(|_| "Failed to write output: the writer thread has exited".to_string())
+  };
+  let io_log = runtime.log().clone();
   let io_tx = tx.clone();
+  let io_queued = queued.clone();
   let input_notifier_thread = std::thread::spawn(move || -> Result<(), String> {
-    let stdin = std::io::stdin().lock();
-    let mut reader = BufReader::new(stdin);
     let mut buf = String::with_capacity(512);
     loop {
-      reader.read_line(&mut buf).expect("Failed to read input");
-      let req: req::MaelstromRequest<ServiceType> =
-        req::parse_request(&buf).map_err(|e| format!("Failed to parse request: {e:?}"))?;
+      match input.read_line(&mut buf) {
+        Ok(0) => break,
+        Ok(_) => {}
+        Err(e) => {
+          // treated as the end of input
+          io_log
+            .record(
+              log::Level::Error,
+              "failed to read input",
+              std::time::SystemTime::now(),
+            )
+            .field("error", e.to_string())
+            .emit();
+          break;
+        }
+      }
+      let line = std::mem::replace(&mut buf, String::with_capacity(512));
+      io_queued.fetch_add(1, std::sync::atomic::Ordering::AcqRel);
       io_tx
-        .send(Event::IOEvent(req))
+        .send(Inbound::Line(line))
         .map_err(|e| format!("Failed to send IO Event {e:#}"))?;
-      buf.clear();
     }
+    io_tx
+      .send(Inbound::Eof)
+      .map_err(|e| format!("Failed to send IO Event {e:#}"))
   });
 
-  let mut stdout: StdoutLock = std::io::stdout().lock();
-  let mut msg_id = 2..usize::MAX;
+  if let Ok(path) = std::env::var("VIRVELVIND_HISTORY") {
+    let file = std::fs::File::create(&path)
+      .map_err(|e| format!("Failed to create history file {path}: {e}"))?;
+    runtime.record_history(Instant::now(), Some(Box::new(file)));
+  }
   loop {
-    match rx.recv() {
-      Ok(evt) => {
-        node.process_event(
-          evt,
-          msg_id.next().expect("failed to get new msg_id"),
-          &mut stdout,
-        );
-      }
+    runtime.tick(Instant::now());
+    runtime.fire_expired();
+    flush(&mut runtime)?;
+
+    let received = match runtime.next_deadline() {
+      Some(deadline) => rx.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+      None => rx.recv().map_err(RecvTimeoutError::from),
+    };
+    runtime.tick(Instant::now());
+    if matches!(received, Ok(Inbound::Line(_) | Inbound::Event(_))) {
+      let depth = queued.fetch_sub(1, std::sync::atomic::Ordering::AcqRel) - 1;
+      runtime
+        .metrics_mut()
+        .observe("queue_depth", &[], depth as u64);
+    }
+    match received {
+      Ok(Inbound::Line(line)) => runtime.handle_line(&line),
+      Ok(Inbound::Event(evt)) => runtime.handle_event(evt),
+      Ok(Inbound::Eof) => break,
+      Err(RecvTimeoutError::Timeout) => {}
       Err(e) => {
-        exit_threads(input_notifier_thread, gossip_thread)?;
+        exit_threads(input_notifier_thread, sidechannel_thread)?;
         return Err(format!("Application Level Error: {e:#}"));
       }
     }
   }
+
+  runtime.shutdown();
+  flush(&mut runtime)?;
+  // side channel threads see the flag, or fail to send once the event loop's end is dropped
+  shutting_down.store(true, std::sync::atomic::Ordering::Release);
+  drop(rx);
+  exit_threads(input_notifier_thread, sidechannel_thread)?;
+  // account for what the side channel threads sent last
+  flush(&mut runtime)?;
+  // only once the side channel threads are done, so that the last messages they sent get written
+  let _ = writer_tx.send(Vec::new());
+  writer_thread
+    .join()
+    .map_err(|e| format!("Writer thread join failed. Cause:\n\t {e:#?}"))?
+    .map_err(|e| format!("Failed to write output: {e}"))
 }
 
-pub fn start_maelstrom_service_node<N, ServiceType>(mut node: N) -> Result<(), String>
-where
-  N: Node<ServiceType>,
-  ServiceType: Serialize + DeserializeOwned,
-{
-  let init = wait_for_init_and_respond(std::io::stdin().lock())?;
-  node.init(init);
+#[cfg(test)]
+mod tests {
+  use std::io::Cursor;
 
-  if !node.is_initialized() {
-    panic!("Node initialized with faulty settings");
+  use super::*;
+
+  fn init(line: &str) -> (Result<Initialize, String>, Vec<u8>) {
+    let mut written = Vec::new();
+    let mut output = writer::MessageWriter::new(&mut written, writer::FlushPolicy::Message);
+    let init = wait_for_init_and_respond(&mut Cursor::new(line), &mut output);
+    drop(output);
+    (init, written)
   }
 
-  let mut stdout: StdoutLock = std::io::stdout().lock();
-  let mut reader = BufReader::new(std::io::stdin().lock());
-  let mut msg_id = 2..usize::MAX;
-  let mut buf = String::with_capacity(512);
-  loop {
-    buf.clear();
-    reader.read_line(&mut buf).expect("Failed to read input");
-    let req: req::MaelstromRequest<ServiceType> =
-      req::parse_request(&buf).map_err(|e| format!("Failed to parse request: {e:?}"))?;
-    node
-      .process_message(req, msg_id.next().expect("Ran out of message id's"))?
-      .take_send(&mut stdout)?;
+  #[test]
+  fn init_is_answered_with_init_ok() {
+    let line = r#"{"src":"c0","dest":"n1","body":{"type":"init","msg_id":7,"node_id":"n1","node_ids":["n1","n2"]}}"#;
+    let (init, output) = init(line);
+    let init = init.expect("a valid init");
+    assert_eq!(init.node_id, "n1");
+    assert_eq!(init.node_ids, ["n1", "n2"]);
+    let reply: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(reply["dest"], "c0");
+    assert_eq!(reply["body"]["type"], "init_ok");
+    assert_eq!(reply["body"]["in_reply_to"], 7);
+  }
+
+  #[test]
+  fn init_without_a_msg_id_is_an_error() {
+    let line = r#"{"src":"c0","dest":"n1","body":{"type":"init","node_id":"n1","node_ids":["n1"]}}"#;
+    let (init, output) = init(line);
+    let Err(error) = init else {
+      panic!("init without a msg_id was accepted");
+    };
+    assert!(error.starts_with("Init request has no msg_id"), "{error}");
+    assert!(output.is_empty());
   }
 }
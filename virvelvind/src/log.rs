@@ -0,0 +1,209 @@
+//! Structured logging to stderr, one JSON object per line, so that the logs Maelstrom collects
+//! for each node can be grepped and parsed. Every record carries the time, level, node id and a
+//! message, followed by whatever fields were attached:
+//!
+//! ```text
+//! {"ts_us":1700000000000000,"level":"debug","node":"n1","msg":"message","dir":"in","src":"c1",...}
+//! ```
+//!
+//! The runtime logs every message a node receives (with how long handling it took) and sends at
+//! `debug`. Records logged by a handler through [`Context::log`](crate::Context::log) get a
+//! `span` field describing the message being handled, so they can be matched up with it.
+//!
+//! The level is read from the `VIRVELVIND_LOG` environment variable; one of `off`, `error`,
+//! `warn` (the default), `info`, `debug` or `trace`.
+
+use std::{
+  fmt::Write as _,
+  io::Write as _,
+  str::FromStr,
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+use crate::{
+  req::{self, Header},
+  NetworkEntityId,
+};
+
+/// The environment variable the log level is read from
+pub const LOG_ENV: &str = "VIRVELVIND_LOG";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+  Error,
+  Warn,
+  Info,
+  Debug,
+  Trace,
+}
+
+impl Level {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Level::Error => "error",
+      Level::Warn => "warn",
+      Level::Info => "info",
+      Level::Debug => "debug",
+      Level::Trace => "trace",
+    }
+  }
+}
+
+impl FromStr for Level {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Level, String> {
+    match s.trim().to_ascii_lowercase().as_str() {
+      "error" => Ok(Level::Error),
+      "warn" | "warning" => Ok(Level::Warn),
+      "info" => Ok(Level::Info),
+      "debug" => Ok(Level::Debug),
+      "trace" => Ok(Level::Trace),
+      _ => Err(format!("Unknown log level: {s}")),
+    }
+  }
+}
+
+/// The message a node is handling; attached to everything it logs meanwhile.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Span {
+  src: NetworkEntityId,
+  #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+  kind: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  msg_id: Option<usize>,
+}
+
+/// Writes a node's log records. `None` as the level means logging is off.
+#[derive(Debug, Clone)]
+pub struct Logger {
+  node_id: NetworkEntityId,
+  level: Option<Level>,
+  span: Option<Span>,
+}
+
+impl Logger {
+  pub fn new(node_id: &str, level: Option<Level>) -> Logger {
+    Logger {
+      node_id: node_id.to_owned(),
+      level,
+      span: None,
+    }
+  }
+
+  /// Log at the level set by `VIRVELVIND_LOG`, `warn` if it isn't set or can't be parsed.
+  pub fn from_env(node_id: &str) -> Logger {
+    let (level, invalid) = match std::env::var(LOG_ENV) {
+      Ok(level) if level.trim().eq_ignore_ascii_case("off") => (None, None),
+      Ok(level) => match level.parse() {
+        Ok(level) => (Some(level), None),
+        Err(e) => (Some(Level::Warn), Some(e)),
+      },
+      Err(_) => (Some(Level::Warn), None),
+    };
+    let log = Logger::new(node_id, level);
+    if let Some(e) = invalid {
+      log
+        .record(
+          Level::Warn,
+          "invalid log level, logging at warn",
+          SystemTime::now(),
+        )
+        .field("error", e)
+        .emit();
+    }
+    log
+  }
+
+  pub fn enabled(&self, level: Level) -> bool {
+    self.level.is_some_and(|max| level <= max)
+  }
+
+  /// Start a record, written once [`Record::emit`] is called. `now` is the time it's logged at.
+  pub fn record(&self, level: Level, msg: &str, now: SystemTime) -> Record {
+    if !self.enabled(level) {
+      return Record { buf: None };
+    }
+    let ts = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let record = Record {
+      buf: Some(String::with_capacity(256)),
+    }
+    .field("ts_us", ts.as_micros() as u64)
+    .field("level", level.as_str())
+    .field("node", &self.node_id)
+    .field("msg", msg);
+    match &self.span {
+      Some(span) => record.field("span", span),
+      None => record,
+    }
+  }
+
+  /// Log a message the node received or sent at `debug`; `dir` is `in` or `out`.
+  pub(crate) fn message(
+    &self,
+    dir: &str,
+    header: Header,
+    latency: Option<Duration>,
+    now: SystemTime,
+  ) {
+    if !self.enabled(Level::Debug) {
+      return;
+    }
+    let mut record = self
+      .record(Level::Debug, "message", now)
+      .field("dir", dir)
+      .field("src", header.src)
+      .field("dest", header.dest)
+      .field("type", header.kind)
+      .field("msg_id", header.msg_id)
+      .field("in_reply_to", header.in_reply_to);
+    if let Some(latency) = latency {
+      record = record.field("latency_us", latency.as_micros() as u64);
+    }
+    record.emit();
+  }
+
+  /// Attach `envelope` to what's logged until the span is cleared.
+  pub(crate) fn enter(&mut self, envelope: &req::Envelope) {
+    self.span = Some(Span {
+      src: envelope.src.to_string(),
+      kind: envelope.body.kind.clone(),
+      msg_id: envelope.body.msg_id,
+    });
+  }
+
+  pub(crate) fn exit(&mut self) {
+    self.span = None;
+  }
+}
+
+/// A log record being built. Does nothing if its level isn't enabled.
+#[must_use = "a record is only written by `emit`"]
+pub struct Record {
+  // JSON object, without the closing brace; None if the record won't be written
+  buf: Option<String>,
+}
+
+impl Record {
+  /// Add `key` with `value` to the record. Values that can't be serialized are logged as null.
+  pub fn field<V: Serialize>(mut self, key: &str, value: V) -> Record {
+    if let Some(buf) = &mut self.buf {
+      buf.push(if buf.is_empty() { '{' } else { ',' });
+      let value = serde_json::to_string(&value).unwrap_or_else(|_| "null".into());
+      let key = serde_json::to_string(key).expect("strings always serialize");
+      let _ = write!(buf, "{key}:{value}");
+    }
+    self
+  }
+
+  /// Write the record to stderr.
+  pub fn emit(self) {
+    let Some(mut buf) = self.buf else {
+      return;
+    };
+    buf.push_str("}\n");
+    let _ = std::io::stderr().lock().write_all(buf.as_bytes());
+  }
+}
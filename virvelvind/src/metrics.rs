@@ -0,0 +1,214 @@
+//! Counters, gauges and histograms kept by every node. The runtime keeps track of:
+//!
+//! - `messages_received` and `messages_sent`, by `type`, and `messages_received_from` and
+//!   `messages_sent_to`, by `peer`
+//! - `bytes_sent`
+//! - `handler_latency_us`, a histogram of how long handling a message took, by `type`
+//! - `queue_depth`, a histogram of the number of events still waiting each time one is taken off
+//!   the queue to be handled (only when run by [`start_service`](crate::start_service))
+//! - `pending_requests`, the number of requests waiting on a reply
+//! - `rejected_messages`, by `type` and `reason`; messages dropped or answered with an error
+//!   because they weren't understood: `invalid_envelope`, `not_supported` (the node's service
+//!   type doesn't have the message's `type`, or its router has no handler for it) or `malformed`
+//!   (the body doesn't fit the type)
+//!
+//! Nodes add their own through [`Context::metrics`](crate::Context::metrics). Metrics are
+//! identified by a name and a set of labels, written like `messages_sent{type="gossip"}`.
+//!
+//! A node's metrics are logged at `info` when it shuts down (the default level is `warn`, see
+//! [`log`](crate::log)), and it replies to a message of type [`METRICS_REQUEST`] with a
+//! `metrics_ok` carrying them, so they can be fetched while it runs.
+
+use std::collections::BTreeMap;
+
+use serde::{Serialize, Serializer};
+
+use crate::req::Header;
+
+/// The message type a node answers with its metrics
+pub const METRICS_REQUEST: &str = "metrics";
+
+/// A distribution of values, counted in buckets of powers of two. Quantiles are estimated as the
+/// upper bound of the bucket they fall in.
+#[derive(Debug, Clone, Default)]
+pub struct Histogram {
+  // buckets[i] counts the values v where 2^(i-1) < v <= 2^i; buckets[0] counts 0 and 1
+  buckets: Vec<u64>,
+  count: u64,
+  sum: u64,
+  min: u64,
+  max: u64,
+}
+
+impl Histogram {
+  pub fn observe(&mut self, value: u64) {
+    let bucket = if value <= 1 {
+      0
+    } else {
+      (u64::BITS - (value - 1).leading_zeros()) as usize
+    };
+    if self.buckets.len() <= bucket {
+      self.buckets.resize(bucket + 1, 0);
+    }
+    self.buckets[bucket] += 1;
+    self.min = if self.count == 0 {
+      value
+    } else {
+      self.min.min(value)
+    };
+    self.max = self.max.max(value);
+    self.count += 1;
+    self.sum = self.sum.saturating_add(value);
+  }
+
+  pub fn count(&self) -> u64 {
+    self.count
+  }
+
+  pub fn sum(&self) -> u64 {
+    self.sum
+  }
+
+  pub fn min(&self) -> Option<u64> {
+    (self.count > 0).then_some(self.min)
+  }
+
+  pub fn max(&self) -> Option<u64> {
+    (self.count > 0).then_some(self.max)
+  }
+
+  pub fn mean(&self) -> Option<f64> {
+    (self.count > 0).then(|| self.sum as f64 / self.count as f64)
+  }
+
+  /// Estimate the value that the fraction `q` (between 0 and 1) of the values are less than or
+  /// equal to.
+  pub fn quantile(&self, q: f64) -> Option<u64> {
+    if self.count == 0 {
+      return None;
+    }
+    let rank = ((q.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+    let mut seen = 0;
+    for (bucket, count) in self.buckets.iter().enumerate() {
+      seen += count;
+      if seen >= rank {
+        return Some((1u64 << bucket).clamp(self.min, self.max));
+      }
+    }
+    Some(self.max)
+  }
+}
+
+impl Serialize for Histogram {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    #[derive(Serialize)]
+    struct Summary {
+      count: u64,
+      sum: u64,
+      min: Option<u64>,
+      max: Option<u64>,
+      p50: Option<u64>,
+      p90: Option<u64>,
+      p99: Option<u64>,
+    }
+    Summary {
+      count: self.count,
+      sum: self.sum,
+      min: self.min(),
+      max: self.max(),
+      p50: self.quantile(0.5),
+      p90: self.quantile(0.9),
+      p99: self.quantile(0.99),
+    }
+    .serialize(serializer)
+  }
+}
+
+/// A node's metrics, by name and labels. Serializes to an object with `counters`, `gauges` and
+/// `histograms`, each keyed like `name{label="value"}`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Metrics {
+  counters: BTreeMap<String, u64>,
+  gauges: BTreeMap<String, i64>,
+  histograms: BTreeMap<String, Histogram>,
+}
+
+impl Metrics {
+  pub fn new() -> Metrics {
+    Metrics::default()
+  }
+
+  /// The key `name` with `labels` is stored under.
+  pub fn key(name: &str, labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+      return name.to_owned();
+    }
+    let labels: Vec<_> = labels.iter().map(|(k, v)| format!("{k}={v:?}")).collect();
+    format!("{name}{{{}}}", labels.join(","))
+  }
+
+  /// Add `by` to the counter.
+  pub fn increment(&mut self, name: &str, labels: &[(&str, &str)], by: u64) {
+    *self.counters.entry(Metrics::key(name, labels)).or_default() += by;
+  }
+
+  pub fn set_gauge(&mut self, name: &str, labels: &[(&str, &str)], value: i64) {
+    self.gauges.insert(Metrics::key(name, labels), value);
+  }
+
+  /// Record `value` in the histogram.
+  pub fn observe(&mut self, name: &str, labels: &[(&str, &str)], value: u64) {
+    self
+      .histograms
+      .entry(Metrics::key(name, labels))
+      .or_default()
+      .observe(value);
+  }
+
+  /// The value of the counter, 0 if it's never been incremented.
+  pub fn counter(&self, name: &str, labels: &[(&str, &str)]) -> u64 {
+    self
+      .counters
+      .get(&Metrics::key(name, labels))
+      .copied()
+      .unwrap_or(0)
+  }
+
+  pub fn gauge(&self, name: &str, labels: &[(&str, &str)]) -> Option<i64> {
+    self.gauges.get(&Metrics::key(name, labels)).copied()
+  }
+
+  pub fn histogram(&self, name: &str, labels: &[(&str, &str)]) -> Option<&Histogram> {
+    self.histograms.get(&Metrics::key(name, labels))
+  }
+
+  /// Sum of the counters called `name`, whatever their labels.
+  pub fn counter_total(&self, name: &str) -> u64 {
+    self
+      .counters
+      .iter()
+      .filter(|(key, _)| key.as_str() == name || key.starts_with(&format!("{name}{{")))
+      .map(|(_, value)| value)
+      .sum()
+  }
+
+  /// The message `header` is from was received, and handling it took `latency`.
+  pub(crate) fn received(&mut self, header: Header, latency: std::time::Duration) {
+    let kind = header.kind.unwrap_or("");
+    self.increment("messages_received", &[("type", kind)], 1);
+    self.increment("messages_received_from", &[("peer", header.src)], 1);
+    self.observe(
+      "handler_latency_us",
+      &[("type", kind)],
+      latency.as_micros() as u64,
+    );
+  }
+
+  /// The message `header` is from, `bytes` long, was sent.
+  pub(crate) fn sent(&mut self, header: Header, bytes: usize) {
+    let kind = header.kind.unwrap_or("");
+    self.increment("messages_sent", &[("type", kind)], 1);
+    self.increment("messages_sent_to", &[("peer", header.dest)], 1);
+    self.increment("bytes_sent", &[], bytes as u64);
+  }
+}
@@ -0,0 +1,117 @@
+//! Sending messages from outside the event loop. [`Outbound`] handles can be cloned and moved to
+//! any thread; every message is serialized on the sending thread and handed to whatever writes
+//! the node's output as one complete line, so messages from different threads never interleave.
+//! When run by [`start_service`](crate::start_service) that's a dedicated writer thread, which
+//! also writes everything the node sends from its handlers.
+//!
+//! The runtime is handed a copy of every message sent through an `Outbound`, which it counts in
+//! the node's [`metrics`](crate::metrics), logs and records in its history like the messages the
+//! node sends from its handlers. It does so the next time it writes out what the node has sent,
+//! so the message itself isn't held up, only its accounting.
+
+use std::sync::{
+  atomic::{AtomicUsize, Ordering},
+  mpsc, Arc,
+};
+
+use serde::Serialize;
+
+use crate::{
+  res::{MaelstromResponse, ResponseBody},
+  NetworkEntityId, NodeError, Outbox,
+};
+
+/// The node's `msg_id` sequence, shared by the event loop and every [`Outbound`] handle so that
+/// every message gets a unique id, whichever thread sent it.
+#[derive(Debug, Clone)]
+pub struct MsgIds {
+  next: Arc<AtomicUsize>,
+}
+
+impl MsgIds {
+  pub fn starting_at(first: usize) -> MsgIds {
+    MsgIds {
+      next: Arc::new(AtomicUsize::new(first)),
+    }
+  }
+
+  pub fn next(&self) -> usize {
+    let id = self.next.fetch_add(1, Ordering::Relaxed);
+    assert!(id != usize::MAX, "Ran out of message id's");
+    id
+  }
+}
+
+/// A handle for sending messages as the node from any thread.
+#[derive(Debug, Clone)]
+pub struct Outbound {
+  node_id: NetworkEntityId,
+  msg_ids: MsgIds,
+  tx: mpsc::Sender<Vec<u8>>,
+  /// Where the runtime picks up what was sent, to account for it
+  sent: mpsc::Sender<Outbox>,
+}
+
+impl Outbound {
+  pub(crate) fn new(
+    node_id: &str,
+    msg_ids: MsgIds,
+    tx: mpsc::Sender<Vec<u8>>,
+    sent: mpsc::Sender<Outbox>,
+  ) -> Outbound {
+    Outbound {
+      node_id: node_id.to_owned(),
+      msg_ids,
+      tx,
+      sent,
+    }
+  }
+
+  pub fn node_id(&self) -> &str {
+    &self.node_id
+  }
+
+  /// Allocate a new message id for a message sent by this node.
+  pub fn next_msg_id(&self) -> usize {
+    self.msg_ids.next()
+  }
+
+  /// Send `body` to `dest`, not expecting any reply. Fails if the node's output has been closed.
+  pub fn send<B: Serialize>(&self, dest: &str, body: B) -> Result<(), NodeError> {
+    self.push(MaelstromResponse {
+      src: self.node_id.clone(),
+      dest: dest.to_owned(),
+      body: ResponseBody::uni_dir(body),
+    })
+  }
+
+  /// Reply with `body` to the message `in_reply_to` that `dest` sent.
+  pub fn reply<B: Serialize>(
+    &self,
+    dest: &str,
+    in_reply_to: Option<usize>,
+    body: B,
+  ) -> Result<(), NodeError> {
+    self.push(MaelstromResponse {
+      src: self.node_id.clone(),
+      dest: dest.to_owned(),
+      body: ResponseBody {
+        in_reply_to,
+        msg_id: Some(self.msg_ids.next()),
+        response_type: body,
+      },
+    })
+  }
+
+  fn push<B: Serialize>(&self, msg: MaelstromResponse<B>) -> Result<(), NodeError> {
+    let mut outbox = Outbox::default();
+    outbox.push(&msg)?;
+    self
+      .tx
+      .send(outbox.as_bytes().to_vec())
+      .map_err(|_| NodeError::crash("The node's output has been closed"))?;
+    // the runtime is gone once the node has shut down, and there's nothing left to account for
+    let _ = self.sent.send(outbox);
+    Ok(())
+  }
+}
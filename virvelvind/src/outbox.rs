@@ -0,0 +1,176 @@
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{req::Header, res::MaelstromResponse, NetworkEntityId, NodeError};
+
+/// The messages a node has produced while handling an event. They're serialized and line framed
+/// as they're pushed, and written out by the runtime once the handler returns.
+#[derive(Default)]
+pub struct Outbox {
+  buf: Vec<u8>,
+  queued: Vec<Queued>,
+}
+
+/// What's known about a queued message; taken from the message as it's pushed, except for the
+/// body's `type`, which is read back from the serialized body.
+struct Queued {
+  src: NetworkEntityId,
+  dest: NetworkEntityId,
+  kind: Option<String>,
+  msg_id: Option<usize>,
+  in_reply_to: Option<usize>,
+  /// Where the message's line starts and ends in the buffer, without the newline
+  start: usize,
+  end: usize,
+}
+
+impl Outbox {
+  pub fn push<T: Serialize>(&mut self, msg: &MaelstromResponse<T>) -> Result<(), NodeError> {
+    let start = self.buf.len();
+    if let Err(e) = serde_json::to_writer(&mut self.buf, msg) {
+      // don't leave half a message in the buffer
+      self.buf.truncate(start);
+      return Err(NodeError::crash(format!("Couldn't serialize message: {e}")));
+    }
+    let end = self.buf.len();
+    self.buf.push(b'\n');
+    self.queued.push(Queued {
+      src: msg.src.clone(),
+      dest: msg.dest.clone(),
+      kind: TypeOf::of(&self.buf[start..end]),
+      msg_id: msg.body.msg_id,
+      in_reply_to: msg.body.in_reply_to,
+      start,
+      end,
+    });
+    Ok(())
+  }
+
+  /// Number of messages waiting to be written
+  pub fn len(&self) -> usize {
+    self.queued.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.queued.is_empty()
+  }
+
+  /// The queued messages; each one's header, and the message serialized.
+  pub(crate) fn messages(&self) -> impl Iterator<Item = (Header<'_>, &str)> {
+    self.queued.iter().map(|queued| {
+      let header = Header {
+        src: &queued.src,
+        dest: &queued.dest,
+        kind: queued.kind.as_deref(),
+        msg_id: queued.msg_id,
+        in_reply_to: queued.in_reply_to,
+      };
+      // only ever holds what serde_json wrote, which is UTF-8
+      let line = std::str::from_utf8(&self.buf[queued.start..queued.end]).unwrap_or_default();
+      (header, line)
+    })
+  }
+
+  /// The queued messages as they're written; one line each.
+  pub(crate) fn as_bytes(&self) -> &[u8] {
+    &self.buf
+  }
+
+  /// Empty the outbox, handing over the queued messages as they're written; one line each.
+  pub(crate) fn take(&mut self) -> Vec<u8> {
+    self.queued.clear();
+    std::mem::take(&mut self.buf)
+  }
+}
+
+/// The `type` of a serialized message's body; the rest of the message is skipped over.
+#[derive(Deserialize)]
+struct TypeOf<'a> {
+  #[serde(borrow)]
+  body: BodyType<'a>,
+}
+
+#[derive(Deserialize)]
+struct BodyType<'a> {
+  #[serde(rename = "type", borrow, default)]
+  kind: Option<Cow<'a, str>>,
+}
+
+impl TypeOf<'_> {
+  /// Bodies that aren't objects, or don't have a string `type`, have none.
+  fn of(line: &[u8]) -> Option<String> {
+    let type_of: TypeOf = serde_json::from_slice(line).ok()?;
+    type_of.body.kind.map(Cow::into_owned)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serde::Serialize;
+  use serde_json::json;
+
+  use super::*;
+  use crate::res::ResponseBody;
+
+  #[derive(Serialize)]
+  #[serde(tag = "type", rename_all = "snake_case")]
+  enum Kv {
+    Read { key: u64 },
+    WriteOk,
+  }
+
+  fn push<T: Serialize>(outbox: &mut Outbox, msg_id: usize, body: T) {
+    let msg = MaelstromResponse {
+      src: "n1".to_string(),
+      dest: "c1".to_string(),
+      body: ResponseBody {
+        in_reply_to: Some(msg_id - 1),
+        msg_id: Some(msg_id),
+        response_type: body,
+      },
+    };
+    outbox.push(&msg).unwrap();
+  }
+
+  #[test]
+  fn headers_are_taken_from_the_pushed_messages() {
+    let mut outbox = Outbox::default();
+    push(&mut outbox, 2, Kv::Read { key: 1 });
+    push(&mut outbox, 3, Kv::WriteOk);
+    push(&mut outbox, 4, json!({ "key": 1, "type": "cas" }));
+    push(&mut outbox, 5, NodeError::timeout("too slow"));
+    push(&mut outbox, 6, json!({ "key": 1 }));
+
+    let kinds: Vec<_> = outbox.messages().map(|(header, _)| header.kind).collect();
+    assert_eq!(
+      kinds,
+      [
+        Some("read"),
+        Some("write_ok"),
+        Some("cas"),
+        Some("error"),
+        None
+      ]
+    );
+    for (i, (header, line)) in outbox.messages().enumerate() {
+      let envelope = crate::req::parse_envelope(line).unwrap();
+      assert_eq!((header.src, header.dest), ("n1", "c1"));
+      assert_eq!(header.msg_id, Some(i + 2));
+      assert_eq!(header.in_reply_to, Some(i + 1));
+      assert_eq!(header.kind, envelope.body.kind.as_deref());
+    }
+  }
+
+  #[test]
+  fn taken_messages_leave_the_outbox() {
+    let mut outbox = Outbox::default();
+    push(&mut outbox, 2, Kv::WriteOk);
+    push(&mut outbox, 3, Kv::WriteOk);
+    assert_eq!(outbox.len(), 2);
+    let output = outbox.take();
+    assert_eq!(String::from_utf8(output).unwrap().lines().count(), 2);
+    assert!(outbox.is_empty());
+    assert_eq!(outbox.messages().count(), 0);
+  }
+}
@@ -0,0 +1,46 @@
+/// Small, seedable pseudo random number generator (SplitMix64). Not suitable for anything that
+/// needs real randomness, but it's deterministic for a given seed, which is what we want for
+/// jittering and simulations that must be replayable.
+#[derive(Debug, Clone)]
+pub struct Rng {
+  state: u64,
+}
+
+impl Rng {
+  pub fn new(seed: u64) -> Rng {
+    Rng { state: seed }
+  }
+
+  /// Seeded from the current time; for when replayability doesn't matter.
+  pub fn from_time() -> Rng {
+    let nanos = std::time::SystemTime::now()
+      .duration_since(std::time::UNIX_EPOCH)
+      .map(|d| d.as_nanos() as u64)
+      .unwrap_or_default();
+    Rng::new(nanos)
+  }
+
+  pub fn next_u64(&mut self) -> u64 {
+    self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+    let mut z = self.state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+  }
+
+  /// Uniformly distributed in `[0, 1)`
+  pub fn next_f64(&mut self) -> f64 {
+    (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+  }
+
+  /// Uniformly distributed in `[0, bound)`. `bound` must not be 0.
+  pub fn below(&mut self, bound: u64) -> u64 {
+    assert!(bound != 0, "bound must be larger than 0");
+    self.next_u64() % bound
+  }
+
+  /// `true` with probability `p`
+  pub fn chance(&mut self, p: f64) -> bool {
+    self.next_f64() < p
+  }
+}
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::{req::MaelstromRequest, Context, NodeError};
+
+type Handler<N> =
+  Box<dyn Fn(&mut N, MaelstromRequest<Value>, &mut Context<N>) -> Result<(), Unhandled>>;
+
+/// Why a message dispatched by a router wasn't handled.
+pub(crate) enum Unhandled {
+  /// The router turned the message away without calling a handler, for `reason`; how the
+  /// `rejected_messages` metric counts it.
+  Rejected(&'static str, NodeError),
+  /// The handler returned an error
+  Failed(NodeError),
+}
+
+impl From<Unhandled> for NodeError {
+  fn from(unhandled: Unhandled) -> Self {
+    match unhandled {
+      Unhandled::Rejected(_, err) | Unhandled::Failed(err) => err,
+    }
+  }
+}
+
+/// Behaviour that can be mixed into any node that has somewhere to keep its state, f.ex. gossip or
+/// a client for one of Maelstrom's services. See [`Router::mount`].
+pub trait Module<N>: 'static {
+  /// Register the handlers of the module. `state` finds the module's state in the node.
+  fn register(router: &mut Router<N>, state: fn(&mut N) -> &mut Self);
+}
+
+/// Dispatches messages to a handler per message `type`. Nodes using a router take their messages
+/// as [`serde_json::Value`]s, i.e. implement `Node<Value>`, and each handler deserializes the
+/// message as the type it wants. A message of a type nobody handles is answered with a
+/// `not-supported` error.
+///
+/// The runtime dispatches messages with a router handed to [`start_routed`](crate::start_routed).
+/// Nodes can also dispatch themselves, from `Node::handle`, but handlers get the node mutably, so
+/// the router can't be borrowed from the node while dispatching; keep it in an `Rc` and clone
+/// that.
+pub struct Router<N> {
+  handlers: HashMap<String, Handler<N>>,
+}
+
+impl<N> Default for Router<N> {
+  fn default() -> Self {
+    Router {
+      handlers: HashMap::new(),
+    }
+  }
+}
+
+impl<N: 'static> Router<N> {
+  pub fn new() -> Router<N> {
+    Router::default()
+  }
+
+  /// Handle messages of type `type_name` with `handler`. The message is deserialized as `Req`,
+  /// with its `type` included, so both an internally tagged enum and a struct work; a message
+  /// that isn't a valid `Req` is answered with a `malformed-request` error.
+  ///
+  /// Panics if `type_name` already has a handler; two modules that handle the same messages can't
+  /// both be mounted.
+  pub fn on<Req, F>(&mut self, type_name: &str, handler: F) -> &mut Self
+  where
+    Req: DeserializeOwned,
+    F: Fn(&mut N, MaelstromRequest<Req>, &mut Context<N>) -> Result<(), NodeError> + 'static,
+  {
+    let erased: Handler<N> = Box::new(move |node, msg, ctx| {
+      let data = serde_json::from_value(msg.body.data).map_err(|e| {
+        Unhandled::Rejected("malformed", NodeError::malformed_request(e.to_string()))
+      })?;
+      let msg = MaelstromRequest {
+        src: msg.src,
+        dest: msg.dest,
+        body: crate::req::RequestBody {
+          data,
+          msg_id: msg.body.msg_id,
+          in_reply_to: msg.body.in_reply_to,
+        },
+      };
+      handler(node, msg, ctx).map_err(Unhandled::Failed)
+    });
+    if self.handlers.insert(type_name.to_owned(), erased).is_some() {
+      panic!("Messages of type {type_name} already have a handler");
+    }
+    self
+  }
+
+  /// Register the handlers of module `M`, whose state is found with `state`.
+  pub fn mount<M: Module<N>>(&mut self, state: fn(&mut N) -> &mut M) -> &mut Self {
+    M::register(self, state);
+    self
+  }
+
+  /// Add all the handlers of `other`. Panics like [`Router::on`] if they overlap.
+  pub fn merge(&mut self, other: Router<N>) -> &mut Self {
+    for (type_name, handler) in other.handlers {
+      if self.handlers.insert(type_name.clone(), handler).is_some() {
+        panic!("Messages of type {type_name} already have a handler");
+      }
+    }
+    self
+  }
+}
+
+impl<N> Router<N> {
+  pub fn handles(&self, type_name: &str) -> bool {
+    self.handlers.contains_key(type_name)
+  }
+
+  /// The message types there are handlers for
+  pub fn types(&self) -> impl Iterator<Item = &str> {
+    self.handlers.keys().map(String::as_str)
+  }
+
+  /// Hand `msg` to the handler for its type.
+  pub fn dispatch(
+    &self,
+    node: &mut N,
+    msg: MaelstromRequest<Value>,
+    ctx: &mut Context<N>,
+  ) -> Result<(), NodeError> {
+    self.handle(node, msg, ctx).map_err(NodeError::from)
+  }
+
+  /// Like [`Router::dispatch`], but tells the messages the router rejected from those its
+  /// handler failed on.
+  pub(crate) fn handle(
+    &self,
+    node: &mut N,
+    msg: MaelstromRequest<Value>,
+    ctx: &mut Context<N>,
+  ) -> Result<(), Unhandled> {
+    let type_name = msg
+      .body
+      .data
+      .get("type")
+      .and_then(Value::as_str)
+      .unwrap_or_default();
+    match self.handlers.get(type_name) {
+      Some(handler) => handler(node, msg, ctx),
+      None => Err(Unhandled::Rejected(
+        "not_supported",
+        NodeError::not_supported(format!("{type_name} is not supported")),
+      )),
+    }
+  }
+}
@@ -0,0 +1,488 @@
+use std::{
+  cmp::Reverse,
+  collections::{BinaryHeap, HashMap},
+  sync::mpsc,
+  time::{Duration, Instant, SystemTime},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+  log::{Level, Logger},
+  outbound::MsgIds,
+  req::{self, MaelstromRequest},
+  res::{MaelstromResponse, ResponseBody},
+  rng::Rng,
+  Context, NetworkEntityId, NodeError, Outbox,
+};
+
+/// What a reply callback gets handed; the reply or the error the request was answered with.
+pub type RpcResult<Resp> = Result<MaelstromRequest<Resp>, NodeError>;
+
+/// Type erased reply handler. It's handed the reply's envelope, so that the body can be
+/// deserialized into whatever type the caller expected, which isn't necessarily the node's own
+/// service type (replies from f.ex. `lin-kv` are not part of the node's protocol definition).
+/// If no reply arrived in time, it's handed the timeout error instead.
+pub(crate) type ReplyCallback<N> =
+  Box<dyn FnOnce(&mut N, Result<&req::Envelope, NodeError>, &mut Context<N>)>;
+
+/// How long to wait before resending a request that hasn't been answered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backoff {
+  /// Wait the same amount of time between every attempt
+  Fixed(Duration),
+  /// Double the wait for every attempt, starting at `initial`, never waiting longer than `max`
+  Exponential { initial: Duration, max: Duration },
+  /// Like `Exponential`, but wait somewhere between half and all of that time, so that nodes
+  /// retrying at the same time spread out.
+  Jittered { initial: Duration, max: Duration },
+}
+
+impl Backoff {
+  /// The wait before resend number `retry` (starting at 1)
+  fn delay(&self, retry: u32, rng: &mut Rng) -> Duration {
+    let exponential = |initial: Duration, max: Duration| {
+      initial
+        .checked_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+        .map_or(max, |delay| delay.min(max))
+    };
+    match *self {
+      Backoff::Fixed(delay) => delay,
+      Backoff::Exponential { initial, max } => exponential(initial, max),
+      Backoff::Jittered { initial, max } => {
+        let delay = exponential(initial, max);
+        delay / 2 + delay.mul_f64(rng.next_f64() / 2.0)
+      }
+    }
+  }
+}
+
+/// Resend requests that haven't been answered. Every resend gets a fresh `msg_id`, but a (late)
+/// reply to any of the attempts resolves the request.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+  pub backoff: Backoff,
+  /// Total number of times the request is sent, including the first one
+  pub max_attempts: u32,
+}
+
+impl RetryPolicy {
+  pub fn fixed(interval: Duration) -> RetryPolicy {
+    RetryPolicy {
+      backoff: Backoff::Fixed(interval),
+      max_attempts: u32::MAX,
+    }
+  }
+
+  pub fn exponential(initial: Duration, max: Duration) -> RetryPolicy {
+    RetryPolicy {
+      backoff: Backoff::Exponential { initial, max },
+      max_attempts: u32::MAX,
+    }
+  }
+
+  pub fn jittered(initial: Duration, max: Duration) -> RetryPolicy {
+    RetryPolicy {
+      backoff: Backoff::Jittered { initial, max },
+      max_attempts: u32::MAX,
+    }
+  }
+
+  pub fn max_attempts(self, max_attempts: u32) -> RetryPolicy {
+    RetryPolicy {
+      max_attempts,
+      ..self
+    }
+  }
+}
+
+/// Options for a single request. The default waits forever for a reply and never resends. A
+/// request that is resent but has no `timeout` gives up once its last attempt goes unanswered
+/// for as long as the backoff would have waited before another one.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CallOptions {
+  /// If no reply has arrived by this long after the request was first sent, the request is
+  /// resolved with a `timeout` error.
+  pub timeout: Option<Duration>,
+  pub retry: Option<RetryPolicy>,
+}
+
+impl CallOptions {
+  pub fn timeout(timeout: Duration) -> CallOptions {
+    CallOptions {
+      timeout: Some(timeout),
+      retry: None,
+    }
+  }
+
+  pub fn retry(self, retry: RetryPolicy) -> CallOptions {
+    CallOptions {
+      retry: Some(retry),
+      ..self
+    }
+  }
+}
+
+struct Resend {
+  policy: RetryPolicy,
+  attempts: u32,
+  at: Instant,
+  // the request as sent, so that it can be sent again with a new msg_id
+  message: MaelstromResponse<serde_json::Value>,
+  // msg_id's of all attempts made so far
+  msg_ids: Vec<usize>,
+}
+
+struct Pending<N> {
+  on_reply: ReplyCallback<N>,
+  deadline: Option<Instant>,
+  resend: Option<Resend>,
+}
+
+impl<N> Pending<N> {
+  /// When the request gives up on a reply: at its deadline, or without one, once the last
+  /// attempt has gone unanswered for as long as the wait before another resend would have been.
+  fn expiry(&self) -> Option<Instant> {
+    self.deadline.or_else(|| {
+      self
+        .resend
+        .as_ref()
+        .filter(|resend| resend.attempts >= resend.policy.max_attempts)
+        .map(|resend| resend.at)
+    })
+  }
+
+  fn next_check(&self) -> Option<Instant> {
+    let resend = self
+      .resend
+      .as_ref()
+      .filter(|resend| resend.attempts < resend.policy.max_attempts)
+      .map(|resend| resend.at);
+    match (self.expiry(), resend) {
+      (Some(expiry), Some(resend)) => Some(expiry.min(resend)),
+      (expiry, resend) => expiry.or(resend),
+    }
+  }
+}
+
+/// Keeps track of the requests this node has sent that expect a reply. Replies are matched on
+/// `in_reply_to` against the `msg_id` the request was sent with, by the runtime, before the
+/// message ever reaches the node's `handle`. Requests can be given a deadline and a retry
+/// policy, see [`CallOptions`]. Nodes make requests through their [`Context`].
+///
+/// Also hands out the node's message ids, from the sequence it shares with every [`Outbound`]
+/// handle, so that every message sent, request or not, gets a unique `msg_id`.
+///
+/// [`Outbound`]: crate::Outbound
+pub(crate) struct Rpc<N> {
+  msg_ids: MsgIds,
+  // keyed by the msg_id of the first attempt
+  pending: HashMap<usize, Pending<N>>,
+  // msg_id of a resent attempt -> msg_id of the first attempt
+  resent: HashMap<usize, usize>,
+  checks: BinaryHeap<Reverse<(Instant, usize)>>,
+  now: Instant,
+  rng: Rng,
+}
+
+/// One-shot handle to a reply of a request sent with [`Context::call_handle`]. The reply is
+/// delivered by the runtime's event loop, so never block on it from within `handle` as that
+/// would dead lock the node.
+pub struct ReplyHandle<Resp> {
+  rx: mpsc::Receiver<RpcResult<Resp>>,
+}
+
+impl<Resp> ReplyHandle<Resp> {
+  pub(crate) fn channel() -> (mpsc::Sender<RpcResult<Resp>>, ReplyHandle<Resp>) {
+    let (tx, rx) = mpsc::channel();
+    (tx, ReplyHandle { rx })
+  }
+
+  /// Returns the reply if it has arrived.
+  pub fn try_recv(&self) -> Option<RpcResult<Resp>> {
+    self.rx.try_recv().ok()
+  }
+
+  /// Block until the reply arrives. Returns `Err` if the request can never be answered (the
+  /// runtime has shut down)
+  pub fn recv(&self) -> Result<RpcResult<Resp>, mpsc::RecvError> {
+    self.rx.recv()
+  }
+
+  pub fn recv_timeout(&self, timeout: Duration) -> Result<RpcResult<Resp>, mpsc::RecvTimeoutError> {
+    self.rx.recv_timeout(timeout)
+  }
+}
+
+impl<N> Rpc<N> {
+  pub(crate) fn new(msg_ids: MsgIds, now: Instant, rng: Rng) -> Rpc<N> {
+    Rpc {
+      msg_ids,
+      pending: HashMap::new(),
+      resent: HashMap::new(),
+      checks: BinaryHeap::new(),
+      now,
+      rng,
+    }
+  }
+
+  /// Allocate a new message id for a message sent by this node.
+  pub(crate) fn next_msg_id(&mut self) -> usize {
+    self.msg_ids.next()
+  }
+
+  /// Forget every pending request; their callbacks are dropped without being called.
+  pub(crate) fn clear(&mut self) {
+    self.pending.clear();
+    self.resent.clear();
+    self.checks.clear();
+  }
+
+  /// Number of requests still waiting on a reply.
+  pub(crate) fn pending(&self) -> usize {
+    self.pending.len()
+  }
+
+  /// Queue `request` in `outbox` and register `on_reply` to be run with the reply, or a
+  /// `timeout` error if the deadline in `options` passes without a reply.
+  pub(crate) fn call_with<Req, Resp, F>(
+    &mut self,
+    outbox: &mut Outbox,
+    src: NetworkEntityId,
+    dest: NetworkEntityId,
+    request: Req,
+    options: CallOptions,
+    on_reply: F,
+  ) -> Result<usize, NodeError>
+  where
+    Req: Serialize,
+    Resp: DeserializeOwned,
+    F: FnOnce(&mut N, RpcResult<Resp>, &mut Context<N>) + 'static,
+  {
+    let msg_id = self.next_msg_id();
+    let message = MaelstromResponse {
+      src,
+      dest,
+      body: ResponseBody {
+        in_reply_to: None,
+        msg_id: Some(msg_id),
+        response_type: request,
+      },
+    };
+    let resend = match options.retry {
+      Some(policy) => Some(Resend {
+        policy,
+        attempts: 1,
+        at: self.now + policy.backoff.delay(1, &mut self.rng),
+        message: MaelstromResponse {
+          src: message.src.clone(),
+          dest: message.dest.clone(),
+          body: ResponseBody {
+            in_reply_to: None,
+            msg_id: Some(msg_id),
+            response_type: serde_json::to_value(&message.body.response_type)
+              .map_err(|e| NodeError::crash(format!("Couldn't serialize message: {e}")))?,
+          },
+        },
+        msg_ids: vec![msg_id],
+      }),
+      None => None,
+    };
+    outbox.push(&message)?;
+
+    let pending = Pending {
+      on_reply: Box::new(move |node, reply, ctx| on_reply(node, reply.and_then(parse_reply), ctx)),
+      deadline: options.timeout.map(|timeout| self.now + timeout),
+      resend,
+    };
+    if let Some(check) = pending.next_check() {
+      self.checks.push(Reverse((check, msg_id)));
+    }
+    self.pending.insert(msg_id, pending);
+    Ok(msg_id)
+  }
+
+  /// Remove the reply handler waiting on `in_reply_to`, if there is one.
+  pub(crate) fn take(&mut self, in_reply_to: usize) -> Option<ReplyCallback<N>> {
+    let key = self
+      .resent
+      .get(&in_reply_to)
+      .copied()
+      .unwrap_or(in_reply_to);
+    let pending = self.pending.remove(&key)?;
+    for msg_id in pending
+      .resend
+      .iter()
+      .flat_map(|resend| resend.msg_ids.iter())
+    {
+      self.resent.remove(msg_id);
+    }
+    Some(pending.on_reply)
+  }
+
+  /// The earliest time a pending request needs to be resent or timed out.
+  pub(crate) fn next_deadline(&mut self) -> Option<Instant> {
+    self.discard_stale();
+    self.checks.peek().map(|Reverse((at, _))| *at)
+  }
+
+  /// Called by the runtime before it starts handling an event.
+  pub(crate) fn tick(&mut self, now: Instant) {
+    self.now = now;
+  }
+
+  /// Resend the requests that are due for it and return the reply handler of the next request
+  /// that has timed out by now, if any. Resends that fail are logged to `log`, at `wall_time`.
+  pub(crate) fn pop_expired(
+    &mut self,
+    outbox: &mut Outbox,
+    log: &Logger,
+    wall_time: SystemTime,
+  ) -> Option<ReplyCallback<N>> {
+    loop {
+      self.discard_stale();
+      let Reverse((at, _)) = self.checks.peek()?;
+      if *at > self.now {
+        return None;
+      }
+      let Reverse((_, key)) = self.checks.pop()?;
+      let pending = self.pending.get_mut(&key)?;
+      if pending.expiry().is_some_and(|expiry| expiry <= self.now) {
+        return self.take(key);
+      }
+
+      let resend = pending
+        .resend
+        .as_mut()
+        .expect("a check is only scheduled for deadlines and resends");
+      let msg_id = self.msg_ids.next();
+      resend.attempts += 1;
+      resend.at = self.now + resend.policy.backoff.delay(resend.attempts, &mut self.rng);
+      resend.msg_ids.push(msg_id);
+      resend.message.body.msg_id = Some(msg_id);
+      if let Err(e) = outbox.push(&resend.message) {
+        log
+          .record(Level::Error, "failed to resend request", wall_time)
+          .field("dest", &resend.message.dest)
+          .field("msg_id", key)
+          .field("error", e.to_string())
+          .emit();
+      }
+      self.resent.insert(msg_id, key);
+      if let Some(check) = pending.next_check() {
+        self.checks.push(Reverse((check, key)));
+      }
+    }
+  }
+
+  // Drop checks for requests that have been answered, or whose check has been moved.
+  fn discard_stale(&mut self) {
+    while let Some(Reverse((at, key))) = self.checks.peek() {
+      match self
+        .pending
+        .get(key)
+        .and_then(|pending| pending.next_check())
+      {
+        Some(check) if check == *at => return,
+        _ => {
+          self.checks.pop();
+        }
+      }
+    }
+  }
+}
+
+fn parse_reply<Resp: DeserializeOwned>(envelope: &req::Envelope) -> RpcResult<Resp> {
+  if envelope.is_error() {
+    return match envelope.parse_body::<NodeError>() {
+      Ok(err) => Err(err.data),
+      Err(e) => Err(NodeError::malformed_request(format!(
+        "Malformed error reply: {e}"
+      ))),
+    };
+  }
+  envelope
+    .to_request::<Resp>()
+    .map_err(|e| NodeError::malformed_request(format!("Unexpected reply: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::{json, Value};
+
+  use super::*;
+
+  fn expire(rpc: &mut Rpc<()>, outbox: &mut Outbox) -> bool {
+    let log = Logger::new("n1", None);
+    rpc
+      .pop_expired(outbox, &log, SystemTime::UNIX_EPOCH)
+      .is_some()
+  }
+
+  fn call(rpc: &mut Rpc<()>, outbox: &mut Outbox, options: CallOptions) -> usize {
+    rpc
+      .call_with(
+        outbox,
+        "n1".into(),
+        "n2".into(),
+        json!({ "type": "ping" }),
+        options,
+        |_: &mut (), _: RpcResult<Value>, _| {},
+      )
+      .unwrap()
+  }
+
+  #[test]
+  fn resends_until_the_deadline() {
+    let start = Instant::now();
+    let mut rpc = Rpc::new(MsgIds::starting_at(1), start, Rng::new(1));
+    let mut outbox = Outbox::default();
+    let retry = RetryPolicy::fixed(Duration::from_millis(10));
+    call(
+      &mut rpc,
+      &mut outbox,
+      CallOptions::timeout(Duration::from_millis(25)).retry(retry),
+    );
+    for ms in [10, 20] {
+      rpc.tick(start + Duration::from_millis(ms));
+      assert!(!expire(&mut rpc, &mut outbox));
+    }
+    assert_eq!(outbox.len(), 3);
+    rpc.tick(start + Duration::from_millis(25));
+    assert!(expire(&mut rpc, &mut outbox));
+    assert_eq!(rpc.pending(), 0);
+  }
+
+  #[test]
+  fn retries_without_a_timeout_give_up_after_the_last_attempt() {
+    let start = Instant::now();
+    let mut rpc = Rpc::new(MsgIds::starting_at(1), start, Rng::new(1));
+    let mut outbox = Outbox::default();
+    let retry = RetryPolicy::fixed(Duration::from_millis(10)).max_attempts(3);
+    call(&mut rpc, &mut outbox, CallOptions::default().retry(retry));
+    for ms in [10, 20, 29] {
+      rpc.tick(start + Duration::from_millis(ms));
+      assert!(!expire(&mut rpc, &mut outbox));
+    }
+    assert_eq!(outbox.len(), 3);
+    assert_eq!(rpc.next_deadline(), Some(start + Duration::from_millis(30)));
+    rpc.tick(start + Duration::from_millis(30));
+    assert!(expire(&mut rpc, &mut outbox));
+    assert_eq!(rpc.pending(), 0);
+    assert_eq!(rpc.next_deadline(), None);
+  }
+
+  #[test]
+  fn a_late_reply_to_an_earlier_attempt_resolves_the_request() {
+    let start = Instant::now();
+    let mut rpc = Rpc::new(MsgIds::starting_at(1), start, Rng::new(1));
+    let mut outbox = Outbox::default();
+    let retry = RetryPolicy::fixed(Duration::from_millis(10)).max_attempts(2);
+    let first = call(&mut rpc, &mut outbox, CallOptions::default().retry(retry));
+    rpc.tick(start + Duration::from_millis(10));
+    assert!(!expire(&mut rpc, &mut outbox));
+    assert!(rpc.take(first).is_some());
+    assert_eq!(rpc.pending(), 0);
+    assert!(rpc.take(first + 1).is_none());
+  }
+}
@@ -0,0 +1,448 @@
+use std::{io::Write, marker::PhantomData, rc::Rc, sync::mpsc, time::Instant};
+
+use crate::{
+  clock::Clock,
+  cluster::Cluster,
+  history::{History, Recorder},
+  log::{Level, Logger},
+  metrics::{Metrics, METRICS_REQUEST},
+  outbound::{MsgIds, Outbound},
+  req,
+  res::{MaelstromResponse, ResponseBody},
+  rng::Rng,
+  router::{Router, Unhandled},
+  rpc::Rpc,
+  Context, Event, NetworkEntityId, Node, NodeError, Outbox, Service, Timers,
+};
+
+/// The part of running a node that doesn't care where messages come from or go to: matching
+/// replies to pending requests, dispatching events to the node, turning handler errors into
+/// `error` replies, timers and request deadlines. Whatever drives it feeds it input lines and
+/// events, tells it what time it is, and writes out the outbox.
+pub(crate) struct Runtime<N, ServiceType> {
+  node: N,
+  node_id: NetworkEntityId,
+  cluster: Rc<Cluster>,
+  rpc: Rpc<N>,
+  timers: Timers,
+  outbox: Outbox,
+  outbound: Outbound,
+  /// What was sent through the node's `Outbound` handles, yet to be accounted for
+  outbound_sent: mpsc::Receiver<Outbox>,
+  clock: Rc<dyn Clock>,
+  log: Logger,
+  metrics: Metrics,
+  #[cfg(feature = "async")]
+  tasks: crate::task::Tasks,
+  /// Records client operations, if asked to, with times relative to the instant
+  recorder: Option<(Instant, Recorder)>,
+  /// Handles every message the node is sent instead of the node, if set
+  router: Option<Rc<Router<N>>>,
+  _service: PhantomData<fn() -> ServiceType>,
+}
+
+impl<N, ServiceType> Runtime<N, ServiceType>
+where
+  N: Node<ServiceType>,
+  ServiceType: Service,
+{
+  /// `node` must have been handed `init` already. `rng` is used for jittering retries. Messages
+  /// sent through the node's [`Outbound`] handles are handed to `outbound_tx`, and accounted for
+  /// with the node's output.
+  pub(crate) fn new(
+    mut node: N,
+    init: &req::Initialize,
+    clock: Box<dyn Clock>,
+    rng: Rng,
+    outbound_tx: mpsc::Sender<Vec<u8>>,
+  ) -> Runtime<N, ServiceType> {
+    let clock: Rc<dyn Clock> = Rc::from(clock);
+    let now = clock.now();
+    let mut timers = Timers::new(now);
+    node.setup_timers(&mut timers);
+    let log = Logger::from_env(&init.node_id);
+    log
+      .record(Level::Info, "initialized", clock.system_time())
+      .field("node_ids", &init.node_ids)
+      .emit();
+    // msg_id 1 is used by the init_ok reply
+    let msg_ids = MsgIds::starting_at(2);
+    let cluster = Rc::new(Cluster::new(init));
+    let (sent_tx, outbound_sent) = mpsc::channel();
+    Runtime {
+      node_id: init.node_id.clone(),
+      outbound: Outbound::new(&init.node_id, msg_ids.clone(), outbound_tx, sent_tx),
+      outbound_sent,
+      #[cfg(feature = "async")]
+      tasks: crate::task::Tasks::new(cluster.clone(), log.clone(), clock.clone()),
+      cluster,
+      node,
+      rpc: Rpc::new(msg_ids, now, rng),
+      timers,
+      outbox: Outbox::default(),
+      clock,
+      log,
+      metrics: Metrics::new(),
+      recorder: None,
+      router: None,
+      _service: PhantomData,
+    }
+  }
+
+  pub(crate) fn node(&self) -> &N {
+    &self.node
+  }
+
+  pub(crate) fn node_mut(&mut self) -> &mut N {
+    &mut self.node
+  }
+
+  pub(crate) fn outbound(&self) -> Outbound {
+    self.outbound.clone()
+  }
+
+  /// Record the operations clients make from now on, timed from `start`, and write each to `sink`
+  /// as it's recorded.
+  pub(crate) fn record_history(&mut self, start: Instant, sink: Option<Box<dyn Write + Send>>) {
+    self.recorder = Some((start, Recorder::new(sink)));
+  }
+
+  /// Dispatch messages to the node with `router`, rather than handing them to `Node::handle`.
+  pub(crate) fn route(&mut self, router: Rc<Router<N>>) {
+    self.router = Some(router);
+  }
+
+  pub(crate) fn log(&self) -> &Logger {
+    &self.log
+  }
+
+  pub(crate) fn metrics(&self) -> &Metrics {
+    &self.metrics
+  }
+
+  pub(crate) fn metrics_mut(&mut self) -> &mut Metrics {
+    &mut self.metrics
+  }
+
+  pub(crate) fn history(&self) -> Option<&History> {
+    self
+      .recorder
+      .as_ref()
+      .map(|(_, recorder)| recorder.history())
+  }
+
+  pub(crate) fn tick(&mut self, now: Instant) {
+    self.timers.tick(now);
+    self.rpc.tick(now);
+  }
+
+  /// The earliest time something needs to happen; a timer firing, a request being resent or
+  /// timing out.
+  pub(crate) fn next_deadline(&mut self) -> Option<Instant> {
+    match (self.timers.next_deadline(), self.rpc.next_deadline()) {
+      (Some(timer), Some(rpc)) => Some(timer.min(rpc)),
+      (timer, rpc) => timer.or(rpc),
+    }
+  }
+
+  /// Resend or time out pending requests and fire timers that are due.
+  pub(crate) fn fire_expired(&mut self) {
+    let wall_time = self.clock.system_time();
+    while let Some(on_reply) = self.rpc.pop_expired(&mut self.outbox, &self.log, wall_time) {
+      let (node, mut ctx) = self.split();
+      on_reply(
+        node,
+        Err(NodeError::timeout("No reply before the deadline")),
+        &mut ctx,
+      );
+    }
+    while let Some(id) = self.timers.pop_expired() {
+      #[cfg(feature = "async")]
+      if self.tasks.timer_fired(&id) {
+        continue;
+      }
+      self.handle_event(Event::Timer(id));
+    }
+    self.run_tasks();
+  }
+
+  /// Handle a line of input; a reply to a pending request or a message for the node.
+  pub(crate) fn handle_line(&mut self, line: &str) {
+    let started = Instant::now();
+    let envelope = match req::parse_envelope(line) {
+      Ok(envelope) => envelope,
+      Err(e) => {
+        self.metrics.increment(
+          "rejected_messages",
+          &[("type", ""), ("reason", "invalid_envelope")],
+          1,
+        );
+        self
+          .log
+          .record(
+            Level::Warn,
+            "dropping message without a valid envelope",
+            self.clock.system_time(),
+          )
+          .field("error", e.to_string())
+          .field("line", line.trim())
+          .emit();
+        return;
+      }
+    };
+    self.log.enter(&envelope);
+    self.dispatch_line(line, &envelope);
+    self.log.exit();
+    let latency = started.elapsed();
+    self.metrics.received(envelope.header(), latency);
+    self.log.message(
+      "in",
+      envelope.header(),
+      Some(latency),
+      self.clock.system_time(),
+    );
+  }
+
+  fn dispatch_line(&mut self, line: &str, envelope: &req::Envelope) {
+    if let Some(on_reply) = envelope.body.in_reply_to.and_then(|id| self.rpc.take(id)) {
+      let (node, mut ctx) = self.split();
+      on_reply(node, Ok(envelope), &mut ctx);
+      self.run_tasks();
+      return;
+    }
+    if envelope.is_error() {
+      self
+        .log
+        .record(
+          Level::Warn,
+          "dropping error that isn't a reply to any pending request",
+          self.clock.system_time(),
+        )
+        .field("line", line.trim())
+        .emit();
+      return;
+    }
+    if envelope.body.kind.as_deref() == Some(METRICS_REQUEST) {
+      let metrics = serde_json::to_value(&self.metrics).unwrap_or_default();
+      let (_, mut ctx) = self.split();
+      let body = serde_json::json!({ "type": "metrics_ok", "metrics": metrics });
+      if let Err(e) = ctx.reply(&envelope.src, envelope.body.msg_id, body) {
+        ctx
+          .log(Level::Error, "failed to send metrics")
+          .field("error", e.to_string())
+          .emit();
+      }
+      return;
+    }
+    if let Some((start, recorder)) = &mut self.recorder {
+      let time = self.clock.now().saturating_duration_since(*start);
+      if let Err(e) = recorder.received(time, envelope) {
+        history_failed(&self.log, &*self.clock, e);
+      }
+    }
+    let handled = match self.router.clone() {
+      Some(router) => match envelope.to_request::<serde_json::Value>() {
+        Ok(request) => {
+          let (node, mut ctx) = self.split();
+          match router.handle(node, request, &mut ctx) {
+            Err(Unhandled::Rejected(reason, err)) => return self.reject(envelope, reason, err),
+            handled => handled.map_err(NodeError::from),
+          }
+        }
+        Err(e) => return self.reject_unparsed(envelope, e),
+      },
+      None => match envelope.to_request::<ServiceType>() {
+        Ok(request) => {
+          let (node, mut ctx) = self.split();
+          node.handle(Event::IOEvent(request), &mut ctx)
+        }
+        Err(e) => return self.reject_unparsed(envelope, e),
+      },
+    };
+    if let Err(err) = handled {
+      self.reply_with_error(envelope, err);
+    }
+    self.run_tasks();
+  }
+
+  /// Reject a message that couldn't be parsed as a `ServiceType`; with `not-supported` if its
+  /// `type` is unknown, or `malformed-request` if its body doesn't fit the type.
+  fn reject_unparsed(&mut self, envelope: &req::Envelope, e: serde_json::Error) {
+    let kind = envelope.body.kind.as_deref().unwrap_or("");
+    let known = ServiceType::type_names();
+    let unknown_type = !kind.is_empty() && !known.is_empty() && !known.contains(&kind);
+    if unknown_type {
+      let err = NodeError::not_supported(format!("Unknown message type {kind}"));
+      self.reject(envelope, "not_supported", err)
+    } else {
+      self.reject(
+        envelope,
+        "malformed",
+        NodeError::malformed_request(e.to_string()),
+      )
+    }
+  }
+
+  /// Count a message the node didn't understand under `reason`, and answer it with `err`.
+  /// Messages that don't expect a reply are logged and dropped.
+  fn reject(&mut self, envelope: &req::Envelope, reason: &str, err: NodeError) {
+    let kind = envelope.body.kind.as_deref().unwrap_or("");
+    self.metrics.increment(
+      "rejected_messages",
+      &[("type", kind), ("reason", reason)],
+      1,
+    );
+    if envelope.body.msg_id.is_some() {
+      return self.reply_with_error(envelope, err);
+    }
+    self
+      .log
+      .record(
+        Level::Warn,
+        "dropping message the node doesn't understand",
+        self.clock.system_time(),
+      )
+      .field("error", err.to_string())
+      .emit();
+  }
+
+  /// Hand a non-IO event to the node; there's nobody to reply to if handling it fails.
+  pub(crate) fn handle_event(&mut self, evt: Event<ServiceType>) {
+    let (node, mut ctx) = self.split();
+    if let Err(err) = node.handle(evt, &mut ctx) {
+      ctx
+        .log(Level::Error, "error handling event")
+        .field("error", err.to_string())
+        .emit();
+    }
+    self.run_tasks();
+  }
+
+  /// Give the node a last chance to send messages or dump its state, then cancel its timers and
+  /// forget its pending requests. Nothing the node sends is written until the next `flush`.
+  pub(crate) fn shutdown(&mut self) {
+    let (node, mut ctx) = self.split();
+    node.on_shutdown(&mut ctx);
+    self.run_tasks();
+    self.timers.clear();
+    self.rpc.clear();
+    self
+      .log
+      .record(Level::Info, "metrics", self.clock.system_time())
+      .field("metrics", &self.metrics)
+      .emit();
+  }
+
+  /// Poll the node's tasks and carry out what they ask for, until they're all waiting.
+  #[cfg(feature = "async")]
+  fn run_tasks(&mut self) {
+    loop {
+      let commands = self.tasks.poll();
+      if commands.is_empty() {
+        return;
+      }
+      let (_, mut ctx) = self.split();
+      for command in commands {
+        crate::task::execute(command, &mut ctx);
+      }
+    }
+  }
+
+  #[cfg(not(feature = "async"))]
+  fn run_tasks(&mut self) {}
+
+  /// Everything the node has sent so far, as lines of JSON, taken out of the outbox. What was
+  /// sent through its `Outbound` handles since is accounted for too, but was written already.
+  pub(crate) fn take_output(&mut self) -> Vec<u8> {
+    let mut outbox = std::mem::take(&mut self.outbox);
+    self.account(&outbox);
+    while let Ok(sent) = self.outbound_sent.try_recv() {
+      self.account(&sent);
+    }
+    self
+      .metrics
+      .set_gauge("pending_requests", &[], self.rpc.pending() as i64);
+    outbox.take()
+  }
+
+  /// Count, log and record the messages in `outbox` as sent.
+  fn account(&mut self, outbox: &Outbox) {
+    if let Some((start, recorder)) = &mut self.recorder {
+      let time = self.clock.now().saturating_duration_since(*start);
+      for (header, line) in outbox.messages() {
+        if let Err(e) = recorder.sent(time, header, line) {
+          history_failed(&self.log, &*self.clock, e);
+        }
+      }
+    }
+    let now = self.clock.system_time();
+    for (header, line) in outbox.messages() {
+      // + the newline
+      self.metrics.sent(header, line.len() + 1);
+      self.log.message("out", header, None, now);
+    }
+  }
+
+  /// Send `err` as a reply to the message `envelope` belongs to. Messages that don't expect a
+  /// reply (i.e. has no `msg_id`) just get the error logged.
+  fn reply_with_error(&mut self, envelope: &req::Envelope, err: NodeError) {
+    let Some(in_reply_to) = envelope.body.msg_id else {
+      self
+        .log
+        .record(
+          Level::Error,
+          "error handling message",
+          self.clock.system_time(),
+        )
+        .field("error", err.to_string())
+        .emit();
+      return;
+    };
+    let reply = MaelstromResponse {
+      src: envelope.dest.to_string(),
+      dest: envelope.src.to_string(),
+      body: ResponseBody {
+        in_reply_to: Some(in_reply_to),
+        msg_id: Some(self.rpc.next_msg_id()),
+        response_type: err,
+      },
+    };
+    if let Err(e) = self.outbox.push(&reply) {
+      self
+        .log
+        .record(
+          Level::Error,
+          "failed to send error reply",
+          self.clock.system_time(),
+        )
+        .field("error", e.to_string())
+        .emit();
+    }
+  }
+
+  fn split(&mut self) -> (&mut N, Context<'_, N>) {
+    (
+      &mut self.node,
+      Context {
+        node_id: &self.node_id,
+        cluster: &self.cluster,
+        outbox: &mut self.outbox,
+        outbound: &self.outbound,
+        rpc: &mut self.rpc,
+        timers: &mut self.timers,
+        clock: &*self.clock,
+        log: &self.log,
+        metrics: &mut self.metrics,
+        #[cfg(feature = "async")]
+        tasks: self.tasks.handle(),
+      },
+    )
+  }
+}
+
+fn history_failed(log: &Logger, clock: &dyn Clock, e: std::io::Error) {
+  log
+    .record(Level::Error, "failed to write history", clock.system_time())
+    .field("error", e.to_string())
+    .emit();
+}
@@ -0,0 +1,541 @@
+//! Run a cluster of nodes in one process, on virtual time, over a simulated network. Everything
+//! random about a run (message latency, retry jitter) is drawn from one seeded [`Rng`], so a run
+//! can be replayed exactly by running it again with the same seed; as long as the nodes
+//! themselves don't read the system clock or spawn threads. Side channel threads are not started
+//! by the simulation. Faults can be injected into the network by scheduling them with a
+//! [`Nemesis`].
+
+use std::{
+  cmp::Reverse,
+  collections::{BinaryHeap, HashMap, VecDeque},
+  rc::Rc,
+  sync::mpsc,
+  time::{Duration, Instant, SystemTime},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+  clock::{Clock, ManualClock},
+  history::History,
+  log::{Level, Record},
+  metrics::Metrics,
+  req::{self, Initialize, MaelstromRequest, RequestBody},
+  rng::Rng,
+  runtime::Runtime,
+  NetworkEntityId, Node, Router, Service,
+};
+
+pub mod nemesis;
+use nemesis::Faults;
+pub use nemesis::{Fault, Nemesis, NetworkStats, Partition};
+
+/// How long the simulated network takes to deliver a message.
+#[derive(Debug, Clone, Copy)]
+pub enum Latency {
+  Fixed(Duration),
+  /// Uniformly distributed in `[min, max]`
+  Uniform {
+    min: Duration,
+    max: Duration,
+  },
+  /// Exponentially distributed with the given mean, like Maelstrom's default latency, but never
+  /// more than `max`
+  Exponential {
+    mean: Duration,
+    max: Duration,
+  },
+}
+
+impl Default for Latency {
+  fn default() -> Self {
+    Latency::Fixed(Duration::ZERO)
+  }
+}
+
+impl Latency {
+  fn sample(&self, rng: &mut Rng) -> Duration {
+    match *self {
+      Latency::Fixed(latency) => latency,
+      Latency::Uniform { min, max } => {
+        if max <= min {
+          return min;
+        }
+        min + (max - min).mul_f64(rng.next_f64())
+      }
+      Latency::Exponential { mean, max } => {
+        // inverse transform sampling; 1 - u is in (0, 1] so the log is finite
+        let u = 1.0 - rng.next_f64();
+        mean.mul_f64(-u.ln()).min(max)
+      }
+    }
+  }
+}
+
+/// A message that was delivered to a client, i.e. to a destination that isn't a node in the
+/// simulation.
+#[derive(Debug, Clone)]
+pub struct Delivery {
+  /// Time since the start of the simulation
+  pub at: Duration,
+  pub src: NetworkEntityId,
+  pub dest: NetworkEntityId,
+  pub in_reply_to: Option<usize>,
+  pub line: String,
+}
+
+impl Delivery {
+  pub fn parse<T: DeserializeOwned>(&self) -> Result<MaelstromRequest<T>, serde_json::Error> {
+    req::parse_request(&self.line)
+  }
+}
+
+struct InFlight {
+  src: NetworkEntityId,
+  dest: NetworkEntityId,
+  line: String,
+}
+
+/// What happens next in the simulation
+enum Next {
+  Fault,
+  Message,
+  Node(usize),
+}
+
+struct SimNode<N, ServiceType> {
+  id: NetworkEntityId,
+  clock: ManualClock,
+  runtime: Runtime<N, ServiceType>,
+  /// Messages sent through the node's `Outbound` handles
+  outbound: mpsc::Receiver<Vec<u8>>,
+}
+
+/// A cluster of `N`s on a simulated network. Nodes are named `n0`, `n1`, ... and are initialized
+/// as if Maelstrom had sent them `init`. Clients (any name that isn't a node's) inject requests
+/// with [`Simulation::client_request`] and whatever nodes send them ends up in
+/// [`Simulation::client_messages`].
+pub struct Simulation<N, ServiceType> {
+  rng: Rng,
+  latency: Latency,
+  epoch: Instant,
+  now: Instant,
+  nodes: Vec<SimNode<N, ServiceType>>,
+  node_index: HashMap<NetworkEntityId, usize>,
+  in_flight: BinaryHeap<Reverse<(Instant, u64)>>,
+  messages: HashMap<u64, InFlight>,
+  next_seq: u64,
+  client_msg_id: usize,
+  client_messages: Vec<Delivery>,
+  faults: Faults,
+  schedule: VecDeque<(Instant, Fault)>,
+  stats: NetworkStats,
+}
+
+impl<N, ServiceType> Simulation<N, ServiceType>
+where
+  N: Node<ServiceType>,
+  ServiceType: Service,
+{
+  /// A cluster of `node_count` nodes, created by `make_node`, with a network that delivers
+  /// messages instantly. See [`Simulation::latency`].
+  pub fn new<F>(seed: u64, node_count: usize, mut make_node: F) -> Simulation<N, ServiceType>
+  where
+    F: FnMut(&str) -> N,
+  {
+    let mut rng = Rng::new(seed);
+    let epoch = Instant::now();
+    let node_ids: Vec<NetworkEntityId> = (0..node_count).map(|i| format!("n{i}")).collect();
+    let nodes: Vec<_> = node_ids
+      .iter()
+      .map(|id| {
+        let mut node = make_node(id);
+        let init = Initialize {
+          node_id: id.clone(),
+          node_ids: node_ids.clone(),
+        };
+        node.init(&init);
+        // the wall clock starts at the same, fixed, time on every run
+        let clock = ManualClock::new(epoch, SystemTime::UNIX_EPOCH);
+        let (outbound_tx, outbound) = mpsc::channel();
+        SimNode {
+          id: id.clone(),
+          clock: clock.clone(),
+          runtime: Runtime::new(
+            node,
+            &init,
+            Box::new(clock),
+            Rng::new(rng.next_u64()),
+            outbound_tx,
+          ),
+          outbound,
+        }
+      })
+      .collect();
+    Simulation {
+      rng,
+      latency: Latency::default(),
+      epoch,
+      now: epoch,
+      node_index: node_ids
+        .into_iter()
+        .enumerate()
+        .map(|(i, id)| (id, i))
+        .collect(),
+      nodes,
+      in_flight: BinaryHeap::new(),
+      messages: HashMap::new(),
+      next_seq: 0,
+      client_msg_id: 0,
+      client_messages: Vec::new(),
+      faults: Faults::default(),
+      schedule: VecDeque::new(),
+      stats: NetworkStats::default(),
+    }
+  }
+
+  pub fn latency(mut self, latency: Latency) -> Self {
+    self.latency = latency;
+    self
+  }
+
+  pub fn set_latency(&mut self, latency: Latency) {
+    self.latency = latency;
+  }
+
+  /// Inject the faults scheduled by `nemesis`, replacing any previous schedule. Times are
+  /// relative to the start of the simulation; faults scheduled in the past are applied right away.
+  pub fn nemesis(mut self, nemesis: Nemesis) -> Self {
+    self.schedule = nemesis
+      .into_schedule()
+      .into_iter()
+      .map(|(at, fault)| (self.epoch + at, fault))
+      .collect();
+    self
+  }
+
+  /// Apply `fault` to the network now.
+  pub fn apply(&mut self, fault: Fault) {
+    let node_ids: Vec<NetworkEntityId> = self.nodes.iter().map(|n| n.id.clone()).collect();
+    self.faults.apply(fault, &node_ids, &mut self.rng);
+  }
+
+  /// Dispatch the messages every node is sent with `router`, like
+  /// [`start_routed`](crate::start_routed) does.
+  pub fn router(mut self, router: Router<N>) -> Self {
+    let router = Rc::new(router);
+    for node in &mut self.nodes {
+      node.runtime.route(router.clone());
+    }
+    self
+  }
+
+  /// Record the operations clients make against the nodes, see [`Simulation::history`].
+  pub fn record_history(mut self) -> Self {
+    for node in &mut self.nodes {
+      node.runtime.record_history(self.epoch, None);
+    }
+    self
+  }
+
+  /// The operations clients made against the nodes, as the nodes saw them, in the order they
+  /// happened. Empty unless recording was turned on with [`Simulation::record_history`].
+  pub fn history(&self) -> History {
+    let mut ops: Vec<_> = self
+      .nodes
+      .iter()
+      .filter_map(|node| node.runtime.history())
+      .flat_map(|history| history.ops().iter().cloned())
+      .collect();
+    ops.sort_by_key(|op| op.time);
+    let mut history = History::new();
+    for op in ops {
+      history.push(op);
+    }
+    history
+  }
+
+  /// The metrics of node `id`, see [`metrics`](crate::metrics).
+  pub fn metrics(&self, id: &str) -> Option<&Metrics> {
+    self
+      .node_index
+      .get(id)
+      .map(|idx| self.nodes[*idx].runtime.metrics())
+  }
+
+  /// What happened to the messages sent between nodes so far.
+  pub fn network_stats(&self) -> NetworkStats {
+    self.stats
+  }
+
+  /// The simulation's random number generator; use it for anything that must be replayable.
+  pub fn rng(&mut self) -> &mut Rng {
+    &mut self.rng
+  }
+
+  /// Time since the start of the simulation
+  pub fn elapsed(&self) -> Duration {
+    self.now - self.epoch
+  }
+
+  pub fn node_ids(&self) -> impl Iterator<Item = &str> {
+    self.nodes.iter().map(|n| n.id.as_str())
+  }
+
+  pub fn node(&self, id: &str) -> Option<&N> {
+    self
+      .node_index
+      .get(id)
+      .map(|idx| self.nodes[*idx].runtime.node())
+  }
+
+  /// The clock of node `id`. Its monotonic time follows the simulation's virtual time, but its
+  /// wall clock can be set to skew it, or make it jump, relative to the other nodes'.
+  pub fn clock(&self, id: &str) -> Option<&ManualClock> {
+    self.node_index.get(id).map(|idx| &self.nodes[*idx].clock)
+  }
+
+  pub fn nodes(&self) -> impl Iterator<Item = (&str, &N)> {
+    self.nodes.iter().map(|n| (n.id.as_str(), n.runtime.node()))
+  }
+
+  /// Send `body` from `client` to `dest` over the simulated network. Returns the `msg_id` of the
+  /// request, which the reply will be `in_reply_to`.
+  pub fn client_request<B: Serialize>(&mut self, client: &str, dest: &str, body: B) -> usize {
+    self.client_msg_id += 1;
+    let msg = MaelstromRequest {
+      src: client.to_owned(),
+      dest: dest.to_owned(),
+      body: RequestBody {
+        data: body,
+        msg_id: Some(self.client_msg_id),
+        in_reply_to: None,
+      },
+    };
+    let line = serde_json::to_string(&msg).expect("client request must serialize");
+    self.transmit(client.to_owned(), dest.to_owned(), line);
+    self.client_msg_id
+  }
+
+  /// Every message that nodes have sent to clients, in the order they were delivered.
+  pub fn client_messages(&self) -> &[Delivery] {
+    &self.client_messages
+  }
+
+  pub fn take_client_messages(&mut self) -> Vec<Delivery> {
+    std::mem::take(&mut self.client_messages)
+  }
+
+  /// The reply `client` got to its request `msg_id`, if it has been delivered.
+  pub fn reply_to(&self, client: &str, msg_id: usize) -> Option<&Delivery> {
+    self
+      .client_messages
+      .iter()
+      .find(|d| d.dest == client && d.in_reply_to == Some(msg_id))
+  }
+
+  /// When the next thing happens; a fault is injected, a message is delivered, or a node's timer
+  /// or request deadline expires. When several are due at the same time, they happen in that
+  /// order.
+  fn next_event(&mut self) -> Option<(Instant, Next)> {
+    let fault = self.schedule.front().map(|(at, _)| (*at, Next::Fault));
+    let message = self
+      .in_flight
+      .peek()
+      .map(|Reverse((at, _))| (*at, Next::Message));
+    let node = self
+      .nodes
+      .iter_mut()
+      .enumerate()
+      .filter_map(|(idx, n)| n.runtime.next_deadline().map(|at| (at, Next::Node(idx))))
+      .min_by_key(|(at, _)| *at);
+    [fault, message, node]
+      .into_iter()
+      .flatten()
+      .reduce(|first, next| if next.0 < first.0 { next } else { first })
+  }
+
+  /// Process the next event, advancing virtual time to when it happens. Returns `false` if
+  /// nothing is left to happen; no messages are in flight and no node has a timer or pending
+  /// request with a deadline.
+  pub fn step(&mut self) -> bool {
+    let Some((at, next)) = self.next_event() else {
+      return false;
+    };
+    self.now = self.now.max(at);
+    match next {
+      Next::Fault => {
+        let (_, fault) = self.schedule.pop_front().expect("peeked above");
+        self.apply(fault);
+      }
+      Next::Node(idx) => {
+        let node = &mut self.nodes[idx];
+        node.clock.set(self.now);
+        let runtime = &mut node.runtime;
+        runtime.tick(self.now);
+        runtime.fire_expired();
+        self.collect_output(idx);
+      }
+      Next::Message => {
+        let Reverse((_, seq)) = self.in_flight.pop().expect("peeked above");
+        let msg = self.messages.remove(&seq).expect("in flight message");
+        self.deliver(msg);
+      }
+    }
+    true
+  }
+
+  /// Run the simulation until `duration` of virtual time has passed.
+  pub fn run_for(&mut self, duration: Duration) {
+    let until = self.now + duration;
+    while let Some((at, _)) = self.next_event() {
+      if at > until {
+        break;
+      }
+      self.step();
+    }
+    self.now = until;
+  }
+
+  /// Run the simulation until `done` returns true, or `timeout` of virtual time has passed.
+  /// Returns whether `done` returned true.
+  pub fn run_until<F>(&mut self, timeout: Duration, mut done: F) -> bool
+  where
+    F: FnMut(&Simulation<N, ServiceType>) -> bool,
+  {
+    let until = self.now + timeout;
+    loop {
+      if done(self) {
+        return true;
+      }
+      match self.next_event() {
+        Some((at, _)) if at <= until => {
+          self.step();
+        }
+        _ => {
+          self.now = self.now.max(until);
+          return done(self);
+        }
+      }
+    }
+  }
+
+  fn deliver(&mut self, msg: InFlight) {
+    if self.between_nodes(&msg.src, &msg.dest) {
+      if self.faults.is_cut(&msg.src, &msg.dest) {
+        self.stats.partitioned += 1;
+        return;
+      }
+      self.stats.delivered += 1;
+    }
+    match self.node_index.get(&msg.dest).copied() {
+      Some(idx) => {
+        let node = &mut self.nodes[idx];
+        node.clock.set(self.now);
+        let runtime = &mut node.runtime;
+        runtime.tick(self.now);
+        runtime.fire_expired();
+        runtime.handle_line(&msg.line);
+        self.collect_output(idx);
+      }
+      None => {
+        let Ok(envelope) = req::parse_envelope(&msg.line) else {
+          return;
+        };
+        self.client_messages.push(Delivery {
+          at: self.elapsed(),
+          src: envelope.src.into_owned(),
+          dest: envelope.dest.into_owned(),
+          in_reply_to: envelope.body.in_reply_to,
+          line: msg.line,
+        });
+      }
+    }
+  }
+
+  /// Shut every node down, like the runtime does when its input ends; `Node::on_shutdown` is
+  /// called and their timers and pending requests are dropped. What the nodes send while
+  /// shutting down is put on the network.
+  pub fn shutdown(&mut self) {
+    for idx in 0..self.nodes.len() {
+      let node = &mut self.nodes[idx];
+      node.clock.set(self.now);
+      node.runtime.tick(self.now);
+      node.runtime.shutdown();
+      self.collect_output(idx);
+    }
+  }
+
+  /// Log to the logger of node `idx`, at the node's time.
+  fn log(&self, idx: usize, level: Level, msg: &str) -> Record {
+    let node = &self.nodes[idx];
+    node
+      .runtime
+      .log()
+      .record(level, msg, node.clock.system_time())
+  }
+
+  /// Log to the logger of node `id`, at the node's time. `None` if there's no such node.
+  pub(crate) fn log_as(&self, id: &str, level: Level, msg: &str) -> Option<Record> {
+    Some(self.log(*self.node_index.get(id)?, level, msg))
+  }
+
+  /// Put whatever node `idx` has sent on the network.
+  fn collect_output(&mut self, idx: usize) {
+    let mut output = self.nodes[idx].runtime.take_output();
+    while let Ok(line) = self.nodes[idx].outbound.try_recv() {
+      output.extend_from_slice(&line);
+    }
+    for line in output.split(|b| *b == b'\n').filter(|l| !l.is_empty()) {
+      let line = String::from_utf8_lossy(line).into_owned();
+      match req::parse_envelope(&line) {
+        Ok(envelope) => self.transmit(envelope.src.into_owned(), envelope.dest.into_owned(), line),
+        Err(e) => self
+          .log(idx, Level::Error, "sent a message without a valid envelope")
+          .field("error", e.to_string())
+          .emit(),
+      }
+    }
+  }
+
+  fn between_nodes(&self, src: &str, dest: &str) -> bool {
+    self.node_index.contains_key(src) && self.node_index.contains_key(dest)
+  }
+
+  fn transmit(&mut self, src: NetworkEntityId, dest: NetworkEntityId, line: String) {
+    if !self.between_nodes(&src, &dest) {
+      self.enqueue(InFlight { src, dest, line });
+      return;
+    }
+    self.stats.sent += 1;
+    if self.faults.lose(&mut self.rng) {
+      self.stats.lost += 1;
+      return;
+    }
+    let msg = InFlight { src, dest, line };
+    if self.faults.duplicate(&mut self.rng) {
+      self.stats.duplicated += 1;
+      let duplicate = InFlight {
+        src: msg.src.clone(),
+        dest: msg.dest.clone(),
+        line: msg.line.clone(),
+      };
+      self.enqueue(duplicate);
+    }
+    self.enqueue(msg);
+  }
+
+  fn enqueue(&mut self, msg: InFlight) {
+    let mut at = self.now + self.latency.sample(&mut self.rng);
+    if self.between_nodes(&msg.src, &msg.dest) {
+      if let Some(delay) = self.faults.reorder_delay(&mut self.rng) {
+        self.stats.reordered += 1;
+        at += delay;
+      }
+    }
+    let seq = self.next_seq;
+    self.next_seq += 1;
+    self.in_flight.push(Reverse((at, seq)));
+    self.messages.insert(seq, msg);
+  }
+}
@@ -0,0 +1,343 @@
+use std::{collections::HashSet, time::Duration};
+
+use crate::{rng::Rng, NetworkEntityId};
+
+/// Which nodes can't talk to each other. Partitions only ever cut links between nodes; clients can
+/// always reach every node, like in Maelstrom.
+#[derive(Debug, Clone)]
+pub enum Partition {
+  /// A random minority of the nodes is cut off from the rest
+  MajorityMinority,
+  /// Two halves that can't talk to each other, except through one node that can talk to both
+  Bridge,
+  /// One random node is cut off from every other node
+  IsolateOne,
+  /// Every link between two nodes fails with the given probability, independently in each
+  /// direction
+  RandomLinks(f64),
+  /// Nodes can only talk to nodes in the same group. Nodes not in any group are isolated
+  Groups(Vec<Vec<NetworkEntityId>>),
+  /// One of `MajorityMinority`, `Bridge` or `IsolateOne`, picked when the fault is applied
+  Random,
+}
+
+/// A change to the simulated network's behaviour. Loss, duplication and reordering only affect
+/// messages between nodes.
+#[derive(Debug, Clone)]
+pub enum Fault {
+  /// Replaces any current partition
+  Partition(Partition),
+  /// Removes the current partition
+  Heal,
+  /// Probability that a message is lost
+  Loss(f64),
+  /// Probability that a message is delivered twice
+  Duplicate(f64),
+  /// With `probability`, a message is held back for up to `max_delay` on top of its latency, so
+  /// that it's likely to be overtaken by messages sent after it
+  Reorder {
+    probability: f64,
+    max_delay: Duration,
+  },
+  /// Back to a reliable network; no partition, loss, duplication or reordering
+  Calm,
+}
+
+/// A schedule of faults, tied to the simulation's virtual time.
+#[derive(Debug, Clone, Default)]
+pub struct Nemesis {
+  schedule: Vec<(Duration, Fault)>,
+}
+
+impl Nemesis {
+  pub fn new() -> Nemesis {
+    Nemesis::default()
+  }
+
+  /// Apply `fault` when `at` has passed since the start of the simulation.
+  pub fn at(mut self, at: Duration, fault: Fault) -> Self {
+    // stable, so faults scheduled for the same time are applied in the order they were added
+    let idx = self.schedule.partition_point(|(t, _)| *t <= at);
+    self.schedule.insert(idx, (at, fault));
+    self
+  }
+
+  /// What Maelstrom's `--nemesis partition` does; every `interval`, alternate between starting a
+  /// random partition and healing it, until `until`, when the network is healed for good.
+  pub fn partitions(interval: Duration, until: Duration) -> Nemesis {
+    assert!(!interval.is_zero(), "interval must be non-zero");
+    let mut nemesis = Nemesis::new();
+    let mut at = interval;
+    let mut partition = true;
+    while at < until {
+      let fault = if partition {
+        Fault::Partition(Partition::Random)
+      } else {
+        Fault::Heal
+      };
+      nemesis = nemesis.at(at, fault);
+      partition = !partition;
+      at += interval;
+    }
+    nemesis.at(until, Fault::Heal)
+  }
+
+  pub(crate) fn into_schedule(self) -> Vec<(Duration, Fault)> {
+    self.schedule
+  }
+}
+
+/// Counts of what happened to messages sent between nodes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NetworkStats {
+  pub sent: usize,
+  pub delivered: usize,
+  /// Lost to `Fault::Loss`
+  pub lost: usize,
+  /// Dropped because a partition separated sender and receiver when the message arrived
+  pub partitioned: usize,
+  pub duplicated: usize,
+  pub reordered: usize,
+}
+
+/// The faults currently in effect.
+#[derive(Default)]
+pub(crate) struct Faults {
+  cut: HashSet<(NetworkEntityId, NetworkEntityId)>,
+  loss: f64,
+  duplicate: f64,
+  reorder: Option<(f64, Duration)>,
+}
+
+impl Faults {
+  pub(crate) fn apply(&mut self, fault: Fault, nodes: &[NetworkEntityId], rng: &mut Rng) {
+    match fault {
+      Fault::Partition(partition) => self.cut = cut_links(partition, nodes, rng),
+      Fault::Heal => self.cut.clear(),
+      Fault::Loss(p) => self.loss = p,
+      Fault::Duplicate(p) => self.duplicate = p,
+      Fault::Reorder {
+        probability,
+        max_delay,
+      } => self.reorder = Some((probability, max_delay)),
+      Fault::Calm => *self = Faults::default(),
+    }
+  }
+
+  pub(crate) fn is_cut(&self, src: &str, dest: &str) -> bool {
+    // avoids allocating for the common case
+    !self.cut.is_empty() && self.cut.contains(&(src.to_owned(), dest.to_owned()))
+  }
+
+  pub(crate) fn lose(&self, rng: &mut Rng) -> bool {
+    self.loss > 0.0 && rng.chance(self.loss)
+  }
+
+  pub(crate) fn duplicate(&self, rng: &mut Rng) -> bool {
+    self.duplicate > 0.0 && rng.chance(self.duplicate)
+  }
+
+  /// Extra delay for a message, if it's to be reordered.
+  pub(crate) fn reorder_delay(&self, rng: &mut Rng) -> Option<Duration> {
+    let (probability, max_delay) = self.reorder?;
+    rng
+      .chance(probability)
+      .then(|| max_delay.mul_f64(rng.next_f64()))
+  }
+}
+
+fn shuffle<T>(items: &mut [T], rng: &mut Rng) {
+  for i in (1..items.len()).rev() {
+    let j = rng.below(i as u64 + 1) as usize;
+    items.swap(i, j);
+  }
+}
+
+fn cut_links(
+  partition: Partition,
+  nodes: &[NetworkEntityId],
+  rng: &mut Rng,
+) -> HashSet<(NetworkEntityId, NetworkEntityId)> {
+  let mut shuffled = nodes.to_vec();
+  shuffle(&mut shuffled, rng);
+  let groups = match partition {
+    Partition::Random => {
+      let partition = match rng.below(3) {
+        0 => Partition::MajorityMinority,
+        1 => Partition::Bridge,
+        _ => Partition::IsolateOne,
+      };
+      return cut_links(partition, nodes, rng);
+    }
+    Partition::RandomLinks(p) => {
+      let mut cut = HashSet::new();
+      for src in nodes {
+        for dest in nodes.iter().filter(|dest| *dest != src) {
+          if rng.chance(p) {
+            cut.insert((src.clone(), dest.clone()));
+          }
+        }
+      }
+      return cut;
+    }
+    Partition::MajorityMinority => {
+      let majority = shuffled.split_off(shuffled.len() - (nodes.len() / 2 + 1).min(nodes.len()));
+      vec![shuffled, majority]
+    }
+    Partition::Bridge => {
+      if shuffled.is_empty() {
+        return HashSet::new();
+      }
+      let bridge = shuffled.remove(0);
+      let mut right = shuffled.split_off(shuffled.len() / 2);
+      shuffled.push(bridge.clone());
+      right.push(bridge);
+      vec![shuffled, right]
+    }
+    Partition::IsolateOne => {
+      let rest = shuffled.split_off(shuffled.len().min(1));
+      vec![shuffled, rest]
+    }
+    Partition::Groups(groups) => groups,
+  };
+
+  let mut cut = HashSet::new();
+  for src in nodes {
+    for dest in nodes.iter().filter(|dest| *dest != src) {
+      let connected = groups
+        .iter()
+        .any(|group| group.contains(src) && group.contains(dest));
+      if !connected {
+        cut.insert((src.clone(), dest.clone()));
+      }
+    }
+  }
+  cut
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn nodes(count: usize) -> Vec<NetworkEntityId> {
+    (0..count).map(|i| format!("n{i}")).collect()
+  }
+
+  /// For each node, the other nodes it can still talk to, sorted by how many there are. Also checks
+  /// that links are cut in both directions.
+  fn reachable(partition: Partition, nodes: &[NetworkEntityId], seed: u64) -> Vec<Vec<&str>> {
+    let mut faults = Faults::default();
+    faults.apply(Fault::Partition(partition), nodes, &mut Rng::new(seed));
+    let mut reachable: Vec<Vec<&str>> = nodes
+      .iter()
+      .map(|src| {
+        nodes
+          .iter()
+          .filter(|dest| *dest != src)
+          .filter(|dest| {
+            assert_eq!(faults.is_cut(src, dest), faults.is_cut(dest, src));
+            !faults.is_cut(src, dest)
+          })
+          .map(String::as_str)
+          .collect()
+      })
+      .collect();
+    reachable.sort_by_key(Vec::len);
+    reachable
+  }
+
+  fn sizes(reachable: &[Vec<&str>]) -> Vec<usize> {
+    reachable.iter().map(Vec::len).collect()
+  }
+
+  #[test]
+  fn majority_minority_splits_off_less_than_half() {
+    let (five, four) = (nodes(5), nodes(4));
+    for seed in 0..10 {
+      // 2 nodes that see each other, and 3
+      let reachable = reachable(Partition::MajorityMinority, &five, seed);
+      assert_eq!(sizes(&reachable), [1, 1, 2, 2, 2]);
+      let reachable = self::reachable(Partition::MajorityMinority, &four, seed);
+      assert_eq!(sizes(&reachable), [0, 2, 2, 2]);
+    }
+  }
+
+  #[test]
+  fn isolate_one_cuts_a_single_node_off() {
+    let nodes = nodes(4);
+    let mut isolated = HashSet::new();
+    for seed in 0..20 {
+      let reachable = reachable(Partition::IsolateOne, &nodes, seed);
+      assert_eq!(sizes(&reachable), [0, 2, 2, 2]);
+      let rest: HashSet<&str> = reachable[1..].iter().flatten().copied().collect();
+      let lone = nodes.iter().find(|n| !rest.contains(n.as_str())).unwrap();
+      isolated.insert(lone.clone());
+    }
+    // the node is picked at random
+    assert!(isolated.len() > 1, "always isolated {isolated:?}");
+  }
+
+  #[test]
+  fn a_bridge_is_the_only_node_both_halves_reach() {
+    let nodes = nodes(5);
+    let reachable = reachable(Partition::Bridge, &nodes, 1);
+    assert_eq!(sizes(&reachable), [2, 2, 2, 2, 4]);
+  }
+
+  #[test]
+  fn nodes_outside_every_group_are_isolated() {
+    let nodes = nodes(4);
+    let groups = vec![vec!["n0".into(), "n1".into()], vec!["n2".into()]];
+    let reachable = reachable(Partition::Groups(groups), &nodes, 0);
+    assert_eq!(reachable, [vec![], vec![], vec!["n1"], vec!["n0"]]);
+  }
+
+  #[test]
+  fn heal_and_calm_reconnect_every_node() {
+    let nodes = nodes(3);
+    let mut rng = Rng::new(0);
+    let mut faults = Faults::default();
+    faults.apply(Fault::Partition(Partition::IsolateOne), &nodes, &mut rng);
+    faults.apply(Fault::Loss(0.5), &nodes, &mut rng);
+    faults.apply(Fault::Heal, &nodes, &mut rng);
+    assert!(faults.cut.is_empty());
+    assert_eq!(faults.loss, 0.5);
+
+    faults.apply(Fault::Partition(Partition::IsolateOne), &nodes, &mut rng);
+    faults.apply(Fault::Calm, &nodes, &mut rng);
+    assert!(faults.cut.is_empty());
+    assert_eq!(faults.loss, 0.0);
+  }
+
+  #[test]
+  fn partitions_alternate_with_heals_until_healed_for_good() {
+    let secs = Duration::from_secs;
+    let schedule = Nemesis::partitions(secs(1), Duration::from_millis(4500)).into_schedule();
+    let times: Vec<_> = schedule.iter().map(|(at, _)| at.as_millis()).collect();
+    assert_eq!(times, [1000, 2000, 3000, 4000, 4500]);
+    let partitions: Vec<_> = schedule
+      .iter()
+      .map(|(_, fault)| matches!(fault, Fault::Partition(Partition::Random)))
+      .collect();
+    assert_eq!(partitions, [true, false, true, false, false]);
+    assert!(matches!(schedule.last(), Some((_, Fault::Heal))));
+
+    // nothing but the final heal if the nemesis stops before the first interval
+    let schedule = Nemesis::partitions(secs(5), secs(3)).into_schedule();
+    assert!(matches!(schedule[..], [(at, Fault::Heal)] if at == secs(3)));
+  }
+
+  #[test]
+  fn faults_at_the_same_time_keep_the_order_they_were_added_in() {
+    let secs = Duration::from_secs;
+    let schedule = Nemesis::new()
+      .at(secs(2), Fault::Heal)
+      .at(secs(1), Fault::Loss(0.1))
+      .at(secs(2), Fault::Calm)
+      .into_schedule();
+    assert!(matches!(
+      schedule[..],
+      [(_, Fault::Loss(_)), (_, Fault::Heal), (_, Fault::Calm)]
+    ));
+  }
+}
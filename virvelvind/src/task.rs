@@ -0,0 +1,437 @@
+//! Async handlers, behind the `async` feature. A node can spawn tasks onto its own executor with
+//! [`Context::spawn`](crate::Context::spawn), and inside them `await` replies to the requests
+//! they make, rather than handing [`Context::call`](crate::Context::call) a callback. Any number
+//! of tasks can be waiting on replies at the same time.
+//!
+//! The executor is the runtime itself; tasks are polled on the node's thread, after every event
+//! the node handles, and not tied to any async runtime. That also keeps them deterministic in the
+//! [simulator](crate::sim). Tasks don't get to borrow the node, so state they share with it, or
+//! each other, goes in an `Rc<RefCell<..>>`. Don't hold a borrow across an `await`.
+//!
+//! Nodes that only ever handle requests in tasks can implement [`AsyncNode`], whose `handle` is
+//! an `async fn`, and be run wrapped in an [`Async`].
+
+use std::{
+  cell::RefCell,
+  collections::{HashMap, VecDeque},
+  future::Future,
+  marker::PhantomData,
+  pin::Pin,
+  rc::Rc,
+  sync::{Arc, Mutex},
+  task::{Poll, Wake, Waker},
+  time::Duration,
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use crate::{
+  clock::Clock,
+  cluster::Cluster,
+  log::{Level, Logger, Record},
+  req::{MaelstromRequest, RequestBody},
+  rpc::{CallOptions, RpcResult},
+  Context, Event, Node, NodeError, TimerId,
+};
+
+type Task = Pin<Box<dyn Future<Output = ()>>>;
+
+/// The result of something a task is waiting on, and who to wake when it's there.
+pub(crate) struct Slot<T> {
+  value: Option<T>,
+  waker: Option<Waker>,
+}
+
+impl<T> Slot<T> {
+  fn new() -> Rc<RefCell<Slot<T>>> {
+    Rc::new(RefCell::new(Slot {
+      value: None,
+      waker: None,
+    }))
+  }
+
+  fn fill(&mut self, value: T) {
+    self.value = Some(value);
+    if let Some(waker) = self.waker.take() {
+      waker.wake();
+    }
+  }
+
+  fn poll(&mut self, cx: &mut std::task::Context<'_>) -> Poll<T> {
+    match self.value.take() {
+      Some(value) => Poll::Ready(value),
+      None => {
+        self.waker = Some(cx.waker().clone());
+        Poll::Pending
+      }
+    }
+  }
+}
+
+/// What tasks asked for, carried out by the runtime once they've been polled.
+pub(crate) enum Command {
+  Send {
+    dest: String,
+    in_reply_to: Option<usize>,
+    body: Value,
+  },
+  Call {
+    dest: String,
+    body: Value,
+    options: CallOptions,
+    slot: Rc<RefCell<Slot<RpcResult<Value>>>>,
+  },
+  Sleep {
+    id: TimerId,
+    after: Duration,
+  },
+}
+
+#[derive(Default)]
+struct Shared {
+  spawned: Vec<Task>,
+  commands: Vec<Command>,
+  sleepers: HashMap<TimerId, Rc<RefCell<Slot<()>>>>,
+  next_sleep: u64,
+}
+
+/// What tasks use to talk to the world, and spawn other tasks. Cheap to clone.
+#[derive(Clone)]
+pub struct Handle {
+  cluster: Rc<Cluster>,
+  log: Logger,
+  clock: Rc<dyn Clock>,
+  shared: Rc<RefCell<Shared>>,
+}
+
+impl Handle {
+  pub fn node_id(&self) -> &str {
+    self.cluster.me()
+  }
+
+  /// The nodes this one runs alongside, see [`cluster`](crate::cluster).
+  pub fn cluster(&self) -> &Cluster {
+    &self.cluster
+  }
+
+  /// Start a log record, see [`log`](crate::log).
+  pub fn log(&self, level: Level, msg: &str) -> Record {
+    self.log.record(level, msg, self.clock.system_time())
+  }
+
+  pub fn spawn<F: Future<Output = ()> + 'static>(&self, task: F) {
+    self.shared.borrow_mut().spawned.push(Box::pin(task));
+  }
+
+  fn to_value<B: Serialize>(body: B) -> Result<Value, NodeError> {
+    serde_json::to_value(body)
+      .map_err(|e| NodeError::crash(format!("Couldn't serialize message: {e}")))
+  }
+
+  /// Send `body` to `dest`, not expecting any reply.
+  pub fn send<B: Serialize>(&self, dest: &str, body: B) -> Result<(), NodeError> {
+    self.reply(dest, None, body)
+  }
+
+  /// Reply with `body` to the message `in_reply_to` that `dest` sent.
+  pub fn reply<B: Serialize>(
+    &self,
+    dest: &str,
+    in_reply_to: Option<usize>,
+    body: B,
+  ) -> Result<(), NodeError> {
+    let body = Handle::to_value(body)?;
+    self.shared.borrow_mut().commands.push(Command::Send {
+      dest: dest.to_owned(),
+      in_reply_to,
+      body,
+    });
+    Ok(())
+  }
+
+  /// Send `request` to `dest`, and wait for the reply forever.
+  pub fn call<Req, Resp>(&self, dest: &str, request: Req) -> Reply<Resp>
+  where
+    Req: Serialize,
+    Resp: DeserializeOwned,
+  {
+    self.call_with(dest, request, CallOptions::default())
+  }
+
+  /// Send `request` to `dest`. The reply resolves like the one passed to the callback of
+  /// [`Context::call_with`].
+  pub fn call_with<Req, Resp>(&self, dest: &str, request: Req, options: CallOptions) -> Reply<Resp>
+  where
+    Req: Serialize,
+    Resp: DeserializeOwned,
+  {
+    let slot = Slot::new();
+    match Handle::to_value(request) {
+      Ok(body) => self.shared.borrow_mut().commands.push(Command::Call {
+        dest: dest.to_owned(),
+        body,
+        options,
+        slot: slot.clone(),
+      }),
+      Err(e) => slot.borrow_mut().fill(Err(e)),
+    }
+    Reply {
+      slot,
+      _resp: PhantomData,
+    }
+  }
+
+  /// Resolves once `duration` has passed, on the node's clock.
+  pub fn sleep(&self, duration: Duration) -> Sleep {
+    let slot = Slot::new();
+    let mut shared = self.shared.borrow_mut();
+    shared.next_sleep += 1;
+    let id = format!("virvelvind/sleep/{}", shared.next_sleep);
+    shared.sleepers.insert(id.clone(), slot.clone());
+    shared.commands.push(Command::Sleep {
+      id,
+      after: duration,
+    });
+    Sleep { slot }
+  }
+}
+
+/// The reply to a request sent with [`Handle::call`].
+pub struct Reply<Resp> {
+  slot: Rc<RefCell<Slot<RpcResult<Value>>>>,
+  _resp: PhantomData<fn() -> Resp>,
+}
+
+impl<Resp: DeserializeOwned> Future for Reply<Resp> {
+  type Output = RpcResult<Resp>;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Self::Output> {
+    self.slot.borrow_mut().poll(cx).map(|reply| {
+      let reply = reply?;
+      let data = serde_json::from_value(reply.body.data)
+        .map_err(|e| NodeError::malformed_request(format!("Unexpected reply: {e}")))?;
+      Ok(MaelstromRequest {
+        src: reply.src,
+        dest: reply.dest,
+        body: RequestBody {
+          data,
+          msg_id: reply.body.msg_id,
+          in_reply_to: reply.body.in_reply_to,
+        },
+      })
+    })
+  }
+}
+
+/// See [`Handle::sleep`].
+pub struct Sleep {
+  slot: Rc<RefCell<Slot<()>>>,
+}
+
+impl Future for Sleep {
+  type Output = ();
+
+  fn poll(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<()> {
+    self.slot.borrow_mut().poll(cx)
+  }
+}
+
+struct TaskWaker {
+  task: usize,
+  ready: Arc<Mutex<VecDeque<usize>>>,
+}
+
+impl Wake for TaskWaker {
+  fn wake(self: Arc<Self>) {
+    self.wake_by_ref()
+  }
+
+  fn wake_by_ref(self: &Arc<Self>) {
+    if let Ok(mut ready) = self.ready.lock() {
+      ready.push_back(self.task);
+    }
+  }
+}
+
+/// The tasks of a node.
+pub(crate) struct Tasks {
+  tasks: Vec<Option<Task>>,
+  free: Vec<usize>,
+  ready: Arc<Mutex<VecDeque<usize>>>,
+  handle: Handle,
+}
+
+impl Tasks {
+  pub(crate) fn new(cluster: Rc<Cluster>, log: Logger, clock: Rc<dyn Clock>) -> Tasks {
+    Tasks {
+      tasks: Vec::new(),
+      free: Vec::new(),
+      ready: Arc::default(),
+      handle: Handle {
+        cluster,
+        log,
+        clock,
+        shared: Rc::default(),
+      },
+    }
+  }
+
+  pub(crate) fn handle(&self) -> &Handle {
+    &self.handle
+  }
+
+  fn next_ready(&self) -> Option<usize> {
+    self.ready.lock().ok()?.pop_front()
+  }
+
+  /// Poll every task that's ready, until none are. Returns what they asked the runtime to do.
+  pub(crate) fn poll(&mut self) -> Vec<Command> {
+    loop {
+      let spawned = std::mem::take(&mut self.handle.shared.borrow_mut().spawned);
+      for task in spawned {
+        let idx = match self.free.pop() {
+          Some(idx) => idx,
+          None => {
+            self.tasks.push(None);
+            self.tasks.len() - 1
+          }
+        };
+        self.tasks[idx] = Some(task);
+        if let Ok(mut ready) = self.ready.lock() {
+          ready.push_back(idx);
+        }
+      }
+      let Some(idx) = self.next_ready() else {
+        break;
+      };
+      // a wake up for a task that has finished since
+      let Some(task) = self.tasks[idx].as_mut() else {
+        continue;
+      };
+      let waker = Waker::from(Arc::new(TaskWaker {
+        task: idx,
+        ready: self.ready.clone(),
+      }));
+      if task
+        .as_mut()
+        .poll(&mut std::task::Context::from_waker(&waker))
+        .is_ready()
+      {
+        self.tasks[idx] = None;
+        self.free.push(idx);
+      }
+    }
+    std::mem::take(&mut self.handle.shared.borrow_mut().commands)
+  }
+
+  /// Wake the task sleeping on timer `id`. Returns `false` if it's not a timer of a task.
+  pub(crate) fn timer_fired(&mut self, id: &str) -> bool {
+    let Some(slot) = self.handle.shared.borrow_mut().sleepers.remove(id) else {
+      return false;
+    };
+    slot.borrow_mut().fill(());
+    true
+  }
+}
+
+/// Carry out what a task asked for.
+pub(crate) fn execute<N>(command: Command, ctx: &mut Context<N>) {
+  match command {
+    Command::Send {
+      dest,
+      in_reply_to,
+      body,
+    } => {
+      let sent = match in_reply_to {
+        Some(_) => ctx.reply(&dest, in_reply_to, body),
+        None => ctx.send(&dest, body),
+      };
+      if let Err(e) = sent {
+        ctx
+          .log(Level::Error, "failed to send message")
+          .field("dest", &dest)
+          .field("error", e.to_string())
+          .emit();
+      }
+    }
+    Command::Call {
+      dest,
+      body,
+      options,
+      slot,
+    } => {
+      let on_reply = slot.clone();
+      let sent = ctx.call_with(&dest, body, options, move |_, reply, _| {
+        on_reply.borrow_mut().fill(reply)
+      });
+      if let Err(e) = sent {
+        slot.borrow_mut().fill(Err(e));
+      }
+    }
+    Command::Sleep { id, after } => ctx.timers().once(id, after),
+  }
+}
+
+/// A node whose requests are each handled in a task of their own. Run it wrapped in an
+/// [`Async`].
+pub trait AsyncNode<S>: 'static {
+  /// Handle `msg`. If it fails, the error is sent back to whoever sent `msg`, like it is for
+  /// [`Node::handle`].
+  fn handle(
+    self: Rc<Self>,
+    msg: MaelstromRequest<S>,
+    handle: Handle,
+  ) -> impl Future<Output = Result<(), NodeError>>;
+}
+
+/// Runs an [`AsyncNode`] as a [`Node`].
+pub struct Async<A> {
+  node: Rc<A>,
+}
+
+impl<A> Async<A> {
+  pub fn new(node: A) -> Async<A> {
+    Async {
+      node: Rc::new(node),
+    }
+  }
+
+  pub fn node(&self) -> &A {
+    &self.node
+  }
+}
+
+impl<A, S> Node<S> for Async<A>
+where
+  A: AsyncNode<S>,
+  S: Serialize + DeserializeOwned + Send + 'static,
+{
+  fn handle(&mut self, evt: Event<S>, ctx: &mut Context<Self>) -> Result<(), NodeError> {
+    let Event::IOEvent(msg) = evt else {
+      return Ok(());
+    };
+    let node = self.node.clone();
+    let handle = ctx.handle();
+    ctx.spawn(async move {
+      let (src, msg_id) = (msg.src.clone(), msg.body.msg_id);
+      let Err(err) = node.handle(msg, handle.clone()).await else {
+        return;
+      };
+      if msg_id.is_none() {
+        handle
+          .log(Level::Error, "error handling message")
+          .field("src", &src)
+          .field("error", err.to_string())
+          .emit();
+      } else if let Err(e) = handle.reply(&src, msg_id, err) {
+        handle
+          .log(Level::Error, "failed to send error reply")
+          .field("dest", &src)
+          .field("error", e.to_string())
+          .emit();
+      }
+    });
+    Ok(())
+  }
+}
@@ -0,0 +1,155 @@
+use std::{
+  cmp::Reverse,
+  collections::{BinaryHeap, HashMap},
+  time::{Duration, Instant},
+};
+
+/// Timers are identified by name. Registering a timer with a name that's already in use replaces
+/// the old timer.
+pub type TimerId = String;
+
+struct Timer {
+  interval: Option<Duration>,
+  deadline: Instant,
+  // unique per (re)scheduling, so that stale entries in the queue can be told apart from the live
+  // one.
+  generation: u64,
+}
+
+/// The timers of a node. These are owned by the runtime, which delivers an `Event::Timer(id)` on
+/// the node's event loop when a timer expires. Recurring timers are re-armed automatically, one
+/// shot timers are removed once they've fired.
+///
+/// All deadlines are relative to the time the runtime started handling the current event, so
+/// that scheduling is consistent for everything a node does while handling one event.
+pub struct Timers {
+  now: Instant,
+  timers: HashMap<TimerId, Timer>,
+  queue: BinaryHeap<Reverse<(Instant, u64, TimerId)>>,
+  generation: u64,
+}
+
+impl Timers {
+  pub(crate) fn new(now: Instant) -> Timers {
+    Timers {
+      now,
+      timers: HashMap::new(),
+      queue: BinaryHeap::new(),
+      generation: 0,
+    }
+  }
+
+  /// Fire `id` once, `after` from now.
+  pub fn once<T: Into<TimerId>>(&mut self, id: T, after: Duration) {
+    self.schedule(id.into(), None, after);
+  }
+
+  /// Fire `id` every `interval`, the first time `interval` from now.
+  pub fn every<T: Into<TimerId>>(&mut self, id: T, interval: Duration) {
+    assert!(
+      !interval.is_zero(),
+      "Recurring timers can't have a zero interval"
+    );
+    self.schedule(id.into(), Some(interval), interval);
+  }
+
+  /// Move the next firing of `id` to `after` from now. Recurring timers keep their interval
+  /// after that. Returns `false` if there's no timer called `id`.
+  pub fn reschedule(&mut self, id: &str, after: Duration) -> bool {
+    let Some(interval) = self.timers.get(id).map(|timer| timer.interval) else {
+      return false;
+    };
+    self.schedule(id.to_owned(), interval, after);
+    true
+  }
+
+  /// Returns `false` if there was no timer called `id`.
+  pub fn cancel(&mut self, id: &str) -> bool {
+    self.timers.remove(id).is_some()
+  }
+
+  /// Cancel every timer.
+  pub(crate) fn clear(&mut self) {
+    self.timers.clear();
+    self.queue.clear();
+  }
+
+  pub fn is_scheduled(&self, id: &str) -> bool {
+    self.timers.contains_key(id)
+  }
+
+  /// When the timer `id` fires next, if it's scheduled.
+  pub fn deadline(&self, id: &str) -> Option<Instant> {
+    self.timers.get(id).map(|timer| timer.deadline)
+  }
+
+  pub fn now(&self) -> Instant {
+    self.now
+  }
+
+  /// The earliest deadline among the scheduled timers.
+  pub fn next_deadline(&mut self) -> Option<Instant> {
+    self.discard_stale();
+    self.queue.peek().map(|Reverse((deadline, _, _))| *deadline)
+  }
+
+  /// Called by the runtime before it starts handling an event.
+  pub(crate) fn tick(&mut self, now: Instant) {
+    self.now = now;
+  }
+
+  /// Pop the next timer that has expired by now, re-arming it if it's recurring.
+  pub(crate) fn pop_expired(&mut self) -> Option<TimerId> {
+    self.discard_stale();
+    let Reverse((deadline, _, _)) = self.queue.peek()?;
+    if *deadline > self.now {
+      return None;
+    }
+    let Reverse((deadline, _, id)) = self.queue.pop()?;
+    match self.timers[&id].interval {
+      Some(interval) => {
+        // don't try to catch up on missed intervals if we've fallen behind
+        let next = if deadline + interval > self.now {
+          deadline + interval
+        } else {
+          self.now + interval
+        };
+        self.arm(id.clone(), Some(interval), next);
+      }
+      None => {
+        self.timers.remove(&id);
+      }
+    }
+    Some(id)
+  }
+
+  fn schedule(&mut self, id: TimerId, interval: Option<Duration>, after: Duration) {
+    let deadline = self.now + after;
+    self.arm(id, interval, deadline);
+  }
+
+  fn arm(&mut self, id: TimerId, interval: Option<Duration>, deadline: Instant) {
+    self.generation += 1;
+    let generation = self.generation;
+    self.queue.push(Reverse((deadline, generation, id.clone())));
+    self.timers.insert(
+      id,
+      Timer {
+        interval,
+        deadline,
+        generation,
+      },
+    );
+  }
+
+  fn discard_stale(&mut self) {
+    while let Some(Reverse((_, generation, id))) = self.queue.peek() {
+      match self.timers.get(id) {
+        Some(timer) if timer.generation == *generation => return,
+        _ => {
+          self.queue.pop();
+        }
+      }
+    }
+  }
+}
@@ -0,0 +1,167 @@
+use std::{
+  io::{self, BufRead, BufReader, Read, Write},
+  net::TcpStream,
+  sync::mpsc::{Receiver, RecvTimeoutError, Sender},
+  time::Duration,
+};
+
+/// Where a node reads its messages from and writes its messages to. Messages are newline
+/// delimited JSON in both directions. Input is read on a thread of its own, and output is written
+/// on another, so that messages can be sent from any thread (see [`crate::outbound`]).
+pub trait Transport {
+  type Input: BufRead + Send + 'static;
+  type Output: Write + Send + 'static;
+
+  fn open(self) -> io::Result<(Self::Input, Self::Output)>;
+}
+
+/// The process' stdin and stdout, which is how Maelstrom talks to nodes.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct Stdio;
+
+impl Transport for Stdio {
+  type Input = BufReader<io::Stdin>;
+  type Output = io::Stdout;
+
+  fn open(self) -> io::Result<(Self::Input, Self::Output)> {
+    Ok((BufReader::new(io::stdin()), io::stdout()))
+  }
+}
+
+impl Transport for TcpStream {
+  type Input = BufReader<TcpStream>;
+  type Output = TcpStream;
+
+  fn open(self) -> io::Result<(Self::Input, Self::Output)> {
+    Ok((BufReader::new(self.try_clone()?), self))
+  }
+}
+
+#[cfg(unix)]
+impl Transport for std::os::unix::net::UnixStream {
+  type Input = BufReader<std::os::unix::net::UnixStream>;
+  type Output = std::os::unix::net::UnixStream;
+
+  fn open(self) -> io::Result<(Self::Input, Self::Output)> {
+    Ok((BufReader::new(self.try_clone()?), self))
+  }
+}
+
+/// An in-process transport, passing messages as one `String` per line (without the newline).
+/// Create a connected pair with [`Channel::pair`] and hand one end to the node; the other end can
+/// be driven by a test or a router through [`Channel::send`] and [`Channel::recv`].
+pub struct Channel {
+  input: Receiver<String>,
+  output: Sender<String>,
+}
+
+impl Channel {
+  pub fn new(input: Receiver<String>, output: Sender<String>) -> Channel {
+    Channel { input, output }
+  }
+
+  /// Two channels connected to each other; what is sent on one is received on the other.
+  pub fn pair() -> (Channel, Channel) {
+    let (a_tx, a_rx) = std::sync::mpsc::channel();
+    let (b_tx, b_rx) = std::sync::mpsc::channel();
+    (Channel::new(a_rx, b_tx), Channel::new(b_rx, a_tx))
+  }
+
+  /// Send a message to the other end. Returns `false` if the other end has hung up.
+  pub fn send<L: Into<String>>(&self, line: L) -> bool {
+    self.output.send(line.into()).is_ok()
+  }
+
+  /// Wait for a message from the other end. `None` once the other end has hung up.
+  pub fn recv(&self) -> Option<String> {
+    self.input.recv().ok()
+  }
+
+  pub fn recv_timeout(&self, timeout: Duration) -> Result<String, RecvTimeoutError> {
+    self.input.recv_timeout(timeout)
+  }
+
+  pub fn try_recv(&self) -> Option<String> {
+    self.input.try_recv().ok()
+  }
+}
+
+impl Transport for Channel {
+  type Input = ChannelReader;
+  type Output = ChannelWriter;
+
+  fn open(self) -> io::Result<(Self::Input, Self::Output)> {
+    Ok((
+      ChannelReader {
+        rx: self.input,
+        line: Vec::new(),
+        pos: 0,
+      },
+      ChannelWriter {
+        tx: self.output,
+        buf: Vec::new(),
+      },
+    ))
+  }
+}
+
+/// Reads the lines received by a [`Channel`]. Reaches end of input when the sending side hangs up.
+pub struct ChannelReader {
+  rx: Receiver<String>,
+  line: Vec<u8>,
+  pos: usize,
+}
+
+impl Read for ChannelReader {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let available = self.fill_buf()?;
+    let len = available.len().min(buf.len());
+    buf[..len].copy_from_slice(&available[..len]);
+    self.consume(len);
+    Ok(len)
+  }
+}
+
+impl BufRead for ChannelReader {
+  fn fill_buf(&mut self) -> io::Result<&[u8]> {
+    if self.pos == self.line.len() {
+      self.line.clear();
+      self.pos = 0;
+      if let Ok(line) = self.rx.recv() {
+        self.line = line.into_bytes();
+        self.line.push(b'\n');
+      }
+    }
+    Ok(&self.line[self.pos..])
+  }
+
+  fn consume(&mut self, amt: usize) {
+    self.pos = (self.pos + amt).min(self.line.len());
+  }
+}
+
+/// Sends every complete line written to it as a message on a [`Channel`].
+pub struct ChannelWriter {
+  tx: Sender<String>,
+  buf: Vec<u8>,
+}
+
+impl Write for ChannelWriter {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.buf.extend_from_slice(buf);
+    while let Some(end) = self.buf.iter().position(|b| *b == b'\n') {
+      let line: Vec<u8> = self.buf.drain(..=end).take(end).collect();
+      let line =
+        String::from_utf8(line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+      self
+        .tx
+        .send(line)
+        .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Channel closed"))?;
+    }
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
@@ -0,0 +1,414 @@
+//! A client for Maelstrom's `lin-tso` service, a linearizable timestamp oracle. Every `ts`
+//! request it gets is answered with a timestamp larger than all the ones it handed out before.
+//!
+//! Each timestamp the oracle hands out can be stretched to more than one caller, by numbering
+//! them; see [`Timestamp`]. The client can do so in two ways:
+//!
+//! - batching: callers that ask while a request is already in flight wait for the next request,
+//!   which is sent once that one is answered, and share its timestamp. Everyone in a batch was
+//!   waiting before the request was sent, so timestamps still respect real-time order.
+//! - caching: the last timestamp fetched is handed out again, numbered, to callers up to a limit
+//!   or for a while. Cached timestamps are unique and increasing for this node, but may be
+//!   smaller than ones other nodes have gotten since; don't cache if the order must hold across
+//!   nodes.
+
+use std::{
+  cell::RefCell,
+  collections::HashMap,
+  rc::Rc,
+  time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{rpc::RpcResult, CallOptions, Context, NodeError};
+
+/// The node id of the timestamp oracle
+pub const SERVICE: &str = "lin-tso";
+
+/// A timestamp from the oracle, `ts`, and the number of callers that were handed `ts` before
+/// this one. Ordered by `ts`, then `seq`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Timestamp {
+  pub ts: u64,
+  pub seq: u32,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Request {
+  Ts,
+}
+
+#[derive(Deserialize)]
+struct TsOk {
+  ts: u64,
+}
+
+type Callback<N> = Box<dyn FnOnce(&mut N, Result<Timestamp, NodeError>, &mut Context<N>)>;
+
+struct Cached {
+  ts: u64,
+  next_seq: u32,
+  fetched: Instant,
+}
+
+struct State<N> {
+  options: CallOptions,
+  batch: bool,
+  /// How many callers a timestamp is handed out to and for how long, if caching
+  cache: Option<(u32, Duration)>,
+  cached: Option<Cached>,
+  /// Callers waiting on a request, by the key the request was sent with
+  in_flight: HashMap<u64, Vec<Callback<N>>>,
+  next_key: u64,
+  /// Callers waiting for the next request to be sent, when batching
+  waiting: Vec<Callback<N>>,
+}
+
+impl<N> State<N> {
+  fn take_cached(&mut self, now: Instant) -> Option<Timestamp> {
+    let (max, ttl) = self.cache?;
+    let cached = self.cached.as_mut()?;
+    if cached.next_seq >= max || now.saturating_duration_since(cached.fetched) >= ttl {
+      return None;
+    }
+    let seq = cached.next_seq;
+    cached.next_seq += 1;
+    Some(Timestamp { ts: cached.ts, seq })
+  }
+}
+
+/// A client for the timestamp oracle. Cheap to clone; clones share their batches and cache.
+/// Requests time out after a second by default.
+pub struct TsoClient<N> {
+  state: Rc<RefCell<State<N>>>,
+}
+
+impl<N> Clone for TsoClient<N> {
+  fn clone(&self) -> Self {
+    TsoClient {
+      state: self.state.clone(),
+    }
+  }
+}
+
+impl<N: 'static> Default for TsoClient<N> {
+  fn default() -> Self {
+    TsoClient::new()
+  }
+}
+
+impl<N: 'static> TsoClient<N> {
+  /// A client that sends a request for every timestamp.
+  pub fn new() -> TsoClient<N> {
+    TsoClient {
+      state: Rc::new(RefCell::new(State {
+        options: CallOptions::timeout(Duration::from_secs(1)),
+        batch: false,
+        cache: None,
+        cached: None,
+        in_flight: HashMap::new(),
+        next_key: 0,
+        waiting: Vec::new(),
+      })),
+    }
+  }
+
+  /// Send requests with `options` instead.
+  pub fn with_options(self, options: CallOptions) -> TsoClient<N> {
+    self.state.borrow_mut().options = options;
+    self
+  }
+
+  /// Have callers that ask while a request is in flight share the next request.
+  pub fn batch(self) -> TsoClient<N> {
+    self.state.borrow_mut().batch = true;
+    self
+  }
+
+  /// Hand each timestamp fetched out to up to `max` callers, for at most `ttl` after it was
+  /// fetched.
+  pub fn cache(self, max: u32, ttl: Duration) -> TsoClient<N> {
+    self.state.borrow_mut().cache = Some((max, ttl));
+    self
+  }
+
+  /// Get a timestamp and hand it to `on_ts`; right away if there's one cached, otherwise when
+  /// the oracle replies. `node` is the node the client belongs to, f.ex.
+  /// `self.tso.clone().next(self, ctx, ..)`. If the request can't be sent, `on_ts` is never
+  /// called.
+  pub fn next<F>(&self, node: &mut N, ctx: &mut Context<N>, on_ts: F) -> Result<(), NodeError>
+  where
+    F: FnOnce(&mut N, Result<Timestamp, NodeError>, &mut Context<N>) + 'static,
+  {
+    let cached = self.state.borrow_mut().take_cached(ctx.clock().now());
+    if let Some(ts) = cached {
+      on_ts(node, Ok(ts), ctx);
+      return Ok(());
+    }
+    {
+      let mut state = self.state.borrow_mut();
+      if state.batch && !state.in_flight.is_empty() {
+        state.waiting.push(Box::new(on_ts));
+        return Ok(());
+      }
+    }
+    self
+      .fetch(ctx, vec![Box::new(on_ts)])
+      .map_err(|(err, _)| err)
+  }
+
+  /// Send a request on behalf of `callers`. Hands them back if it couldn't be sent.
+  fn fetch(
+    &self,
+    ctx: &mut Context<N>,
+    callers: Vec<Callback<N>>,
+  ) -> Result<(), (NodeError, Vec<Callback<N>>)> {
+    let (key, options) = {
+      let mut state = self.state.borrow_mut();
+      let key = state.next_key;
+      state.next_key += 1;
+      state.in_flight.insert(key, callers);
+      (key, state.options)
+    };
+    let client = self.clone();
+    let sent = ctx.call_with(SERVICE, Request::Ts, options, move |node, reply, ctx| {
+      client.resolve(node, key, reply, ctx)
+    });
+    match sent {
+      Ok(_) => Ok(()),
+      Err(err) => {
+        let callers = self.state.borrow_mut().in_flight.remove(&key);
+        Err((err, callers.unwrap_or_default()))
+      }
+    }
+  }
+
+  fn resolve(&self, node: &mut N, key: u64, reply: RpcResult<TsOk>, ctx: &mut Context<N>) {
+    let ts = reply.map(|reply| reply.body.data.ts);
+    let (callers, next_batch) = {
+      let mut state = self.state.borrow_mut();
+      let callers = state.in_flight.remove(&key).unwrap_or_default();
+      if let (Ok(ts), Some(_)) = (&ts, state.cache) {
+        // replies to concurrent requests may arrive out of order
+        if state.cached.as_ref().is_none_or(|cached| cached.ts < *ts) {
+          state.cached = Some(Cached {
+            ts: *ts,
+            next_seq: callers.len() as u32,
+            fetched: ctx.clock().now(),
+          });
+        }
+      }
+      let next_batch = std::mem::take(&mut state.waiting);
+      (callers, next_batch)
+    };
+    if !next_batch.is_empty() {
+      if let Err((err, next_batch)) = self.fetch(ctx, next_batch) {
+        for on_ts in next_batch {
+          on_ts(node, Err(err.clone()), ctx);
+        }
+      }
+    }
+    for (seq, on_ts) in callers.into_iter().enumerate() {
+      let ts = ts.clone().map(|ts| Timestamp {
+        ts,
+        seq: seq as u32,
+      });
+      on_ts(node, ts, ctx);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{sync::mpsc, time::SystemTime};
+
+  use serde_json::{json, Value};
+
+  use super::*;
+  use crate::{
+    clock::{Clock, ManualClock},
+    req::Initialize,
+    rng::Rng,
+    runtime::Runtime,
+    Event, Node,
+  };
+
+  /// Asks for a timestamp for every message it's sent, and keeps what it's handed.
+  struct Caller {
+    tso: TsoClient<Caller>,
+    got: Vec<Result<Timestamp, NodeError>>,
+  }
+
+  impl Node<Value> for Caller {
+    fn handle(&mut self, evt: Event<Value>, ctx: &mut Context<Self>) -> Result<(), NodeError> {
+      if let Event::IOEvent(_) = evt {
+        let tso = self.tso.clone();
+        tso.next(self, ctx, |node, ts, _| node.got.push(ts))?;
+      }
+      Ok(())
+    }
+  }
+
+  struct Harness {
+    clock: ManualClock,
+    runtime: Runtime<Caller, Value>,
+  }
+
+  impl Harness {
+    fn new(tso: TsoClient<Caller>) -> Harness {
+      let init = Initialize {
+        node_id: "n0".into(),
+        node_ids: vec!["n0".into()],
+      };
+      let clock = ManualClock::new(Instant::now(), SystemTime::UNIX_EPOCH);
+      let caller = Caller {
+        tso,
+        got: Vec::new(),
+      };
+      let (outbound_tx, _) = mpsc::channel();
+      let runtime = Runtime::new(
+        caller,
+        &init,
+        Box::new(clock.clone()),
+        Rng::new(1),
+        outbound_tx,
+      );
+      Harness { clock, runtime }
+    }
+
+    fn next(&mut self) {
+      let msg = json!({ "src": "c1", "dest": "n0", "body": { "type": "next" } });
+      self.runtime.handle_line(&msg.to_string());
+    }
+
+    /// The `msg_id`s of the `ts` requests sent since the last call.
+    fn requests(&mut self) -> Vec<usize> {
+      let output = self.runtime.take_output();
+      let output = String::from_utf8(output).unwrap();
+      output
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .filter(|msg| msg["dest"] == SERVICE)
+        .map(|msg| msg["body"]["msg_id"].as_u64().unwrap() as usize)
+        .collect()
+    }
+
+    fn reply(&mut self, msg_id: usize, ts: u64) {
+      let body = json!({ "type": "ts_ok", "ts": ts, "in_reply_to": msg_id });
+      let msg = json!({ "src": SERVICE, "dest": "n0", "body": body });
+      self.runtime.handle_line(&msg.to_string());
+    }
+
+    fn advance(&mut self, by: Duration) {
+      self.clock.advance(by);
+      self.runtime.tick(self.clock.now());
+      self.runtime.fire_expired();
+    }
+
+    fn got(&self) -> Vec<Timestamp> {
+      let got = &self.runtime.node().got;
+      got.iter().map(|ts| ts.clone().unwrap()).collect()
+    }
+  }
+
+  fn ts(ts: u64, seq: u32) -> Timestamp {
+    Timestamp { ts, seq }
+  }
+
+  #[test]
+  fn every_caller_gets_a_request_of_its_own_by_default() {
+    let mut harness = Harness::new(TsoClient::new());
+    harness.next();
+    harness.next();
+    let requests = harness.requests();
+    assert_eq!(requests.len(), 2);
+    harness.reply(requests[1], 4);
+    harness.reply(requests[0], 3);
+    assert_eq!(harness.got(), [ts(4, 0), ts(3, 0)]);
+  }
+
+  #[test]
+  fn callers_that_ask_while_a_request_is_in_flight_share_the_next() {
+    let mut harness = Harness::new(TsoClient::new().batch());
+    harness.next();
+    let first = harness.requests();
+    harness.next();
+    harness.next();
+    assert_eq!(first.len(), 1);
+    assert!(harness.requests().is_empty());
+
+    // the batch is sent once the request in flight is answered, and numbers its timestamp
+    harness.reply(first[0], 5);
+    let second = harness.requests();
+    assert_eq!(second.len(), 1);
+    assert_eq!(harness.got(), [ts(5, 0)]);
+    harness.reply(second[0], 7);
+    assert_eq!(harness.got(), [ts(5, 0), ts(7, 0), ts(7, 1)]);
+    assert!(harness.requests().is_empty());
+  }
+
+  #[test]
+  fn cached_timestamps_run_out_and_expire() {
+    let mut harness = Harness::new(TsoClient::new().cache(3, Duration::from_millis(50)));
+    harness.next();
+    let first = harness.requests();
+    harness.reply(first[0], 5);
+    harness.next();
+    harness.next();
+    assert!(harness.requests().is_empty());
+    assert_eq!(harness.got(), [ts(5, 0), ts(5, 1), ts(5, 2)]);
+
+    // handed out to as many callers as allowed
+    harness.next();
+    let second = harness.requests();
+    assert_eq!(second.len(), 1);
+    harness.reply(second[0], 9);
+    harness.next();
+    assert_eq!(harness.got()[3..], [ts(9, 0), ts(9, 1)]);
+
+    // and for as long as allowed
+    harness.advance(Duration::from_millis(50));
+    harness.next();
+    let third = harness.requests();
+    assert_eq!(third.len(), 1);
+    harness.reply(third[0], 12);
+    assert_eq!(harness.got()[5..], [ts(12, 0)]);
+  }
+
+  #[test]
+  fn late_replies_resolve_the_callers_of_their_own_request() {
+    let mut harness = Harness::new(TsoClient::new().cache(10, Duration::from_secs(1)));
+    harness.next();
+    harness.next();
+    let requests = harness.requests();
+    harness.reply(requests[1], 8);
+    harness.reply(requests[0], 6);
+    assert_eq!(harness.got(), [ts(8, 0), ts(6, 0)]);
+    // the older timestamp, answered last, doesn't replace the newer one in the cache
+    harness.next();
+    assert_eq!(harness.got()[2..], [ts(8, 1)]);
+  }
+
+  #[test]
+  fn callers_are_failed_when_their_request_times_out() {
+    let options = CallOptions::timeout(Duration::from_millis(100));
+    let mut harness = Harness::new(TsoClient::new().with_options(options).batch());
+    harness.next();
+    let first = harness.requests();
+    harness.next();
+    harness.advance(Duration::from_millis(100));
+    let got = &harness.runtime.node().got;
+    assert_eq!(got.len(), 1);
+    assert_eq!(got[0].as_ref().unwrap_err().code, crate::ErrorCode::Timeout);
+
+    // the batch waiting behind it goes out, and a reply to the request that timed out is ignored
+    let second = harness.requests();
+    assert_eq!(second.len(), 1);
+    harness.reply(first[0], 3);
+    assert_eq!(harness.runtime.node().got.len(), 1);
+    harness.reply(second[0], 4);
+    assert_eq!(harness.runtime.node().got[1], Ok(ts(4, 0)));
+  }
+}
@@ -0,0 +1,380 @@
+//! Client workloads like Maelstrom's, run against a [`Simulation`]. A workload generates requests
+//! that clients send to the nodes, and checks the history of what they returned. The Maelstrom
+//! flags map onto a run like this:
+//!
+//! | Maelstrom             | here                                                            |
+//! |-----------------------|-----------------------------------------------------------------|
+//! | `--node-count 25`     | `Simulation::new(seed, 25, ..)`                                 |
+//! | `--latency 100`       | `.latency(Latency::Exponential { mean: 100ms, max: .. })`       |
+//! | `--rate 100`          | `Options { rate: 100.0, .. }`                                   |
+//! | `--time-limit 20`     | `Options { time_limit: 20s, .. }`                               |
+//! | `--concurrency 10`    | `Options { concurrency: Some(10), .. }`                         |
+//! | `--nemesis partition` | `.nemesis(Nemesis::partitions(..))`                             |
+
+use std::time::Duration;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+  check::{History, Operation},
+  history,
+  log::Level,
+  req,
+  sim::{Delivery, Simulation},
+  NetworkEntityId, Node, NodeError, Service,
+};
+
+pub mod broadcast;
+pub mod echo;
+pub mod g_counter;
+pub mod kafka;
+pub mod lin_kv;
+pub mod txn_rw_register;
+pub mod unique_ids;
+
+pub use broadcast::Broadcast;
+pub use echo::Echo;
+pub use g_counter::GCounter;
+pub use kafka::Kafka;
+pub use lin_kv::LinKv;
+pub use txn_rw_register::TxnRwRegister;
+pub use unique_ids::UniqueIds;
+
+/// How a request completed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome<Resp> {
+  Ok(Resp),
+  /// The request definitely did not take effect
+  Fail,
+  /// The request may or may not have taken effect
+  Info,
+}
+
+pub trait Workload {
+  type Request: Serialize + Clone;
+  type Response: Serialize + DeserializeOwned + Clone;
+
+  /// Requests sent before the workload starts, f.ex. broadcast's topology. They're not recorded
+  /// in the history.
+  fn setup(&mut self, _nodes: &[NetworkEntityId]) -> Vec<(NetworkEntityId, Self::Request)> {
+    Vec::new()
+  }
+
+  /// The next request a client sends.
+  fn generate(&mut self, rng: &mut crate::rng::Rng) -> Self::Request;
+
+  /// Requests sent once the workload is over and the cluster has had time to recover, f.ex. a
+  /// final read on every node. They're made by processes `clients`, `clients + 1` and so on, in
+  /// the order they're returned.
+  fn final_requests(
+    &mut self,
+    _nodes: &[NetworkEntityId],
+    _clients: usize,
+  ) -> Vec<(NetworkEntityId, Self::Request)> {
+    Vec::new()
+  }
+
+  /// How an `error` reply to `request` is recorded. By default a definite error is a failure, any
+  /// other an indeterminate result.
+  fn on_error(&self, _request: &Self::Request, error: &NodeError) -> Outcome<Self::Response> {
+    if error.code.is_definite() {
+      Outcome::Fail
+    } else {
+      Outcome::Info
+    }
+  }
+
+  /// Check that the history is what the workload expects. Processes are clients; the final
+  /// requests are made by processes numbered after the clients.
+  fn check(&self, history: &History<Self::Request, Self::Response>) -> Result<(), String>;
+}
+
+/// How the workload is run.
+#[derive(Debug, Clone)]
+pub struct Options {
+  /// Requests per second, over all clients
+  pub rate: f64,
+  /// Number of clients. Each client has at most one request in flight. `None` is a client per
+  /// node, like Maelstrom.
+  pub concurrency: Option<usize>,
+  /// For how long (virtual time) clients send requests
+  pub time_limit: Duration,
+  /// How long a client waits for a reply, before recording the request as indeterminate
+  pub timeout: Duration,
+  /// How long the cluster gets to recover after the time limit, before the final requests are
+  /// sent. Faults are not healed by the runner, schedule that with the nemesis.
+  pub recovery: Duration,
+}
+
+impl Default for Options {
+  fn default() -> Self {
+    Options {
+      rate: 5.0,
+      concurrency: None,
+      time_limit: Duration::from_secs(10),
+      timeout: Duration::from_secs(5),
+      recovery: Duration::from_secs(5),
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct Report<Req, Resp> {
+  pub history: History<Req, Resp>,
+  /// When each entry of the history happened, in virtual time since the simulation started
+  pub times: Vec<Duration>,
+  pub ok: usize,
+  pub fail: usize,
+  pub info: usize,
+  /// What the workload's check said about the history
+  pub valid: Result<(), String>,
+}
+
+impl<Req, Resp> Report<Req, Resp> {
+  pub fn is_valid(&self) -> bool {
+    self.valid.is_ok()
+  }
+}
+
+impl<Req: Serialize, Resp: Serialize> Report<Req, Resp> {
+  /// The history as Jepsen would have recorded it, to be written out with
+  /// [`history::History::to_edn`].
+  pub fn jepsen_history(&self) -> Result<history::History, serde_json::Error> {
+    let entries = self.times.iter().copied().zip(self.history.entries());
+    history::History::from_entries(entries)
+  }
+}
+
+struct Pending<Req> {
+  msg_id: usize,
+  request: Req,
+  deadline: Duration,
+}
+
+/// The clients of a run, and what they have in flight.
+struct Clients<W: Workload> {
+  names: Vec<String>,
+  pending: Vec<Option<Pending<W::Request>>>,
+  report: Report<W::Request, W::Response>,
+}
+
+impl<W: Workload> Clients<W> {
+  fn new(count: usize) -> Clients<W> {
+    Clients {
+      names: (0..count).map(|i| format!("c{}", i + 1)).collect(),
+      pending: (0..count).map(|_| None).collect(),
+      report: Report {
+        history: History::new(),
+        times: Vec::new(),
+        ok: 0,
+        fail: 0,
+        info: 0,
+        valid: Ok(()),
+      },
+    }
+  }
+
+  fn add(&mut self) -> usize {
+    self.names.push(format!("c{}", self.names.len() + 1));
+    self.pending.push(None);
+    self.names.len() - 1
+  }
+
+  fn in_flight(&self) -> usize {
+    self.pending.iter().flatten().count()
+  }
+
+  fn send<N, S>(
+    &mut self,
+    sim: &mut Simulation<N, S>,
+    client: usize,
+    dest: &str,
+    request: W::Request,
+    timeout: Duration,
+  ) where
+    N: Node<S>,
+    S: Service,
+  {
+    let msg_id = sim.client_request(&self.names[client], dest, &request);
+    self.report.history.invoke(client, request.clone());
+    self.report.times.push(sim.elapsed());
+    self.pending[client] = Some(Pending {
+      msg_id,
+      request,
+      deadline: sim.elapsed() + timeout,
+    });
+  }
+
+  fn record(&mut self, client: usize, outcome: Outcome<W::Response>, now: Duration) {
+    self.report.times.push(now);
+    match outcome {
+      Outcome::Ok(resp) => {
+        self.report.ok += 1;
+        self.report.history.ok(client, resp);
+      }
+      Outcome::Fail => {
+        self.report.fail += 1;
+        self.report.history.fail(client);
+      }
+      Outcome::Info => {
+        self.report.info += 1;
+        self.report.history.info(client);
+      }
+    }
+  }
+
+  fn receive<N, S>(&mut self, sim: &Simulation<N, S>, workload: &W, delivery: Delivery)
+  where
+    N: Node<S>,
+    S: Service,
+  {
+    let Some(client) = self.names.iter().position(|name| *name == delivery.dest) else {
+      return;
+    };
+    let is_reply =
+      matches!(&self.pending[client], Some(p) if Some(p.msg_id) == delivery.in_reply_to);
+    if !is_reply {
+      // a reply to a request that already timed out
+      return;
+    }
+    let pending = self.pending[client].take().expect("checked above");
+    let outcome = match req::parse_envelope(&delivery.line) {
+      Ok(envelope) if envelope.is_error() => match envelope.parse_body::<NodeError>() {
+        Ok(error) => workload.on_error(&pending.request, &error.data),
+        Err(_) => Outcome::Info,
+      },
+      envelope => match envelope.and_then(|envelope| envelope.parse_body::<W::Response>()) {
+        Ok(resp) => Outcome::Ok(resp.data),
+        Err(e) => {
+          if let Some(record) = sim.log_as(&delivery.src, Level::Warn, "unexpected reply") {
+            record
+              .field("dest", &delivery.dest)
+              .field("error", e.to_string())
+              .field("line", &delivery.line)
+              .emit();
+          }
+          Outcome::Info
+        }
+      },
+    };
+    self.record(client, outcome, delivery.at);
+  }
+
+  fn time_out(&mut self, now: Duration) {
+    for client in 0..self.pending.len() {
+      if matches!(&self.pending[client], Some(p) if p.deadline <= now) {
+        self.pending[client] = None;
+        self.record(client, Outcome::Info, now);
+      }
+    }
+  }
+
+  /// Run the simulation until a client gets a message, or `until`, and handle what they got.
+  fn run_until<N, S>(&mut self, sim: &mut Simulation<N, S>, workload: &W, until: Duration)
+  where
+    N: Node<S>,
+    S: Service,
+  {
+    let next_deadline = self.pending.iter().flatten().map(|p| p.deadline).min();
+    let until = next_deadline.map_or(until, |deadline| deadline.min(until));
+    let timeout = until.saturating_sub(sim.elapsed());
+    sim.run_until(timeout, |sim| !sim.client_messages().is_empty());
+    for delivery in sim.take_client_messages() {
+      self.receive(sim, workload, delivery);
+    }
+    self.time_out(sim.elapsed());
+  }
+
+  /// Run the simulation until every client's request completed or timed out.
+  fn drain<N, S>(&mut self, sim: &mut Simulation<N, S>, workload: &W)
+  where
+    N: Node<S>,
+    S: Service,
+  {
+    while self.in_flight() > 0 {
+      self.run_until(sim, workload, Duration::MAX);
+    }
+  }
+}
+
+/// The operations of the final requests, made by processes `clients..clients + count`, in the
+/// order of their processes. It's an error if one of them wasn't made or failed.
+fn final_operations<Op, Ret>(
+  ops: &[Operation<Op, Ret>],
+  clients: usize,
+  count: usize,
+) -> Result<Vec<&Operation<Op, Ret>>, String> {
+  (clients..clients + count)
+    .map(|process| {
+      ops
+        .iter()
+        .find(|op| op.process == process)
+        .ok_or_else(|| format!("Final request by process {process} failed or was never made"))
+    })
+    .collect()
+}
+
+/// Run `workload` against the nodes of `sim`, and check the result.
+pub fn run<N, S, W>(
+  sim: &mut Simulation<N, S>,
+  workload: &mut W,
+  options: &Options,
+) -> Report<W::Request, W::Response>
+where
+  N: Node<S>,
+  S: Service,
+  W: Workload,
+{
+  let nodes: Vec<NetworkEntityId> = sim.node_ids().map(str::to_owned).collect();
+  let concurrency = options.concurrency.unwrap_or(nodes.len()).max(1);
+  let mut clients = Clients::<W>::new(concurrency);
+
+  // setup requests are sent by a client of their own, that's not part of the history
+  let setup = workload.setup(&nodes);
+  let expected = setup.len();
+  for (dest, request) in setup {
+    sim.client_request("c0", &dest, request);
+  }
+  sim.run_until(options.timeout, |sim| {
+    sim
+      .client_messages()
+      .iter()
+      .filter(|d| d.dest == "c0")
+      .count()
+      >= expected
+  });
+  sim.take_client_messages();
+
+  let end = sim.elapsed() + options.time_limit;
+  let mut next_request = sim.elapsed();
+  while sim.elapsed() < end && !nodes.is_empty() {
+    if sim.elapsed() >= next_request {
+      let free: Vec<usize> = (0..concurrency)
+        .filter(|c| clients.pending[*c].is_none())
+        .collect();
+      if !free.is_empty() {
+        let client = free[sim.rng().below(free.len() as u64) as usize];
+        let request = workload.generate(sim.rng());
+        let dest = nodes[client % nodes.len()].clone();
+        clients.send(sim, client, &dest, request, options.timeout);
+      }
+      // exponentially distributed time between requests, like a Poisson process
+      let u = 1.0 - sim.rng().next_f64();
+      next_request += Duration::from_secs_f64(-u.ln() / options.rate.max(f64::MIN_POSITIVE));
+    }
+    clients.run_until(sim, workload, next_request.min(end));
+  }
+  clients.drain(sim, workload);
+
+  sim.run_for(options.recovery);
+  sim.take_client_messages();
+  for (dest, request) in workload.final_requests(&nodes, concurrency) {
+    let client = clients.add();
+    clients.send(sim, client, &dest, request, options.timeout);
+  }
+  clients.drain(sim, workload);
+
+  let mut report = clients.report;
+  report.valid = workload.check(&report.history);
+  report
+}
@@ -0,0 +1,234 @@
+use std::collections::{BTreeSet, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use super::{final_operations, Workload};
+use crate::{check::History, rng::Rng, NetworkEntityId};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BroadcastRequest {
+  Topology {
+    topology: HashMap<NetworkEntityId, Vec<NetworkEntityId>>,
+  },
+  Broadcast {
+    message: u64,
+  },
+  Read,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BroadcastResponse {
+  TopologyOk,
+  BroadcastOk,
+  ReadOk { messages: Vec<u64> },
+}
+
+/// The topologies Maelstrom can hand the nodes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Topology {
+  /// Nodes are laid out in a square grid and neighbour the nodes above, below, left and right
+  #[default]
+  Grid,
+  /// Every node neighbours the ones before and after it
+  Line,
+  /// Every node neighbours every other node
+  Total,
+}
+
+impl Topology {
+  pub fn neighbours(
+    &self,
+    nodes: &[NetworkEntityId],
+  ) -> HashMap<NetworkEntityId, Vec<NetworkEntityId>> {
+    let n = nodes.len();
+    let width = (n as f64).sqrt().ceil().max(1.0) as usize;
+    (0..n)
+      .map(|i| {
+        let neighbours: Vec<usize> = match self {
+          Topology::Total => (0..n).filter(|j| *j != i).collect(),
+          Topology::Line => [i.checked_sub(1), Some(i + 1)]
+            .into_iter()
+            .flatten()
+            .filter(|j| *j < n)
+            .collect(),
+          Topology::Grid => [
+            (i % width != 0).then(|| i - 1),
+            ((i + 1) % width != 0).then_some(i + 1),
+            i.checked_sub(width),
+            Some(i + width),
+          ]
+          .into_iter()
+          .flatten()
+          .filter(|j| *j < n)
+          .collect(),
+        };
+        let neighbours = neighbours.into_iter().map(|j| nodes[j].clone()).collect();
+        (nodes[i].clone(), neighbours)
+      })
+      .collect()
+  }
+}
+
+/// Maelstrom's `broadcast` workload. Every message that was acknowledged must, once the cluster
+/// has settled, be read on every node, and no node may read a message nobody broadcast.
+#[derive(Debug, Default)]
+pub struct Broadcast {
+  pub topology: Topology,
+  next_message: u64,
+  clients: usize,
+  final_reads: usize,
+}
+
+impl Broadcast {
+  pub fn new(topology: Topology) -> Broadcast {
+    Broadcast {
+      topology,
+      ..Default::default()
+    }
+  }
+}
+
+impl Workload for Broadcast {
+  type Request = BroadcastRequest;
+  type Response = BroadcastResponse;
+
+  fn setup(&mut self, nodes: &[NetworkEntityId]) -> Vec<(NetworkEntityId, Self::Request)> {
+    let topology = self.topology.neighbours(nodes);
+    nodes
+      .iter()
+      .map(|node| {
+        let request = BroadcastRequest::Topology {
+          topology: topology.clone(),
+        };
+        (node.clone(), request)
+      })
+      .collect()
+  }
+
+  fn generate(&mut self, rng: &mut Rng) -> Self::Request {
+    if rng.chance(0.5) {
+      return BroadcastRequest::Read;
+    }
+    self.next_message += 1;
+    BroadcastRequest::Broadcast {
+      message: self.next_message,
+    }
+  }
+
+  fn final_requests(
+    &mut self,
+    nodes: &[NetworkEntityId],
+    clients: usize,
+  ) -> Vec<(NetworkEntityId, Self::Request)> {
+    self.clients = clients;
+    self.final_reads = nodes.len();
+    nodes
+      .iter()
+      .map(|node| (node.clone(), BroadcastRequest::Read))
+      .collect()
+  }
+
+  fn check(&self, history: &History<Self::Request, Self::Response>) -> Result<(), String> {
+    let ops = crate::check::operations(history).map_err(|e| e.to_string())?;
+    let mut attempted = BTreeSet::new();
+    let mut acknowledged = BTreeSet::new();
+    for op in &ops {
+      if let BroadcastRequest::Broadcast { message } = op.op {
+        attempted.insert(message);
+        if op.completed.is_some() {
+          acknowledged.insert(message);
+        }
+      }
+    }
+    for op in &ops {
+      let Some(BroadcastResponse::ReadOk { messages }) = &op.ret else {
+        continue;
+      };
+      if let Some(unexpected) = messages.iter().find(|m| !attempted.contains(*m)) {
+        return Err(format!(
+          "Process {} read {unexpected}, which was never broadcast",
+          op.process
+        ));
+      }
+    }
+
+    for op in final_operations(&ops, self.clients, self.final_reads)? {
+      let Some(BroadcastResponse::ReadOk { messages }) = &op.ret else {
+        return Err(format!(
+          "Final read by process {} didn't complete",
+          op.process
+        ));
+      };
+      let read: BTreeSet<u64> = messages.iter().copied().collect();
+      let lost: Vec<_> = acknowledged.difference(&read).collect();
+      if !lost.is_empty() {
+        return Err(format!(
+          "Final read by process {} is missing acknowledged messages {lost:?}",
+          op.process
+        ));
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn read_ok(messages: &[u64]) -> BroadcastResponse {
+    BroadcastResponse::ReadOk {
+      messages: messages.to_vec(),
+    }
+  }
+
+  /// A broadcast of 1 by the only client, that read it back, followed by a final read on each of
+  /// two nodes
+  fn run() -> (Broadcast, History<BroadcastRequest, BroadcastResponse>) {
+    let mut workload = Broadcast::new(Topology::Line);
+    workload.final_requests(&["n0".to_string(), "n1".to_string()], 1);
+    let mut history = History::new();
+    history.invoke(0, BroadcastRequest::Broadcast { message: 1 });
+    history.ok(0, BroadcastResponse::BroadcastOk);
+    history.invoke(0, BroadcastRequest::Read);
+    history.ok(0, read_ok(&[1]));
+    history.invoke(1, BroadcastRequest::Read);
+    history.ok(1, read_ok(&[1]));
+    history.invoke(2, BroadcastRequest::Read);
+    (workload, history)
+  }
+
+  #[test]
+  fn every_final_read_must_see_every_acknowledged_message() {
+    let (workload, mut history) = run();
+    history.ok(2, read_ok(&[1]));
+    assert_eq!(workload.check(&history), Ok(()));
+
+    let (workload, mut history) = run();
+    history.ok(2, read_ok(&[]));
+    assert_eq!(
+      workload.check(&history),
+      Err("Final read by process 2 is missing acknowledged messages [1]".to_string())
+    );
+  }
+
+  #[test]
+  fn final_reads_that_failed_or_timed_out_fail_the_check() {
+    // a client's read doesn't stand in for the final read that failed
+    let (workload, mut history) = run();
+    history.fail(2);
+    assert_eq!(
+      workload.check(&history),
+      Err("Final request by process 2 failed or was never made".to_string())
+    );
+
+    let (workload, mut history) = run();
+    history.info(2);
+    assert_eq!(
+      workload.check(&history),
+      Err("Final read by process 2 didn't complete".to_string())
+    );
+  }
+}
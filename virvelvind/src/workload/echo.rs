@@ -0,0 +1,67 @@
+use serde::{Deserialize, Serialize};
+
+use super::Workload;
+use crate::{check::History, rng::Rng};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EchoRequest {
+  Echo { echo: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EchoResponse {
+  EchoOk { echo: String },
+}
+
+/// Maelstrom's `echo` workload; every reply must echo what was sent.
+#[derive(Debug, Default)]
+pub struct Echo;
+
+impl Workload for Echo {
+  type Request = EchoRequest;
+  type Response = EchoResponse;
+
+  fn generate(&mut self, rng: &mut Rng) -> Self::Request {
+    EchoRequest::Echo {
+      echo: format!("Please echo {}", rng.below(128)),
+    }
+  }
+
+  fn check(&self, history: &History<Self::Request, Self::Response>) -> Result<(), String> {
+    let ops = crate::check::operations(history).map_err(|e| e.to_string())?;
+    for op in ops {
+      let (EchoRequest::Echo { echo: sent }, Some(EchoResponse::EchoOk { echo })) =
+        (&op.op, &op.ret)
+      else {
+        continue;
+      };
+      if sent != echo {
+        return Err(format!("Sent {sent:?} but got {echo:?} back"));
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn echo(process: usize, sent: &str, got: &str) -> History<EchoRequest, EchoResponse> {
+    let mut history = History::new();
+    history.invoke(process, EchoRequest::Echo { echo: sent.into() });
+    history.ok(process, EchoResponse::EchoOk { echo: got.into() });
+    history
+  }
+
+  #[test]
+  fn replies_must_echo_what_was_sent() {
+    assert_eq!(Echo.check(&echo(0, "hi", "hi")), Ok(()));
+    assert_eq!(
+      Echo.check(&echo(0, "hi", "hello")),
+      Err("Sent \"hi\" but got \"hello\" back".to_string())
+    );
+  }
+}
@@ -0,0 +1,172 @@
+use serde::{Deserialize, Serialize};
+
+use super::{final_operations, Workload};
+use crate::{check::History, rng::Rng, NetworkEntityId};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum GCounterRequest {
+  Add { delta: i64 },
+  Read,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum GCounterResponse {
+  AddOk,
+  ReadOk { value: i64 },
+}
+
+/// Maelstrom's `g-counter` workload. The counter only grows, and once the cluster has settled,
+/// every node must read the same value; the sum of the adds that succeeded, plus any of the ones
+/// that may have.
+#[derive(Debug, Default)]
+pub struct GCounter {
+  clients: usize,
+  final_reads: usize,
+}
+
+impl Workload for GCounter {
+  type Request = GCounterRequest;
+  type Response = GCounterResponse;
+
+  fn generate(&mut self, rng: &mut Rng) -> Self::Request {
+    if rng.chance(0.5) {
+      GCounterRequest::Add {
+        delta: rng.below(5) as i64,
+      }
+    } else {
+      GCounterRequest::Read
+    }
+  }
+
+  fn final_requests(
+    &mut self,
+    nodes: &[NetworkEntityId],
+    clients: usize,
+  ) -> Vec<(NetworkEntityId, Self::Request)> {
+    self.clients = clients;
+    self.final_reads = nodes.len();
+    nodes
+      .iter()
+      .map(|node| (node.clone(), GCounterRequest::Read))
+      .collect()
+  }
+
+  fn check(&self, history: &History<Self::Request, Self::Response>) -> Result<(), String> {
+    let ops = crate::check::operations(history).map_err(|e| e.to_string())?;
+    let (mut lower, mut upper) = (0, 0);
+    for op in &ops {
+      if let GCounterRequest::Add { delta } = op.op {
+        upper += delta;
+        if op.completed.is_some() {
+          lower += delta;
+        }
+      }
+    }
+    for op in &ops {
+      if let Some(GCounterResponse::ReadOk { value }) = op.ret {
+        if value < 0 || value > upper {
+          return Err(format!("Read {value}, but only {upper} was ever added"));
+        }
+      }
+    }
+
+    let mut settled: Option<(usize, i64)> = None;
+    for op in final_operations(&ops, self.clients, self.final_reads)? {
+      let Some(GCounterResponse::ReadOk { value }) = op.ret else {
+        return Err(format!(
+          "Final read by process {} didn't complete",
+          op.process
+        ));
+      };
+      if value < lower || value > upper {
+        return Err(format!(
+          "Final read by process {} was {value}, expected between {lower} and {upper}",
+          op.process
+        ));
+      }
+      match settled {
+        Some((process, other)) if other != value => {
+          return Err(format!(
+            "Final reads disagree; {value} by process {} and {other} by process {process}",
+            op.process
+          ))
+        }
+        _ => settled = Some((op.process, value)),
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  type Run = (GCounter, History<GCounterRequest, GCounterResponse>);
+
+  /// An add of 2 by one client that succeeded, and of 3 by the other that timed out, followed by
+  /// a final read on each of two nodes
+  fn run() -> Run {
+    let mut workload = GCounter::default();
+    workload.final_requests(&["n0".to_string(), "n1".to_string()], 2);
+    let mut history = History::new();
+    history.invoke(0, GCounterRequest::Add { delta: 2 });
+    history.invoke(1, GCounterRequest::Add { delta: 3 });
+    history.ok(0, GCounterResponse::AddOk);
+    history.info(1);
+    (workload, history)
+  }
+
+  fn final_reads((workload, mut history): Run, values: [i64; 2]) -> Result<(), String> {
+    for (process, value) in [2, 3].into_iter().zip(values) {
+      history.invoke(process, GCounterRequest::Read);
+      history.ok(process, GCounterResponse::ReadOk { value });
+    }
+    workload.check(&history)
+  }
+
+  #[test]
+  fn final_reads_must_agree_on_a_value_the_adds_explain() {
+    // the add that timed out may or may not have happened
+    assert_eq!(final_reads(run(), [2, 2]), Ok(()));
+    assert_eq!(final_reads(run(), [5, 5]), Ok(()));
+
+    assert_eq!(
+      final_reads(run(), [2, 5]),
+      Err("Final reads disagree; 5 by process 3 and 2 by process 2".to_string())
+    );
+    assert_eq!(
+      final_reads(run(), [1, 1]),
+      Err("Final read by process 2 was 1, expected between 2 and 5".to_string())
+    );
+  }
+
+  #[test]
+  fn reads_can_not_see_more_than_was_added() {
+    let (workload, mut history) = run();
+    history.invoke(0, GCounterRequest::Read);
+    history.ok(0, GCounterResponse::ReadOk { value: 6 });
+    assert_eq!(
+      final_reads((workload, history), [5, 5]),
+      Err("Read 6, but only 5 was ever added".to_string())
+    );
+  }
+
+  #[test]
+  fn every_final_read_must_complete() {
+    // the read by a client doesn't stand in for the final read on n1
+    let (workload, mut history) = run();
+    history.invoke(0, GCounterRequest::Read);
+    history.ok(0, GCounterResponse::ReadOk { value: 2 });
+    history.invoke(2, GCounterRequest::Read);
+    history.ok(2, GCounterResponse::ReadOk { value: 2 });
+    history.invoke(3, GCounterRequest::Read);
+    history.fail(3);
+    assert_eq!(
+      workload.check(&history),
+      Err("Final request by process 3 failed or was never made".to_string())
+    );
+  }
+}
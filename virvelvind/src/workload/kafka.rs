@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::Workload;
+use crate::{check::History, rng::Rng};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum KafkaRequest {
+  Send { key: String, msg: u64 },
+  Poll { offsets: HashMap<String, u64> },
+  CommitOffsets { offsets: HashMap<String, u64> },
+  ListCommittedOffsets { keys: Vec<String> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum KafkaResponse {
+  SendOk {
+    offset: u64,
+  },
+  /// Per key, `[offset, msg]` pairs
+  PollOk {
+    msgs: HashMap<String, Vec<(u64, u64)>>,
+  },
+  CommitOffsetsOk,
+  ListCommittedOffsetsOk {
+    offsets: HashMap<String, u64>,
+  },
+}
+
+/// Maelstrom's `kafka` workload; append-only logs, one per key. Every message is sent once, so it
+/// identifies the send. The check finds offsets handed out twice, polls returning messages that
+/// weren't sent to that key or at another offset than the send was acknowledged with, and lost
+/// writes; a send acknowledged before a poll was invoked, whose offset lies inside the range the
+/// poll returned, but that's missing from it. Offsets need not be contiguous.
+#[derive(Debug)]
+pub struct Kafka {
+  /// Number of keys (logs) to send to
+  pub keys: usize,
+  next_msg: u64,
+  /// How many sends were generated per key, to pick offsets to poll and commit from
+  sent: Vec<u64>,
+}
+
+impl Kafka {
+  pub fn new(keys: usize) -> Kafka {
+    let keys = keys.max(1);
+    Kafka {
+      keys,
+      next_msg: 0,
+      sent: vec![0; keys],
+    }
+  }
+
+  fn key(&self, idx: usize) -> String {
+    idx.to_string()
+  }
+}
+
+impl Default for Kafka {
+  fn default() -> Self {
+    Kafka::new(4)
+  }
+}
+
+impl Workload for Kafka {
+  type Request = KafkaRequest;
+  type Response = KafkaResponse;
+
+  fn generate(&mut self, rng: &mut Rng) -> Self::Request {
+    let idx = rng.below(self.keys as u64) as usize;
+    let key = self.key(idx);
+    let offset = rng.below(self.sent[idx] + 1);
+    match rng.below(10) {
+      0..=4 => {
+        self.next_msg += 1;
+        self.sent[idx] += 1;
+        KafkaRequest::Send {
+          key,
+          msg: self.next_msg,
+        }
+      }
+      5..=7 => KafkaRequest::Poll {
+        offsets: HashMap::from([(key, offset)]),
+      },
+      8 => KafkaRequest::CommitOffsets {
+        offsets: HashMap::from([(key, offset)]),
+      },
+      _ => KafkaRequest::ListCommittedOffsets { keys: vec![key] },
+    }
+  }
+
+  fn check(&self, history: &History<Self::Request, Self::Response>) -> Result<(), String> {
+    let ops = crate::check::operations(history).map_err(|e| e.to_string())?;
+    // msg -> key it was sent to, and the offset, if the send was acknowledged
+    let mut sends: HashMap<u64, (&str, Option<u64>)> = HashMap::new();
+    let mut offsets: HashMap<(&str, u64), u64> = HashMap::new();
+    for op in &ops {
+      let KafkaRequest::Send { key, msg } = &op.op else {
+        continue;
+      };
+      let offset = match op.ret {
+        Some(KafkaResponse::SendOk { offset }) => Some(offset),
+        _ => None,
+      };
+      sends.insert(*msg, (key, offset));
+      if let Some(offset) = offset {
+        if let Some(other) = offsets.insert((key, offset), *msg) {
+          return Err(format!(
+            "Messages {other} and {msg} were both sent to {key} at offset {offset}"
+          ));
+        }
+      }
+    }
+
+    for op in &ops {
+      let (KafkaRequest::Poll { offsets: from }, Some(KafkaResponse::PollOk { msgs })) =
+        (&op.op, &op.ret)
+      else {
+        continue;
+      };
+      for (key, polled) in msgs {
+        for window in polled.windows(2) {
+          if window[0].0 >= window[1].0 {
+            return Err(format!(
+              "Poll by process {} returned offsets of {key} out of order: {polled:?}",
+              op.process
+            ));
+          }
+        }
+        for (offset, msg) in polled {
+          match sends.get(msg) {
+            None => {
+              return Err(format!(
+                "Poll by process {} returned {msg} from {key}, which was never sent",
+                op.process
+              ))
+            }
+            Some((sent_to, _)) if sent_to != key => {
+              return Err(format!(
+                "Poll by process {} returned {msg} from {key}, but it was sent to {sent_to}",
+                op.process
+              ))
+            }
+            Some((_, Some(acked))) if acked != offset => {
+              return Err(format!(
+                "Poll by process {} returned {msg} at offset {offset} of {key}, but it was sent at {acked}",
+                op.process
+              ))
+            }
+            _ => {}
+          }
+        }
+
+        let (Some((first, _)), Some((last, _))) = (polled.first(), polled.last()) else {
+          continue;
+        };
+        let start = from.get(key).copied().unwrap_or(0).min(*first);
+        let lost = ops.iter().find_map(|send| {
+          let (KafkaRequest::Send { key: sent_to, msg }, Some(completed)) =
+            (&send.op, send.completed)
+          else {
+            return None;
+          };
+          let (_, Some(offset)) = sends[msg] else {
+            return None;
+          };
+          let covered = sent_to == key && (start..=*last).contains(&offset);
+          let missing = !polled.iter().any(|(o, _)| *o == offset);
+          (completed < op.invoked && covered && missing).then_some((msg, offset))
+        });
+        if let Some((msg, offset)) = lost {
+          return Err(format!(
+            "Lost write; {msg} was acknowledged at offset {offset} of {key}, before process {} polled {first}..={last} without it",
+            op.process
+          ));
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn send(key: &str, msg: u64) -> KafkaRequest {
+    KafkaRequest::Send {
+      key: key.into(),
+      msg,
+    }
+  }
+
+  fn poll(key: &str, from: u64) -> KafkaRequest {
+    KafkaRequest::Poll {
+      offsets: HashMap::from([(key.to_string(), from)]),
+    }
+  }
+
+  fn polled(key: &str, msgs: &[(u64, u64)]) -> KafkaResponse {
+    KafkaResponse::PollOk {
+      msgs: HashMap::from([(key.to_string(), msgs.to_vec())]),
+    }
+  }
+
+  /// Sends of 1 and 2 to key "a" acknowledged at offsets 0 and 2, and of 3 to "b" at 0
+  fn sent() -> History<KafkaRequest, KafkaResponse> {
+    let mut history = History::new();
+    for (msg, key, offset) in [(1, "a", 0), (2, "a", 2), (3, "b", 0)] {
+      history.invoke(0, send(key, msg));
+      history.ok(0, KafkaResponse::SendOk { offset });
+    }
+    history
+  }
+
+  fn check_poll(from: u64, msgs: &[(u64, u64)]) -> Result<(), String> {
+    let mut history = sent();
+    history.invoke(1, poll("a", from));
+    history.ok(1, polled("a", msgs));
+    Kafka::default().check(&history)
+  }
+
+  #[test]
+  fn polls_must_return_what_was_sent_where_it_was_sent() {
+    assert_eq!(check_poll(0, &[(0, 1), (2, 2)]), Ok(()));
+    assert_eq!(check_poll(1, &[(2, 2)]), Ok(()));
+
+    assert_eq!(
+      check_poll(0, &[(2, 2), (0, 1)]),
+      Err("Poll by process 1 returned offsets of a out of order: [(2, 2), (0, 1)]".into())
+    );
+    assert_eq!(
+      check_poll(0, &[(0, 1), (1, 4)]),
+      Err("Poll by process 1 returned 4 from a, which was never sent".into())
+    );
+    assert_eq!(
+      check_poll(0, &[(0, 1), (1, 3)]),
+      Err("Poll by process 1 returned 3 from a, but it was sent to b".into())
+    );
+    assert_eq!(
+      check_poll(0, &[(0, 1), (1, 2)]),
+      Err("Poll by process 1 returned 2 at offset 1 of a, but it was sent at 2".into())
+    );
+  }
+
+  #[test]
+  fn acknowledged_sends_must_not_be_lost() {
+    assert_eq!(
+      check_poll(0, &[(2, 2)]),
+      Err(
+        "Lost write; 1 was acknowledged at offset 0 of a, before process 1 polled 2..=2 without it"
+          .into()
+      )
+    );
+
+    // a send that's acknowledged after the poll was invoked may not be in it
+    let mut history = sent();
+    history.invoke(1, poll("a", 0));
+    history.invoke(0, send("a", 4));
+    history.ok(0, KafkaResponse::SendOk { offset: 1 });
+    history.ok(1, polled("a", &[(0, 1), (2, 2)]));
+    assert_eq!(Kafka::default().check(&history), Ok(()));
+  }
+
+  #[test]
+  fn offsets_are_handed_out_once() {
+    let mut history = sent();
+    history.invoke(1, send("a", 4));
+    history.ok(1, KafkaResponse::SendOk { offset: 2 });
+    assert_eq!(
+      Kafka::default().check(&history),
+      Err("Messages 2 and 4 were both sent to a at offset 2".into())
+    );
+  }
+}
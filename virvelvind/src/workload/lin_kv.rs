@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::{Outcome, Workload};
+use crate::{
+  check::{
+    model::{CasRegister, CasRegisterOp},
+    Entry, History,
+  },
+  rng::Rng,
+  ErrorCode, NodeError,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LinKvRequest {
+  Read { key: u64 },
+  Write { key: u64, value: u64 },
+  Cas { key: u64, from: u64, to: u64 },
+}
+
+impl LinKvRequest {
+  pub fn key(&self) -> u64 {
+    match self {
+      LinKvRequest::Read { key }
+      | LinKvRequest::Write { key, .. }
+      | LinKvRequest::Cas { key, .. } => *key,
+    }
+  }
+
+  fn register_op(&self) -> CasRegisterOp<u64> {
+    match *self {
+      LinKvRequest::Read { .. } => CasRegisterOp::Read,
+      LinKvRequest::Write { value, .. } => CasRegisterOp::Write(value),
+      LinKvRequest::Cas { from, to, .. } => CasRegisterOp::Cas { from, to },
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LinKvResponse {
+  /// `None` if the key doesn't exist
+  ReadOk {
+    value: Option<u64>,
+  },
+  WriteOk,
+  CasOk,
+}
+
+/// Maelstrom's `lin-kv` workload; reads, writes and compare-and-sets on a few keys, each of which
+/// must be a linearizable register. A read of a key that doesn't exist is recorded as reading
+/// nothing.
+#[derive(Debug)]
+pub struct LinKv {
+  /// Number of keys
+  pub keys: u64,
+  /// Values are picked from `0..values`; few values make for more compare-and-sets that succeed
+  pub values: u64,
+}
+
+impl Default for LinKv {
+  fn default() -> Self {
+    LinKv { keys: 4, values: 5 }
+  }
+}
+
+impl Workload for LinKv {
+  type Request = LinKvRequest;
+  type Response = LinKvResponse;
+
+  fn generate(&mut self, rng: &mut Rng) -> Self::Request {
+    let key = rng.below(self.keys.max(1));
+    let values = self.values.max(1);
+    match rng.below(3) {
+      0 => LinKvRequest::Read { key },
+      1 => LinKvRequest::Write {
+        key,
+        value: rng.below(values),
+      },
+      _ => LinKvRequest::Cas {
+        key,
+        from: rng.below(values),
+        to: rng.below(values),
+      },
+    }
+  }
+
+  fn on_error(&self, request: &Self::Request, error: &NodeError) -> Outcome<Self::Response> {
+    match (request, error.code) {
+      (LinKvRequest::Read { .. }, ErrorCode::KeyDoesNotExist) => {
+        Outcome::Ok(LinKvResponse::ReadOk { value: None })
+      }
+      (_, code) if code.is_definite() => Outcome::Fail,
+      _ => Outcome::Info,
+    }
+  }
+
+  fn check(&self, history: &History<Self::Request, Self::Response>) -> Result<(), String> {
+    // keys are independent registers, split the history and check each on its own
+    let mut registers: HashMap<u64, History<CasRegisterOp<u64>, Option<u64>>> = HashMap::new();
+    let mut keys: HashMap<usize, u64> = HashMap::new();
+    for entry in history.entries() {
+      let process = entry.process();
+      let key = match entry {
+        Entry::Invoke { op, .. } => {
+          keys.insert(process, op.key());
+          op.key()
+        }
+        _ => match keys.get(&process) {
+          Some(key) => *key,
+          None => {
+            return Err(format!(
+              "process {process} completed an operation it never invoked"
+            ))
+          }
+        },
+      };
+      let register = registers.entry(key).or_default();
+      match entry {
+        Entry::Invoke { op, .. } => register.invoke(process, op.register_op()),
+        Entry::Ok { ret, .. } => {
+          let read = match ret {
+            LinKvResponse::ReadOk { value } => *value,
+            _ => None,
+          };
+          register.ok(process, read)
+        }
+        Entry::Fail { .. } => register.fail(process),
+        Entry::Info { .. } => register.info(process),
+      }
+    }
+
+    let mut keys: Vec<_> = registers.keys().copied().collect();
+    keys.sort_unstable();
+    for key in keys {
+      crate::check::linearizable(&CasRegister(None), &registers[&key])
+        .map_err(|e| format!("key {key}: {e}"))?;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  type Ops = [(LinKvRequest, LinKvResponse)];
+
+  /// Each operation by a process of its own, one after the other
+  fn sequential(ops: &Ops) -> History<LinKvRequest, LinKvResponse> {
+    let mut history = History::new();
+    for (process, (request, response)) in ops.iter().enumerate() {
+      history.invoke(process, request.clone());
+      history.ok(process, response.clone());
+    }
+    history
+  }
+
+  fn read(key: u64, value: Option<u64>) -> (LinKvRequest, LinKvResponse) {
+    (LinKvRequest::Read { key }, LinKvResponse::ReadOk { value })
+  }
+
+  fn write(key: u64, value: u64) -> (LinKvRequest, LinKvResponse) {
+    (LinKvRequest::Write { key, value }, LinKvResponse::WriteOk)
+  }
+
+  fn cas(key: u64, from: u64, to: u64) -> (LinKvRequest, LinKvResponse) {
+    (LinKvRequest::Cas { key, from, to }, LinKvResponse::CasOk)
+  }
+
+  #[test]
+  fn every_key_must_be_a_linearizable_register() {
+    let history = sequential(&[
+      read(0, None),
+      write(0, 1),
+      write(1, 2),
+      cas(0, 1, 3),
+      read(0, Some(3)),
+      read(1, Some(2)),
+    ]);
+    assert_eq!(LinKv::default().check(&history), Ok(()));
+
+    // key 0 is fine, the read of key 1 is stale
+    let history = sequential(&[write(0, 1), write(1, 1), write(1, 2), read(1, Some(1))]);
+    let error = LinKv::default().check(&history).unwrap_err();
+    assert!(error.starts_with("key 1: not linearizable"), "{error}");
+
+    let history = sequential(&[write(0, 1), cas(0, 2, 3)]);
+    let error = LinKv::default().check(&history).unwrap_err();
+    assert!(error.starts_with("key 0: not linearizable"), "{error}");
+  }
+
+  #[test]
+  fn writes_that_timed_out_may_or_may_not_have_happened() {
+    let mut history = History::new();
+    history.invoke(0, LinKvRequest::Write { key: 0, value: 1 });
+    history.info(0);
+    history.invoke(1, LinKvRequest::Read { key: 0 });
+    history.ok(1, LinKvResponse::ReadOk { value: Some(1) });
+    assert_eq!(LinKv::default().check(&history), Ok(()));
+
+    let mut history = History::new();
+    history.invoke(0, LinKvRequest::Write { key: 0, value: 1 });
+    history.fail(0);
+    history.invoke(1, LinKvRequest::Read { key: 0 });
+    history.ok(1, LinKvResponse::ReadOk { value: Some(1) });
+    assert!(LinKv::default().check(&history).is_err());
+  }
+
+  #[test]
+  fn reads_of_keys_that_do_not_exist_read_nothing() {
+    let workload = LinKv::default();
+    let missing = NodeError::new(ErrorCode::KeyDoesNotExist, "no such key");
+    assert_eq!(
+      workload.on_error(&LinKvRequest::Read { key: 0 }, &missing),
+      Outcome::Ok(LinKvResponse::ReadOk { value: None })
+    );
+    let cas = LinKvRequest::Cas {
+      key: 0,
+      from: 1,
+      to: 2,
+    };
+    assert_eq!(workload.on_error(&cas, &missing), Outcome::Fail);
+    let timeout = NodeError::timeout("no reply");
+    assert_eq!(workload.on_error(&cas, &timeout), Outcome::Info);
+  }
+}
@@ -0,0 +1,310 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use super::Workload;
+use crate::{
+  check::{Entry, History},
+  rng::Rng,
+};
+
+/// One read or write of a transaction. On the wire it's `["r", key, null]`, or `["r", key, value]`
+/// once read, and `["w", key, value]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(
+  into = "(String, u64, Option<u64>)",
+  try_from = "(String, u64, Option<u64>)"
+)]
+pub enum MicroOp {
+  Read { key: u64, value: Option<u64> },
+  Write { key: u64, value: u64 },
+}
+
+impl MicroOp {
+  pub fn key(&self) -> u64 {
+    match self {
+      MicroOp::Read { key, .. } | MicroOp::Write { key, .. } => *key,
+    }
+  }
+}
+
+impl From<MicroOp> for (String, u64, Option<u64>) {
+  fn from(op: MicroOp) -> Self {
+    match op {
+      MicroOp::Read { key, value } => ("r".into(), key, value),
+      MicroOp::Write { key, value } => ("w".into(), key, Some(value)),
+    }
+  }
+}
+
+impl TryFrom<(String, u64, Option<u64>)> for MicroOp {
+  type Error = String;
+
+  fn try_from((f, key, value): (String, u64, Option<u64>)) -> Result<Self, Self::Error> {
+    match (f.as_str(), value) {
+      ("r", value) => Ok(MicroOp::Read { key, value }),
+      ("w", Some(value)) => Ok(MicroOp::Write { key, value }),
+      ("w", None) => Err(format!("write of {key} without a value")),
+      _ => Err(format!("unknown micro-op {f}")),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TxnRequest {
+  Txn { txn: Vec<MicroOp> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TxnResponse {
+  TxnOk { txn: Vec<MicroOp> },
+}
+
+/// Maelstrom's `txn-rw-register` workload. Every write writes a unique value, so a read tells which
+/// transaction it observed. This is not a full Elle check, it finds the anomalies that can be seen
+/// without building a dependency graph; replies that don't match the request, transactions that
+/// don't read their own writes, and reads of values that were never written, written by a
+/// transaction that failed (G1a) or overwritten within the transaction that wrote it (G1b).
+#[derive(Debug)]
+pub struct TxnRwRegister {
+  /// Number of keys transactions pick from
+  pub keys: u64,
+  /// Most micro-ops in one transaction
+  pub max_txn_length: usize,
+  next_value: u64,
+}
+
+impl TxnRwRegister {
+  pub fn new(keys: u64, max_txn_length: usize) -> TxnRwRegister {
+    TxnRwRegister {
+      keys: keys.max(1),
+      max_txn_length: max_txn_length.max(1),
+      next_value: 0,
+    }
+  }
+}
+
+impl Default for TxnRwRegister {
+  fn default() -> Self {
+    TxnRwRegister::new(10, 4)
+  }
+}
+
+impl Workload for TxnRwRegister {
+  type Request = TxnRequest;
+  type Response = TxnResponse;
+
+  fn generate(&mut self, rng: &mut Rng) -> Self::Request {
+    let len = 1 + rng.below(self.max_txn_length as u64) as usize;
+    let txn = (0..len)
+      .map(|_| {
+        let key = rng.below(self.keys);
+        if rng.chance(0.5) {
+          MicroOp::Read { key, value: None }
+        } else {
+          self.next_value += 1;
+          MicroOp::Write {
+            key,
+            value: self.next_value,
+          }
+        }
+      })
+      .collect();
+    TxnRequest::Txn { txn }
+  }
+
+  fn check(&self, history: &History<Self::Request, Self::Response>) -> Result<(), String> {
+    let ops = crate::check::operations(history).map_err(|e| e.to_string())?;
+    // (key, value) -> whether it's the last write of the key in its transaction
+    let mut written: HashMap<(u64, u64), bool> = HashMap::new();
+    for op in &ops {
+      let TxnRequest::Txn { txn } = &op.op;
+      for (idx, micro) in txn.iter().enumerate() {
+        if let MicroOp::Write { key, value } = micro {
+          let last = !txn[idx + 1..]
+            .iter()
+            .any(|m| matches!(m, MicroOp::Write { key: k, .. } if k == key));
+          written.insert((*key, *value), last);
+        }
+      }
+    }
+    // operations leaves out the transactions that failed, the history still has them
+    let failed: HashSet<(u64, u64)> = history
+      .entries()
+      .iter()
+      .filter_map(|entry| match entry {
+        Entry::Invoke {
+          op: TxnRequest::Txn { txn },
+          ..
+        } => Some(txn),
+        _ => None,
+      })
+      .flatten()
+      .filter_map(|micro| match micro {
+        MicroOp::Write { key, value } => Some((*key, *value)),
+        MicroOp::Read { .. } => None,
+      })
+      .filter(|write| !written.contains_key(write))
+      .collect();
+
+    for op in &ops {
+      let (TxnRequest::Txn { txn: request }, Some(TxnResponse::TxnOk { txn: reply })) =
+        (&op.op, &op.ret)
+      else {
+        continue;
+      };
+      let matches = request.len() == reply.len()
+        && request
+          .iter()
+          .zip(reply)
+          .all(|(req, rep)| match (req, rep) {
+            (MicroOp::Read { key, .. }, MicroOp::Read { key: k, .. }) => key == k,
+            (write, reply) => write == reply,
+          });
+      if !matches {
+        return Err(format!(
+          "Process {} asked for {request:?}, but the reply was {reply:?}",
+          op.process
+        ));
+      }
+
+      // what this transaction wrote to each key so far
+      let mut own: HashMap<u64, u64> = HashMap::new();
+      for micro in reply {
+        match *micro {
+          MicroOp::Write { key, value } => {
+            own.insert(key, value);
+          }
+          MicroOp::Read { key, value } => {
+            if let Some(expected) = own.get(&key) {
+              if value != Some(*expected) {
+                return Err(format!(
+                  "Process {} wrote {expected} to {key}, then read {value:?} in the same transaction: {reply:?}",
+                  op.process
+                ));
+              }
+              continue;
+            }
+            let Some(value) = value else {
+              continue;
+            };
+            let reason = if failed.contains(&(key, value)) {
+              "G1a; it was written by a transaction that failed"
+            } else {
+              match written.get(&(key, value)) {
+                None => "it was never written",
+                Some(false) => "G1b; it was overwritten by the transaction that wrote it",
+                Some(true) => continue,
+              }
+            };
+            return Err(format!(
+              "Process {} read {value} from {key}, but {reason}: {reply:?}",
+              op.process
+            ));
+          }
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn r(key: u64, value: Option<u64>) -> MicroOp {
+    MicroOp::Read { key, value }
+  }
+
+  fn w(key: u64, value: u64) -> MicroOp {
+    MicroOp::Write { key, value }
+  }
+
+  /// Requests and what they were answered with, each by a process of its own; `None` is a
+  /// transaction that failed. Reads in the requests are unanswered.
+  fn check(txns: &[(Vec<MicroOp>, Option<Vec<MicroOp>>)]) -> Result<(), String> {
+    let mut history = History::new();
+    for (process, (request, reply)) in txns.iter().enumerate() {
+      let request = request
+        .iter()
+        .map(|micro| match *micro {
+          MicroOp::Read { key, .. } => r(key, None),
+          write => write,
+        })
+        .collect();
+      history.invoke(process, TxnRequest::Txn { txn: request });
+      match reply {
+        Some(txn) => history.ok(process, TxnResponse::TxnOk { txn: txn.clone() }),
+        None => history.fail(process),
+      }
+    }
+    TxnRwRegister::default().check(&history)
+  }
+
+  fn ok(txn: Vec<MicroOp>) -> (Vec<MicroOp>, Option<Vec<MicroOp>>) {
+    (txn.clone(), Some(txn))
+  }
+
+  #[test]
+  fn reads_must_observe_committed_writes() {
+    assert_eq!(
+      check(&[
+        ok(vec![w(0, 1), w(1, 2)]),
+        ok(vec![r(0, Some(1)), r(1, Some(2)), r(2, None)]),
+        ok(vec![w(0, 3), r(0, Some(3))]),
+      ]),
+      Ok(())
+    );
+
+    assert_eq!(
+      check(&[ok(vec![w(0, 1)]), ok(vec![r(0, Some(2))])]),
+      Err(
+        "Process 1 read 2 from 0, but it was never written: [Read { key: 0, value: Some(2) }]"
+          .into()
+      )
+    );
+    assert_eq!(
+      check(&[(vec![w(0, 1)], None), ok(vec![r(0, Some(1))])]),
+      Err(
+        "Process 1 read 1 from 0, but G1a; it was written by a transaction that failed: \
+         [Read { key: 0, value: Some(1) }]"
+          .into()
+      )
+    );
+    assert_eq!(
+      check(&[ok(vec![w(0, 1), w(0, 2)]), ok(vec![r(0, Some(1))])]),
+      Err(
+        "Process 1 read 1 from 0, but G1b; it was overwritten by the transaction that wrote it: \
+         [Read { key: 0, value: Some(1) }]"
+          .into()
+      )
+    );
+  }
+
+  #[test]
+  fn transactions_must_read_their_own_writes() {
+    assert_eq!(
+      check(&[ok(vec![w(0, 1)]), ok(vec![w(0, 2), r(0, Some(1))])]),
+      Err(
+        "Process 1 wrote 2 to 0, then read Some(1) in the same transaction: \
+         [Write { key: 0, value: 2 }, Read { key: 0, value: Some(1) }]"
+          .into()
+      )
+    );
+  }
+
+  #[test]
+  fn replies_must_match_their_request() {
+    assert_eq!(
+      check(&[(vec![w(0, 1), r(1, None)], Some(vec![w(0, 1), r(2, None)]))]),
+      Err(
+        "Process 0 asked for [Write { key: 0, value: 1 }, Read { key: 1, value: None }], but the \
+         reply was [Write { key: 0, value: 1 }, Read { key: 2, value: None }]"
+          .into()
+      )
+    );
+  }
+}
@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::Workload;
+use crate::{check::History, rng::Rng};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum UniqueIdsRequest {
+  Generate,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum UniqueIdsResponse {
+  /// Ids can be of any type
+  GenerateOk { id: serde_json::Value },
+}
+
+/// Maelstrom's `unique-ids` workload; no id may be handed out twice.
+#[derive(Debug, Default)]
+pub struct UniqueIds;
+
+impl Workload for UniqueIds {
+  type Request = UniqueIdsRequest;
+  type Response = UniqueIdsResponse;
+
+  fn generate(&mut self, _rng: &mut Rng) -> Self::Request {
+    UniqueIdsRequest::Generate
+  }
+
+  fn check(&self, history: &History<Self::Request, Self::Response>) -> Result<(), String> {
+    let ops = crate::check::operations(history).map_err(|e| e.to_string())?;
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for op in ops {
+      let Some(UniqueIdsResponse::GenerateOk { id }) = &op.ret else {
+        continue;
+      };
+      if let Some(first) = seen.insert(id.to_string(), op.process) {
+        return Err(format!(
+          "Id {id} was handed out to both process {first} and {}",
+          op.process
+        ));
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+
+  fn generated(ids: &[serde_json::Value]) -> History<UniqueIdsRequest, UniqueIdsResponse> {
+    let mut history = History::new();
+    for (process, id) in ids.iter().enumerate() {
+      history.invoke(process, UniqueIdsRequest::Generate);
+      history.ok(process, UniqueIdsResponse::GenerateOk { id: id.clone() });
+    }
+    history
+  }
+
+  #[test]
+  fn ids_must_be_unique() {
+    // ids are compared as JSON, so 1 and "1" are different ids
+    let history = generated(&[json!(1), json!("1"), json!("n0-1")]);
+    assert_eq!(UniqueIds.check(&history), Ok(()));
+
+    let history = generated(&[json!("n0-1"), json!("n1-1"), json!("n0-1")]);
+    assert_eq!(
+      UniqueIds.check(&history),
+      Err("Id \"n0-1\" was handed out to both process 0 and 2".to_string())
+    );
+  }
+}
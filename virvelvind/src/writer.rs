@@ -0,0 +1,352 @@
+//! Writing messages to the node's output. Messages are serialized straight into a buffer, one
+//! per line, and the buffer is written out according to a [`FlushPolicy`]:
+//!
+//! - `message`: after every message
+//! - `tick`: after everything the event loop sent in one go (or a message sent through an
+//!   [`Outbound`](crate::Outbound) handle)
+//! - `idle` (the default): once there's nothing more waiting to be written
+//!
+//! Output is always flushed before the writer waits for more, so no policy holds messages back;
+//! they only differ in how many writes it takes. [`serve`](crate::serve) reads the policy from
+//! the `VIRVELVIND_FLUSH` environment variable.
+
+use std::{
+  io::{self, BufWriter, Write},
+  str::FromStr,
+  sync::mpsc::Receiver,
+};
+
+use serde::Serialize;
+
+use crate::res::MaelstromResponse;
+
+/// The environment variable the flush policy is read from
+pub const FLUSH_ENV: &str = "VIRVELVIND_FLUSH";
+
+/// When buffered output is written out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FlushPolicy {
+  Message,
+  Tick,
+  #[default]
+  Idle,
+}
+
+impl FlushPolicy {
+  /// The policy set by `VIRVELVIND_FLUSH`, `idle` if it isn't set or can't be parsed.
+  pub fn from_env() -> FlushPolicy {
+    std::env::var(FLUSH_ENV)
+      .ok()
+      .and_then(|policy| policy.parse().ok())
+      .unwrap_or_default()
+  }
+}
+
+impl FromStr for FlushPolicy {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<FlushPolicy, String> {
+    match s.trim().to_ascii_lowercase().as_str() {
+      "message" => Ok(FlushPolicy::Message),
+      "tick" => Ok(FlushPolicy::Tick),
+      "idle" => Ok(FlushPolicy::Idle),
+      _ => Err(format!("Unknown flush policy: {s}")),
+    }
+  }
+}
+
+/// Why a message couldn't be written.
+#[derive(Debug)]
+pub enum WriteError {
+  Serialize(serde_json::Error),
+  Io(io::Error),
+}
+
+impl From<io::Error> for WriteError {
+  fn from(err: io::Error) -> WriteError {
+    WriteError::Io(err)
+  }
+}
+
+impl From<serde_json::Error> for WriteError {
+  fn from(err: serde_json::Error) -> WriteError {
+    if err.is_io() {
+      WriteError::Io(err.into())
+    } else {
+      WriteError::Serialize(err)
+    }
+  }
+}
+
+impl std::fmt::Display for WriteError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      WriteError::Serialize(err) => write!(f, "couldn't serialize message: {err}"),
+      WriteError::Io(err) => write!(f, "couldn't write message: {err}"),
+    }
+  }
+}
+
+impl std::error::Error for WriteError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      WriteError::Serialize(err) => Some(err),
+      WriteError::Io(err) => Some(err),
+    }
+  }
+}
+
+/// Serialize `msg` into `output`, followed by a newline.
+pub fn write_line<T: Serialize, W: Write>(
+  output: &mut W,
+  msg: &MaelstromResponse<T>,
+) -> Result<(), WriteError> {
+  serde_json::to_writer(&mut *output, msg)?;
+  output.write_all(b"\n")?;
+  Ok(())
+}
+
+/// Buffers messages written to `W`, and writes them out according to its [`FlushPolicy`].
+pub struct MessageWriter<W: Write> {
+  output: BufWriter<W>,
+  policy: FlushPolicy,
+}
+
+impl<W: Write> MessageWriter<W> {
+  pub fn new(output: W, policy: FlushPolicy) -> MessageWriter<W> {
+    MessageWriter {
+      output: BufWriter::new(output),
+      policy,
+    }
+  }
+
+  pub fn policy(&self) -> FlushPolicy {
+    self.policy
+  }
+
+  pub fn write_message<T: Serialize>(
+    &mut self,
+    msg: &MaelstromResponse<T>,
+  ) -> Result<(), WriteError> {
+    write_line(&mut self.output, msg)?;
+    if self.policy == FlushPolicy::Message {
+      self.output.flush()?;
+    }
+    Ok(())
+  }
+
+  /// Write messages that have already been serialized, as complete, newline terminated lines.
+  pub fn write_lines(&mut self, lines: &[u8]) -> io::Result<()> {
+    if self.policy != FlushPolicy::Message {
+      return self.output.write_all(lines);
+    }
+    for line in lines.split_inclusive(|b| *b == b'\n') {
+      self.output.write_all(line)?;
+      self.output.flush()?;
+    }
+    Ok(())
+  }
+
+  /// Everything sent in one go has been written.
+  pub fn end_of_tick(&mut self) -> io::Result<()> {
+    match self.policy {
+      FlushPolicy::Message | FlushPolicy::Tick => self.output.flush(),
+      FlushPolicy::Idle => Ok(()),
+    }
+  }
+
+  /// Write out everything buffered, whatever the policy.
+  pub fn flush(&mut self) -> io::Result<()> {
+    self.output.flush()
+  }
+}
+
+/// Write the chunks of lines received on `rx` until an empty chunk is received or every sender
+/// is gone.
+pub(crate) fn drain<W: Write>(
+  writer: &mut MessageWriter<W>,
+  rx: Receiver<Vec<u8>>,
+) -> io::Result<()> {
+  while let Ok(mut chunk) = rx.recv() {
+    loop {
+      if chunk.is_empty() {
+        return writer.flush();
+      }
+      writer.write_lines(&chunk)?;
+      writer.end_of_tick()?;
+      match rx.try_recv() {
+        Ok(next) => chunk = next,
+        Err(_) => break,
+      }
+    }
+    writer.flush()?;
+  }
+  writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{collections::BTreeMap, sync::mpsc};
+
+  use serde_json::json;
+
+  use super::*;
+  use crate::res::ResponseBody;
+
+  /// Keeps what reaches it, and how much of it had when it was flushed each time.
+  #[derive(Default)]
+  struct Sink {
+    written: Vec<u8>,
+    flushed: Vec<usize>,
+  }
+
+  impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+      self.written.extend_from_slice(buf);
+      Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+      self.flushed.push(self.written.len());
+      Ok(())
+    }
+  }
+
+  struct Closed;
+
+  impl Write for Closed {
+    fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+      Err(io::ErrorKind::BrokenPipe.into())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+      Ok(())
+    }
+  }
+
+  fn writer(policy: FlushPolicy) -> MessageWriter<Sink> {
+    MessageWriter::new(Sink::default(), policy)
+  }
+
+  fn sink(writer: &MessageWriter<Sink>) -> &Sink {
+    writer.output.get_ref()
+  }
+
+  const LINES: &[u8] = b"{\"n\":1}\n{\"n\":2}\n";
+
+  #[test]
+  fn policies_are_read_by_name() {
+    assert_eq!("message".parse(), Ok(FlushPolicy::Message));
+    assert_eq!(" Tick\n".parse(), Ok(FlushPolicy::Tick));
+    assert_eq!("IDLE".parse(), Ok(FlushPolicy::Idle));
+    assert!("never".parse::<FlushPolicy>().is_err());
+
+    // nothing else in the tests reads the variable
+    std::env::set_var(FLUSH_ENV, "tick");
+    assert_eq!(FlushPolicy::from_env(), FlushPolicy::Tick);
+    std::env::set_var(FLUSH_ENV, "never");
+    assert_eq!(FlushPolicy::from_env(), FlushPolicy::Idle);
+    std::env::remove_var(FLUSH_ENV);
+    assert_eq!(FlushPolicy::from_env(), FlushPolicy::Idle);
+  }
+
+  #[test]
+  fn message_policy_flushes_every_line() {
+    let mut writer = writer(FlushPolicy::Message);
+    writer.write_lines(LINES).unwrap();
+    assert_eq!(sink(&writer).flushed, [8, 16]);
+    let msg = MaelstromResponse {
+      src: "n0".to_string(),
+      dest: "c1".to_string(),
+      body: ResponseBody::uni_dir(json!({ "type": "ok" })),
+    };
+    writer.write_message(&msg).unwrap();
+    assert_eq!(sink(&writer).flushed.len(), 3);
+    assert_eq!(
+      sink(&writer).written.len(),
+      *sink(&writer).flushed.last().unwrap()
+    );
+  }
+
+  #[test]
+  fn tick_policy_flushes_at_the_end_of_a_tick() {
+    let mut writer = writer(FlushPolicy::Tick);
+    writer.write_lines(LINES).unwrap();
+    writer.write_lines(LINES).unwrap();
+    assert!(sink(&writer).written.is_empty());
+    writer.end_of_tick().unwrap();
+    assert_eq!(sink(&writer).flushed, [32]);
+  }
+
+  #[test]
+  fn idle_policy_leaves_flushing_to_the_writer_thread() {
+    let mut writer = writer(FlushPolicy::Idle);
+    writer.write_lines(LINES).unwrap();
+    writer.end_of_tick().unwrap();
+    assert!(sink(&writer).written.is_empty());
+    writer.flush().unwrap();
+    assert_eq!(sink(&writer).flushed, [16]);
+  }
+
+  #[test]
+  fn drain_flushes_once_nothing_is_waiting() {
+    let (tx, rx) = mpsc::channel();
+    tx.send(LINES.to_vec()).unwrap();
+    tx.send(LINES.to_vec()).unwrap();
+    drop(tx);
+    let mut idle = writer(FlushPolicy::Idle);
+    drain(&mut idle, rx).unwrap();
+    // both chunks were waiting, so they're written out together
+    assert_eq!(sink(&idle).flushed[0], 32);
+
+    let (tx, rx) = mpsc::channel();
+    tx.send(LINES.to_vec()).unwrap();
+    tx.send(LINES.to_vec()).unwrap();
+    drop(tx);
+    let mut tick = writer(FlushPolicy::Tick);
+    drain(&mut tick, rx).unwrap();
+    assert_eq!(sink(&tick).flushed[..2], [16, 32]);
+  }
+
+  #[test]
+  fn an_empty_chunk_stops_the_writer() {
+    let (tx, rx) = mpsc::channel();
+    tx.send(LINES.to_vec()).unwrap();
+    tx.send(Vec::new()).unwrap();
+    tx.send(b"{\"n\":3}\n".to_vec()).unwrap();
+    let mut writer = writer(FlushPolicy::Idle);
+    drain(&mut writer, rx).unwrap();
+    // what was sent before it is written, but nothing after
+    assert_eq!(sink(&writer).written, LINES);
+    assert_eq!(sink(&writer).flushed.last(), Some(&16));
+    assert!(tx.send(Vec::new()).is_err());
+  }
+
+  #[test]
+  fn write_errors_are_returned() {
+    let (tx, rx) = mpsc::channel();
+    tx.send(LINES.to_vec()).unwrap();
+    let mut closed = MessageWriter::new(Closed, FlushPolicy::Tick);
+    let err = drain(&mut closed, rx).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+
+    let mut closed = MessageWriter::new(Closed, FlushPolicy::Message);
+    let msg = MaelstromResponse {
+      src: "n0".to_string(),
+      dest: "c1".to_string(),
+      body: ResponseBody::uni_dir(json!({ "type": "ok" })),
+    };
+    let err = closed.write_message(&msg).unwrap_err();
+    assert!(matches!(err, WriteError::Io(ref e) if e.kind() == io::ErrorKind::BrokenPipe));
+
+    // JSON object keys must be strings
+    let unserializable = MaelstromResponse {
+      src: "n0".to_string(),
+      dest: "c1".to_string(),
+      body: ResponseBody::uni_dir(BTreeMap::from([((1, 2), 3)])),
+    };
+    let mut writer = writer(FlushPolicy::Message);
+    let err = writer.write_message(&unserializable).unwrap_err();
+    assert!(matches!(err, WriteError::Serialize(_)), "{err}");
+  }
+}
@@ -0,0 +1,45 @@
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use virvelvind::{history::OpType, sim::Simulation, Context, Event, Node, NodeError};
+
+/// Answers `read`s and tells `n1` about them, all through its `Outbound` handle.
+struct Relay;
+
+impl Node<Value> for Relay {
+  fn handle(&mut self, evt: Event<Value>, ctx: &mut Context<Self>) -> Result<(), NodeError> {
+    let Event::IOEvent(msg) = evt else {
+      return Ok(());
+    };
+    if msg.body.data["type"] != "read" {
+      return Ok(());
+    }
+    let outbound = ctx.outbound();
+    outbound.send("n1", json!({ "type": "relayed" }))?;
+    outbound.reply(
+      &msg.src,
+      msg.body.msg_id,
+      json!({ "type": "read_ok", "value": 1 }),
+    )
+  }
+}
+
+#[test]
+fn messages_sent_through_an_outbound_are_accounted_for() {
+  let mut sim = Simulation::new(1, 2, |_| Relay).record_history();
+  let read = sim.client_request("c1", "n0", json!({ "type": "read" }));
+  sim.run_for(Duration::from_millis(10));
+
+  let reply = sim.reply_to("c1", read).expect("a reply");
+  let metrics = sim.metrics("n0").unwrap();
+  assert_eq!(metrics.counter("messages_sent", &[("type", "relayed")]), 1);
+  assert_eq!(metrics.counter("messages_sent", &[("type", "read_ok")]), 1);
+  assert_eq!(metrics.counter("messages_sent_to", &[("peer", "c1")]), 1);
+  let relayed = json!({ "src": "n0", "dest": "n1", "body": { "type": "relayed" } });
+  // both lines, and their newlines
+  let bytes = relayed.to_string().len() + reply.line.len() + 2;
+  assert_eq!(metrics.counter("bytes_sent", &[]), bytes as u64);
+
+  let ops: Vec<_> = sim.history().ops().iter().map(|op| op.op_type).collect();
+  assert_eq!(ops, [OpType::Invoke, OpType::Ok]);
+}
@@ -0,0 +1,161 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use virvelvind::{
+  req::MaelstromRequest, sim::Simulation, Context, ErrorCode, Event, Module, Node, NodeError,
+  Router,
+};
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Counter {
+  Add { delta: u64 },
+  Read,
+}
+
+/// Keeps a counter.
+#[derive(Default)]
+struct Counting {
+  value: u64,
+}
+
+impl<N: 'static> Module<N> for Counting {
+  fn register(router: &mut Router<N>, state: fn(&mut N) -> &mut Self) {
+    let handler = move |node: &mut N, msg: MaelstromRequest<Counter>, ctx: &mut Context<N>| {
+      let counter = state(node);
+      let body = match msg.body.data {
+        Counter::Add { delta } => {
+          counter.value += delta;
+          json!({ "type": "add_ok" })
+        }
+        Counter::Read => json!({ "type": "read_ok", "value": counter.value }),
+      };
+      ctx.reply(&msg.src, msg.body.msg_id, body)
+    };
+    router.on("add", handler).on("read", handler);
+  }
+}
+
+#[derive(Deserialize)]
+struct Note {
+  text: String,
+}
+
+/// Remembers what it's told.
+#[derive(Default)]
+struct Notes {
+  notes: Vec<String>,
+}
+
+impl<N: 'static> Module<N> for Notes {
+  fn register(router: &mut Router<N>, state: fn(&mut N) -> &mut Self) {
+    router.on("note", move |node, msg: MaelstromRequest<Note>, ctx| {
+      state(node).notes.push(msg.body.data.text);
+      ctx.reply(&msg.src, msg.body.msg_id, json!({ "type": "note_ok" }))
+    });
+  }
+}
+
+#[derive(Default)]
+struct MixedNode {
+  counter: Counting,
+  notes: Notes,
+  pings: usize,
+}
+
+impl Node<Value> for MixedNode {
+  fn handle(&mut self, _: Event<Value>, _: &mut Context<Self>) -> Result<(), NodeError> {
+    Ok(())
+  }
+}
+
+fn router() -> Router<MixedNode> {
+  let mut router = Router::new();
+  router
+    .mount(|node: &mut MixedNode| &mut node.counter)
+    .mount(|node: &mut MixedNode| &mut node.notes)
+    .on("ping", |node, msg: MaelstromRequest<Value>, ctx| {
+      node.pings += 1;
+      ctx.reply(&msg.src, msg.body.msg_id, json!({ "type": "pong" }))
+    });
+  router
+}
+
+fn simulation() -> Simulation<MixedNode, Value> {
+  Simulation::new(1, 1, |_| MixedNode::default()).router(router())
+}
+
+fn reply(sim: &Simulation<MixedNode, Value>, msg_id: usize) -> Value {
+  let reply = sim.reply_to("c1", msg_id).expect("a reply");
+  serde_json::from_str::<Value>(&reply.line).unwrap()["body"].clone()
+}
+
+#[test]
+fn messages_are_dispatched_to_the_handler_for_their_type() {
+  let mut sim = simulation();
+  let ping = sim.client_request("c1", "n0", json!({ "type": "ping" }));
+  let add = sim.client_request("c1", "n0", json!({ "type": "add", "delta": 3 }));
+  let read = sim.client_request("c1", "n0", json!({ "type": "read" }));
+  sim.run_for(Duration::from_millis(10));
+
+  assert_eq!(reply(&sim, ping)["type"], "pong");
+  assert_eq!(reply(&sim, add)["type"], "add_ok");
+  assert_eq!(reply(&sim, read)["value"], 3);
+  assert_eq!(sim.node("n0").unwrap().pings, 1);
+}
+
+#[test]
+fn types_without_a_handler_are_not_supported() {
+  let mut sim = simulation();
+  let unknown = sim.client_request("c1", "n0", json!({ "type": "delete" }));
+  let malformed = sim.client_request("c1", "n0", json!({ "type": "add", "delta": "three" }));
+  sim.run_for(Duration::from_millis(10));
+
+  let unknown = reply(&sim, unknown);
+  assert_eq!(unknown["type"], "error");
+  assert_eq!(unknown["code"], ErrorCode::NotSupported.code());
+  let malformed = reply(&sim, malformed);
+  assert_eq!(malformed["code"], ErrorCode::MalformedRequest.code());
+  assert_eq!(sim.node("n0").unwrap().counter.value, 0);
+
+  // counted like the messages a service type rejects
+  let metrics = sim.metrics("n0").unwrap();
+  let rejected =
+    |kind, reason| metrics.counter("rejected_messages", &[("type", kind), ("reason", reason)]);
+  assert_eq!(rejected("delete", "not_supported"), 1);
+  assert_eq!(rejected("add", "malformed"), 1);
+}
+
+#[test]
+fn modules_keep_their_state_in_the_node() {
+  let mut sim = simulation();
+  sim.client_request("c1", "n0", json!({ "type": "note", "text": "hello" }));
+  sim.client_request("c1", "n0", json!({ "type": "add", "delta": 2 }));
+  sim.client_request("c1", "n0", json!({ "type": "add", "delta": 5 }));
+  sim.run_for(Duration::from_millis(10));
+
+  let node = sim.node("n0").unwrap();
+  assert_eq!(node.notes.notes, ["hello"]);
+  assert_eq!(node.counter.value, 7);
+
+  let mut router = router();
+  assert!(["add", "read", "note", "ping"]
+    .iter()
+    .all(|t| router.handles(t)));
+  // routers built separately can be merged, as long as they handle different types
+  let mut other = Router::new();
+  other.on(
+    "pong",
+    |_: &mut MixedNode, _: MaelstromRequest<Value>, _| Ok(()),
+  );
+  router.merge(other);
+  assert_eq!(router.types().count(), 5);
+}
+
+#[test]
+#[should_panic(expected = "Messages of type add already have a handler")]
+fn a_type_can_only_have_one_handler() {
+  let mut router = router();
+  router.mount(|node: &mut MixedNode| &mut node.counter);
+}
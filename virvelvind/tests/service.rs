@@ -0,0 +1,236 @@
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use virvelvind::{
+  req::Incoming, service, sim::Simulation, Context, ErrorCode, Event, Node, NodeError,
+};
+
+#[service]
+#[derive(Debug, PartialEq)]
+pub enum Kv {
+  Read {
+    key: u64,
+  },
+  ReadOk {
+    value: u64,
+  },
+  #[serde(rename = "cas")]
+  CompareAndSet {
+    key: u64,
+    from: u64,
+    to: u64,
+  },
+  #[serde(rename = "cas_ok")]
+  CompareAndSetOk,
+  #[service(reply = Acked)]
+  Write {
+    key: u64,
+    value: u64,
+  },
+  Acked,
+  #[serde(alias = "nudge")]
+  Poke,
+}
+
+#[test]
+fn type_names_are_snake_case_unless_renamed() {
+  assert_eq!(Kv::Read { key: 1 }.type_name(), "read");
+  assert_eq!(Kv::ReadOk { value: 1 }.type_name(), "read_ok");
+  assert_eq!(
+    Kv::CompareAndSet {
+      key: 1,
+      from: 2,
+      to: 3
+    }
+    .type_name(),
+    "cas"
+  );
+  assert_eq!(Kv::CompareAndSetOk.type_name(), "cas_ok");
+  assert_eq!(Kv::Poke.type_name(), "poke");
+  assert_eq!(
+    Kv::TYPE_NAMES,
+    ["read", "read_ok", "cas", "cas_ok", "write", "acked", "poke"]
+  );
+}
+
+#[test]
+fn replies_are_paired_by_name_or_by_attribute() {
+  assert_eq!(Kv::Read { key: 1 }.reply_type(), Some("read_ok"));
+  assert_eq!(
+    Kv::CompareAndSet {
+      key: 1,
+      from: 2,
+      to: 3
+    }
+    .reply_type(),
+    Some("cas_ok")
+  );
+  assert_eq!(Kv::Write { key: 1, value: 2 }.reply_type(), Some("acked"));
+  assert_eq!(Kv::ReadOk { value: 1 }.reply_type(), None);
+  assert_eq!(Kv::Acked.reply_type(), None);
+  assert_eq!(Kv::Poke.reply_type(), None);
+}
+
+#[test]
+fn messages_are_tagged_by_type() {
+  let cas = Kv::CompareAndSet {
+    key: 1,
+    from: 2,
+    to: 3,
+  };
+  assert_eq!(
+    serde_json::to_value(&cas).unwrap(),
+    json!({ "type": "cas", "key": 1, "from": 2, "to": 3 })
+  );
+  let write: Kv = serde_json::from_value(json!({ "type": "write", "key": 1, "value": 2 })).unwrap();
+  assert_eq!(write, Kv::Write { key: 1, value: 2 });
+  // other serde attributes are left to serde
+  let poke: Kv = serde_json::from_value(json!({ "type": "nudge" })).unwrap();
+  assert_eq!(poke, Kv::Poke);
+}
+
+#[derive(Default)]
+struct KvNode {
+  poked: usize,
+}
+
+impl KvHandler for KvNode {
+  fn on_read(&mut self, _: &Incoming, key: u64, _: &mut Context<Self>) -> Result<Kv, NodeError> {
+    Ok(Kv::ReadOk { value: key * 10 })
+  }
+
+  // answers with the wrong message
+  fn on_write(
+    &mut self,
+    _: &Incoming,
+    _: u64,
+    value: u64,
+    _: &mut Context<Self>,
+  ) -> Result<Kv, NodeError> {
+    Ok(Kv::ReadOk { value })
+  }
+
+  fn on_poke(&mut self, _: &Incoming, _: &mut Context<Self>) -> Result<(), NodeError> {
+    self.poked += 1;
+    Ok(())
+  }
+}
+
+impl Node<Kv> for KvNode {
+  fn handle(&mut self, evt: Event<Kv>, ctx: &mut Context<Self>) -> Result<(), NodeError> {
+    match evt {
+      Event::IOEvent(msg) => self.dispatch(msg, ctx),
+      Event::Timer(_) => Ok(()),
+    }
+  }
+}
+
+fn reply(sim: &Simulation<KvNode, Kv>, msg_id: usize) -> Value {
+  let reply = sim.reply_to("c1", msg_id).expect("a reply");
+  serde_json::from_str::<Value>(&reply.line).unwrap()["body"].clone()
+}
+
+#[test]
+fn dispatch_calls_the_handler_and_sends_its_reply() {
+  let mut sim = Simulation::new(1, 1, |_| KvNode::default());
+  let read = sim.client_request("c1", "n0", json!({ "type": "read", "key": 4 }));
+  let cas = sim.client_request(
+    "c1",
+    "n0",
+    json!({ "type": "cas", "key": 1, "from": 1, "to": 2 }),
+  );
+  let write = sim.client_request("c1", "n0", json!({ "type": "write", "key": 1, "value": 2 }));
+  sim.client_request("c1", "n0", json!({ "type": "poke" }));
+  sim.run_for(Duration::from_millis(10));
+
+  let read = reply(&sim, read);
+  assert_eq!(read["type"], "read_ok");
+  assert_eq!(read["value"], 40);
+  // handlers that aren't implemented answer not-supported
+  let cas = reply(&sim, cas);
+  assert_eq!(cas["type"], "error");
+  assert_eq!(cas["code"], ErrorCode::NotSupported.code());
+  // a reply of the wrong type isn't sent
+  let write = reply(&sim, write);
+  assert_eq!(write["type"], "error");
+  assert_eq!(write["code"], ErrorCode::Crash.code());
+  assert_eq!(sim.node("n0").unwrap().poked, 1);
+}
+
+#[test]
+fn messages_the_service_does_not_know_are_rejected() {
+  let mut sim = Simulation::new(1, 1, |_| KvNode::default());
+  let unknown = sim.client_request("c1", "n0", json!({ "type": "delete", "key": 4 }));
+  let malformed = sim.client_request("c1", "n0", json!({ "type": "read", "key": "four" }));
+  sim.run_for(Duration::from_millis(10));
+
+  let unknown = reply(&sim, unknown);
+  assert_eq!(unknown["code"], ErrorCode::NotSupported.code());
+  let malformed = reply(&sim, malformed);
+  assert_eq!(malformed["code"], ErrorCode::MalformedRequest.code());
+}
+
+/// Maelstrom kafka's `send`, whose fields are named like the handler's own arguments
+#[service]
+pub enum Log {
+  Send { key: String, msg: u64 },
+  SendOk { offset: u64 },
+  Tag { ctx: String },
+}
+
+#[derive(Default)]
+struct LogNode {
+  sent: Vec<(String, u64)>,
+  tags: Vec<String>,
+}
+
+impl LogHandler for LogNode {
+  fn on_send(
+    &mut self,
+    _: &Incoming,
+    key: String,
+    msg: u64,
+    _: &mut Context<Self>,
+  ) -> Result<Log, NodeError> {
+    self.sent.push((key, msg));
+    Ok(Log::SendOk {
+      offset: self.sent.len() as u64 - 1,
+    })
+  }
+
+  fn on_tag(&mut self, _: &Incoming, ctx: String, _: &mut Context<Self>) -> Result<(), NodeError> {
+    self.tags.push(ctx);
+    Ok(())
+  }
+}
+
+impl Node<Log> for LogNode {
+  fn handle(&mut self, evt: Event<Log>, ctx: &mut Context<Self>) -> Result<(), NodeError> {
+    match evt {
+      Event::IOEvent(msg) => self.dispatch(msg, ctx),
+      Event::Timer(_) => Ok(()),
+    }
+  }
+}
+
+#[test]
+fn fields_may_be_named_msg_or_ctx() {
+  let mut sim = Simulation::new(1, 1, |_| LogNode::default());
+  let send = sim.client_request("c1", "n0", json!({ "type": "send", "key": "k1", "msg": 7 }));
+  sim.client_request("c1", "n0", json!({ "type": "tag", "ctx": "t" }));
+  sim.run_for(Duration::from_millis(10));
+
+  let reply = sim.reply_to("c1", send).expect("a reply");
+  let body = &serde_json::from_str::<Value>(&reply.line).unwrap()["body"];
+  assert_eq!(body["type"], "send_ok");
+  assert_eq!(body["offset"], 0);
+  let node = sim.node("n0").unwrap();
+  assert_eq!(node.sent, [("k1".to_string(), 7)]);
+  assert_eq!(node.tags, ["t"]);
+}
+
+#[test]
+fn misused_attributes_fail_to_compile() {
+  let t = trybuild::TestCases::new();
+  t.compile_fail("tests/ui/*.rs");
+}
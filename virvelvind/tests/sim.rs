@@ -0,0 +1,178 @@
+use std::{
+  collections::{BTreeSet, HashMap},
+  time::Duration,
+};
+
+use virvelvind::{
+  req::Incoming,
+  service,
+  sim::{Fault, Latency, Nemesis, NetworkStats, Partition, Simulation},
+  workload::{self, broadcast::Topology, Broadcast, Options, Report},
+  Context, Event, NetworkEntityId, Node, NodeError, Timers,
+};
+
+#[service]
+#[derive(Debug)]
+pub enum Gossip {
+  Topology {
+    topology: HashMap<NetworkEntityId, Vec<NetworkEntityId>>,
+  },
+  TopologyOk,
+  Broadcast {
+    message: u64,
+  },
+  BroadcastOk,
+  Read,
+  ReadOk {
+    messages: BTreeSet<u64>,
+  },
+  /// Everything the sender has seen, sent to its neighbours every so often
+  Sync {
+    messages: BTreeSet<u64>,
+  },
+}
+
+/// A broadcast node that keeps sending its neighbours everything it has, so it gets every message
+/// across eventually, partitions or not.
+#[derive(Default)]
+struct GossipNode {
+  neighbours: Vec<NetworkEntityId>,
+  messages: BTreeSet<u64>,
+  /// Who sent the node what, in the order it was delivered
+  received: Vec<(NetworkEntityId, &'static str)>,
+}
+
+impl GossipHandler for GossipNode {
+  fn on_topology(
+    &mut self,
+    _: &Incoming,
+    mut topology: HashMap<NetworkEntityId, Vec<NetworkEntityId>>,
+    ctx: &mut Context<Self>,
+  ) -> Result<Gossip, NodeError> {
+    self.neighbours = topology.remove(ctx.node_id()).unwrap_or_default();
+    Ok(Gossip::TopologyOk)
+  }
+
+  fn on_broadcast(
+    &mut self,
+    _: &Incoming,
+    message: u64,
+    _: &mut Context<Self>,
+  ) -> Result<Gossip, NodeError> {
+    self.messages.insert(message);
+    Ok(Gossip::BroadcastOk)
+  }
+
+  fn on_read(&mut self, _: &Incoming, _: &mut Context<Self>) -> Result<Gossip, NodeError> {
+    Ok(Gossip::ReadOk {
+      messages: self.messages.clone(),
+    })
+  }
+
+  fn on_sync(
+    &mut self,
+    _: &Incoming,
+    messages: BTreeSet<u64>,
+    _: &mut Context<Self>,
+  ) -> Result<(), NodeError> {
+    self.messages.extend(messages);
+    Ok(())
+  }
+}
+
+impl Node<Gossip> for GossipNode {
+  fn setup_timers(&mut self, timers: &mut Timers) {
+    timers.every("sync", Duration::from_millis(100));
+  }
+
+  fn handle(&mut self, evt: Event<Gossip>, ctx: &mut Context<Self>) -> Result<(), NodeError> {
+    match evt {
+      Event::IOEvent(msg) => {
+        let kind = msg.body.data.type_name();
+        self.received.push((msg.src.clone(), kind));
+        self.dispatch(msg, ctx)
+      }
+      Event::Timer(_) => {
+        for neighbour in &self.neighbours {
+          let sync = Gossip::Sync {
+            messages: self.messages.clone(),
+          };
+          ctx.send(neighbour, sync)?;
+        }
+        Ok(())
+      }
+    }
+  }
+}
+
+type BroadcastReport =
+  Report<workload::broadcast::BroadcastRequest, workload::broadcast::BroadcastResponse>;
+
+struct Run {
+  report: BroadcastReport,
+  received: Vec<Vec<(NetworkEntityId, &'static str)>>,
+  stats: NetworkStats,
+}
+
+fn run(seed: u64, nemesis: Nemesis) -> Run {
+  let mut sim = Simulation::new(seed, 5, |_| GossipNode::default())
+    .latency(Latency::Exponential {
+      mean: Duration::from_millis(20),
+      max: Duration::from_millis(200),
+    })
+    .nemesis(nemesis);
+  let options = Options {
+    rate: 20.0,
+    time_limit: Duration::from_secs(5),
+    timeout: Duration::from_secs(1),
+    recovery: Duration::from_secs(2),
+    ..Options::default()
+  };
+  let report = workload::run(&mut sim, &mut Broadcast::new(Topology::Grid), &options);
+  Run {
+    report,
+    received: sim.nodes().map(|(_, node)| node.received.clone()).collect(),
+    stats: sim.network_stats(),
+  }
+}
+
+#[test]
+fn the_same_seed_replays_the_same_run() {
+  let first = run(7, Nemesis::new());
+  let again = run(7, Nemesis::new());
+  assert!(first.report.is_valid(), "{:?}", first.report.valid);
+  assert!(first.report.ok > 50);
+  assert_eq!(first.received, again.received);
+  assert_eq!(first.stats, again.stats);
+  assert_eq!(first.report.history, again.report.history);
+  assert_eq!(first.report.times, again.report.times);
+  assert_eq!(
+    (first.report.ok, first.report.fail, first.report.info),
+    (again.report.ok, again.report.fail, again.report.info)
+  );
+
+  let other = run(8, Nemesis::new());
+  assert_ne!(first.report.times, other.report.times);
+}
+
+#[test]
+fn broadcasts_reach_every_node_once_partitions_heal() {
+  let nemesis = Nemesis::partitions(Duration::from_millis(500), Duration::from_secs(5));
+  let healed = run(3, nemesis);
+  assert!(healed.stats.partitioned > 0, "{:?}", healed.stats);
+  assert!(healed.report.is_valid(), "{:?}", healed.report.valid);
+  assert_eq!(healed.report.fail + healed.report.info, 0);
+
+  // a node that stays cut off misses what was broadcast through the others
+  let rest = ["n1", "n2", "n3", "n4"].map(String::from).to_vec();
+  let cut_off = Partition::Groups(vec![vec!["n0".to_string()], rest]);
+  let never_healed = run(
+    3,
+    Nemesis::new().at(Duration::from_secs(1), Fault::Partition(cut_off)),
+  );
+  let error = never_healed.report.valid.unwrap_err();
+  assert!(
+    error.contains("is missing acknowledged messages"),
+    "{error}"
+  );
+}
@@ -0,0 +1,149 @@
+#![cfg(feature = "async")]
+
+use std::{cell::Cell, rc::Rc, time::Duration};
+
+use serde_json::{json, Value};
+use virvelvind::{
+  req::MaelstromRequest,
+  sim::Simulation,
+  task::{Async, AsyncNode, Handle},
+  CallOptions, ErrorCode, NodeError,
+};
+
+/// Looks keys up on `n1`, which knows every key except `lost`, and never answers for that one.
+#[derive(Default)]
+struct Proxy {
+  /// Number of requests handled to the end
+  finished: Cell<usize>,
+}
+
+impl AsyncNode<Value> for Proxy {
+  async fn handle(
+    self: Rc<Self>,
+    msg: MaelstromRequest<Value>,
+    handle: Handle,
+  ) -> Result<(), NodeError> {
+    let body = &msg.body.data;
+    let key = body["key"].as_str().unwrap_or_default();
+    let lookup = json!({ "type": "lookup", "key": key });
+    let reply = match body["type"].as_str().unwrap_or_default() {
+      "get" => {
+        let options = CallOptions::timeout(Duration::from_millis(100));
+        let found: MaelstromRequest<Value> = handle.call_with("n1", lookup, options).await?;
+        json!({ "type": "get_ok", "value": found.body.data["value"] })
+      }
+      "get_forever" => {
+        let found: MaelstromRequest<Value> = handle.call("n1", lookup).await?;
+        json!({ "type": "get_ok", "value": found.body.data["value"] })
+      }
+      "lookup" if key == "lost" => return Ok(()),
+      "lookup" => json!({ "type": "lookup_ok", "value": key.to_uppercase() }),
+      "wait" => {
+        let ms = body["ms"].as_u64().unwrap_or_default();
+        handle.sleep(Duration::from_millis(ms)).await;
+        json!({ "type": "wait_ok", "ms": ms })
+      }
+      "forget" => {
+        // neither is awaited; their results come in after the task is gone
+        drop(handle.call::<_, Value>("n1", lookup));
+        drop(handle.sleep(Duration::from_millis(10)));
+        json!({ "type": "forget_ok" })
+      }
+      kind => return Err(NodeError::not_supported(format!("{kind} is not supported"))),
+    };
+    handle.reply(&msg.src, msg.body.msg_id, reply)?;
+    self.finished.set(self.finished.get() + 1);
+    Ok(())
+  }
+}
+
+type Sim = Simulation<Async<Proxy>, Value>;
+
+fn simulation() -> Sim {
+  Simulation::new(1, 2, |_| Async::new(Proxy::default()))
+}
+
+fn reply(sim: &Sim, msg_id: usize) -> Option<(Duration, Value)> {
+  let reply = sim.reply_to("c1", msg_id)?;
+  let body = serde_json::from_str::<Value>(&reply.line).unwrap()["body"].clone();
+  Some((reply.at, body))
+}
+
+fn finished(sim: &Sim, node: &str) -> usize {
+  sim.node(node).unwrap().node().finished.get()
+}
+
+#[test]
+fn a_task_finishes_once_the_reply_it_waits_for_comes_in() {
+  let mut sim = simulation();
+  let get = sim.client_request("c1", "n0", json!({ "type": "get", "key": "a" }));
+  sim.run_for(Duration::from_millis(10));
+
+  let (_, body) = reply(&sim, get).expect("a reply");
+  assert_eq!(body["type"], "get_ok");
+  assert_eq!(body["value"], "A");
+  assert_eq!(finished(&sim, "n0"), 1);
+  assert_eq!(finished(&sim, "n1"), 1);
+}
+
+#[test]
+fn a_task_finishes_when_its_request_times_out() {
+  let mut sim = simulation();
+  let get = sim.client_request("c1", "n0", json!({ "type": "get", "key": "lost" }));
+  sim.run_for(Duration::from_millis(90));
+  assert!(reply(&sim, get).is_none());
+  sim.run_for(Duration::from_millis(20));
+
+  // the error the task returned is sent back, as for a node that isn't async
+  let (at, body) = reply(&sim, get).expect("a reply");
+  assert_eq!(at, Duration::from_millis(100));
+  assert_eq!(body["type"], "error");
+  assert_eq!(body["code"], ErrorCode::Timeout.code());
+  assert_eq!(finished(&sim, "n0"), 0);
+}
+
+#[test]
+fn each_task_is_woken_by_what_it_waits_for() {
+  let mut sim = simulation();
+  let slow = sim.client_request("c1", "n0", json!({ "type": "wait", "ms": 50 }));
+  let fast = sim.client_request("c1", "n0", json!({ "type": "wait", "ms": 20 }));
+  let get = sim.client_request("c1", "n0", json!({ "type": "get", "key": "b" }));
+  sim.run_for(Duration::from_millis(100));
+
+  let at = |msg_id| reply(&sim, msg_id).expect("a reply").0;
+  assert_eq!(at(get), Duration::ZERO);
+  assert_eq!(at(fast), Duration::from_millis(20));
+  assert_eq!(at(slow), Duration::from_millis(50));
+  assert_eq!(finished(&sim, "n0"), 3);
+}
+
+#[test]
+fn a_task_that_is_never_woken_holds_up_nothing_else() {
+  let mut sim = simulation();
+  let stuck = sim.client_request("c1", "n0", json!({ "type": "get_forever", "key": "lost" }));
+  let get = sim.client_request("c1", "n0", json!({ "type": "get", "key": "c" }));
+  sim.run_for(Duration::from_secs(10));
+
+  assert!(reply(&sim, stuck).is_none());
+  assert_eq!(reply(&sim, get).expect("a reply").1["value"], "C");
+  assert_eq!(finished(&sim, "n0"), 1);
+}
+
+#[test]
+fn results_for_a_task_that_is_gone_are_dropped() {
+  let mut sim = simulation();
+  let forget = sim.client_request("c1", "n0", json!({ "type": "forget", "key": "d" }));
+  sim.run_for(Duration::from_millis(50));
+  assert_eq!(reply(&sim, forget).expect("a reply").1["type"], "forget_ok");
+  // the lookup was answered, and the sleep's timer fired, with nobody waiting for either
+  assert_eq!(finished(&sim, "n1"), 1);
+
+  // and the tasks after it run as usual
+  let wait = sim.client_request("c1", "n0", json!({ "type": "wait", "ms": 10 }));
+  sim.run_for(Duration::from_millis(50));
+  assert_eq!(
+    reply(&sim, wait).expect("a reply").0,
+    Duration::from_millis(60)
+  );
+  assert_eq!(finished(&sim, "n0"), 2);
+}
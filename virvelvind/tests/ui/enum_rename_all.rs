@@ -0,0 +1,10 @@
+use virvelvind::service;
+
+#[service]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub enum Echo {
+  Echo { echo: String },
+  EchoOk { echo: String },
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use virvelvind::service;
+
+#[service]
+#[serde(tag = "kind")]
+pub enum Echo {
+  Echo { echo: String },
+  EchoOk { echo: String },
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use virvelvind::service;
+
+#[service]
+pub struct Echo {
+  echo: String,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use virvelvind::service;
+
+#[service(tag = "kind")]
+pub enum Echo {
+  Echo { echo: String },
+  EchoOk { echo: String },
+}
+
+fn main() {}
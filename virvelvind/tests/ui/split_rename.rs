@@ -0,0 +1,10 @@
+use virvelvind::service;
+
+#[service]
+pub enum Echo {
+  #[serde(rename(deserialize = "say"))]
+  Echo { echo: String },
+  EchoOk { echo: String },
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use virvelvind::service;
+
+#[service]
+pub enum Echo {
+  #[service(reply = EchoOkay)]
+  Echo { echo: String },
+  EchoOk { echo: String },
+}
+
+fn main() {}